@@ -0,0 +1,68 @@
+use clap::{Parser, ValueEnum};
+use popl::asplanted::{passes, write_geojson, write_kml, write_shapefile, Shape};
+use popl::geo::M_PER_FT;
+use popl::log::read_session;
+use popl::monitor::ROWS;
+use std::error::Error;
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::PathBuf;
+
+#[derive(Clone, Copy, ValueEnum)]
+enum Format {
+    Geojson,
+    Kml,
+    Shp,
+}
+
+/// Export as-planted coverage from a session log
+#[derive(Parser)]
+struct Opts {
+    /// session file, see ~/.local/share/popl/sessions
+    session: PathBuf,
+
+    #[clap(long, value_enum, default_value = "geojson")]
+    format: Format,
+
+    /// row width in inches, the swath is all rows wide
+    #[clap(long, default_value_t = 34.0)]
+    row_width: f64,
+
+    /// planter path instead of swath polygons
+    #[clap(long)]
+    lines: bool,
+
+    /// output file, for shapefiles the base name of the .shp/.shx/.dbf/.prj set.
+    /// defaults to stdout
+    #[clap(short, long)]
+    out: Option<PathBuf>,
+}
+
+fn main() -> Result<(), Box<dyn Error>> {
+    let opts: Opts = Opts::parse();
+
+    let passes = passes(&read_session(&opts.session)?);
+    let shape = if opts.lines {
+        Shape::Line
+    } else {
+        Shape::Swath(ROWS as f64 * opts.row_width / 12.0 * M_PER_FT)
+    };
+
+    if let Format::Shp = opts.format {
+        let base = opts.out.ok_or("shapefile export needs --out")?;
+        write_shapefile(&passes, shape, &base)?;
+        return Ok(());
+    }
+
+    let mut out: Box<dyn Write> = match &opts.out {
+        Some(path) => Box::new(BufWriter::new(File::create(path)?)),
+        None => Box::new(io::stdout().lock()),
+    };
+    match opts.format {
+        Format::Geojson => write_geojson(&passes, shape, &mut out)?,
+        Format::Kml => write_kml(&passes, shape, &mut out)?,
+        Format::Shp => unreachable!(),
+    }
+    out.flush()?;
+    Ok(())
+}
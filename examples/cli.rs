@@ -11,8 +11,10 @@ use build_time::build_time_local;
 use clap::Parser;
 use crossbeam_channel::tick;
 use popl::gps;
+use popl::io::{IoCfg, Relay};
 use rppal::gpio::{Gpio, Level, Trigger};
 use rppal::pwm::Pwm;
+use tokio::signal::unix::{signal, SignalKind};
 use tokio::sync::mpsc;
 use tokio::sync::mpsc::Sender;
 use tokio::time::interval;
@@ -58,6 +60,7 @@ struct EncoderTickRate(f32);
 enum Rate {
     Up(usize),
     Down(usize),
+    Stop,
 }
 
 enum HopperState {
@@ -152,10 +155,11 @@ async fn main() -> Result<(), Box<dyn Error>> {
         }
     });
 
-    // hopper feed belts relay control pins
+    // hopper feed belts relay control pins, with the same polarity as the gui app
+    let active_low = IoCfg::default().relays_active_low;
     let mut hopper_relay_pins = [
-        Gpio::new()?.get(HOPPER_RELAY_0)?.into_output(),
-        Gpio::new()?.get(HOPPER_RELAY_1)?.into_output(),
+        Relay::new(HOPPER_RELAY_0, active_low)?,
+        Relay::new(HOPPER_RELAY_1, active_low)?,
     ];
 
     // planter channel reports planter lift state to main message channel
@@ -243,14 +247,14 @@ async fn main() -> Result<(), Box<dyn Error>> {
     let (speed_tx, mut speed_rx) = mpsc::channel(1);
     // todo;; mutex instead?
     let changing_speed = Arc::new(AtomicBool::default());
-    if !opts.disable_speed {
+    let flow_task = if !opts.disable_speed {
         dc_motor
             .set_throttle(&mut pwm, -1.0)
             .expect("init throttle -");
         println!("== init flow to zero ==");
         thread::sleep(Duration::from_secs(2));
 
-        tokio::task::spawn({
+        Some(tokio::task::spawn({
             let changing_speed = changing_speed.clone();
             async move {
                 use std::io::{self, Write};
@@ -282,12 +286,18 @@ async fn main() -> Result<(), Box<dyn Error>> {
                                 .set_throttle(&mut pwm, 0.0)
                                 .expect("throttle - 0.0");
                         }
+                        Rate::Stop => {
+                            dc_motor.stop(&mut pwm).expect("flow stop");
+                            break;
+                        }
                     }
                     changing_speed.store(false, Ordering::Relaxed);
                 }
             }
-        });
-    }
+        }))
+    } else {
+        None
+    };
 
     use popl::util::*;
     use Message::*;
@@ -299,6 +309,7 @@ async fn main() -> Result<(), Box<dyn Error>> {
         if planter_lowered { "lowered" } else { "raised" }
     );
     let mut prev_mph = 0.0;
+    let mut sigterm = signal(SignalKind::terminate())?;
 
     // the main event loop
     loop {
@@ -307,6 +318,8 @@ async fn main() -> Result<(), Box<dyn Error>> {
         let target_tps = sps_to_tickrate(target_sps);
 
         select! {
+            _ = tokio::signal::ctrl_c() => break,
+            _ = sigterm.recv() => break,
             Some(msg) = msg_rx.recv() => {
                 match msg {
                     GroundSpeed(speed) => {
@@ -315,11 +328,11 @@ async fn main() -> Result<(), Box<dyn Error>> {
                     },
                     HopperFull(i) => {
                         //println!("hopper {i} full");
-                        hopper_relay_pins[i].write(Level::High)
+                        hopper_relay_pins[i].set(false)
                     },
                     HopperEmpty(i) => {
                         //println!("hopper {i} empty");
-                        hopper_relay_pins[i].write(Level::Low)
+                        hopper_relay_pins[i].set(true)
                     },
                     PlanterRaised => {
                         println!("planter raised - dol[{}]", opts.disable_on_lift);
@@ -357,6 +370,17 @@ async fn main() -> Result<(), Box<dyn Error>> {
             }
         }
     }
+
+    println!("== shutting down ==");
+    if let Some(flow_task) = flow_task {
+        speed_tx.send(Rate::Stop).await;
+        flow_task.await?;
+    }
+    for relay in hopper_relay_pins.iter_mut() {
+        relay.set(false);
+    }
+
+    Ok(())
 }
 
 fn read_encoder(mut tx: Sender<EncoderTick>) -> Result<(), Box<dyn Error>> {
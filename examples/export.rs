@@ -0,0 +1,39 @@
+use clap::Parser;
+use popl::log::{export_csv, export_jsonl, read_session};
+use std::error::Error;
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::PathBuf;
+
+/// Export a planting session log
+#[derive(Parser)]
+struct Opts {
+    /// session file, see ~/.local/share/popl/sessions
+    session: PathBuf,
+
+    /// write csv instead of json lines
+    #[clap(long)]
+    csv: bool,
+
+    /// output file, defaults to stdout
+    #[clap(short, long)]
+    out: Option<PathBuf>,
+}
+
+fn main() -> Result<(), Box<dyn Error>> {
+    let opts: Opts = Opts::parse();
+
+    let entries = read_session(&opts.session)?;
+    let mut out: Box<dyn Write> = match &opts.out {
+        Some(path) => Box::new(BufWriter::new(File::create(path)?)),
+        None => Box::new(io::stdout().lock()),
+    };
+
+    if opts.csv {
+        export_csv(&entries, &mut out)?;
+    } else {
+        export_jsonl(&entries, &mut out)?;
+    }
+    out.flush()?;
+    Ok(())
+}
@@ -0,0 +1,45 @@
+use clap::Parser;
+use popl::db::{self, Db};
+use popl::isoxml::{export_sessions, usb_mounts, DeviceCfg, SessionCache};
+use popl::log;
+use std::error::Error;
+use std::path::PathBuf;
+
+/// Export session logs as ISOXML TASKDATA
+#[derive(Parser)]
+struct Opts {
+    /// directory of session logs
+    #[clap(long)]
+    sessions: Option<PathBuf>,
+
+    /// where TASKDATA is written, defaults to the first USB stick
+    #[clap(short, long)]
+    out: Option<PathBuf>,
+
+    /// row width in inches
+    #[clap(long, default_value_t = 34.0)]
+    row_width: f32,
+}
+
+fn main() -> Result<(), Box<dyn Error>> {
+    let opts: Opts = Opts::parse();
+
+    let out = match opts.out {
+        Some(out) => out,
+        None => usb_mounts()
+            .into_iter()
+            .next()
+            .ok_or("no USB stick found, use --out")?,
+    };
+    let fields = Db::open(&db::default_path())?.fields()?;
+    let dir = export_sessions(
+        &mut SessionCache::default(),
+        &opts.sessions.unwrap_or_else(log::default_dir),
+        &out,
+        opts.row_width,
+        &fields,
+        &DeviceCfg::local(),
+    )?;
+    println!("wrote {}", dir.display());
+    Ok(())
+}
@@ -0,0 +1,170 @@
+use clap::{Parser, Subcommand};
+use popl::boundary::read_boundary;
+use popl::db::{self, Db};
+use popl::prescription::read_prescription;
+use std::error::Error;
+use std::path::PathBuf;
+
+/// Manage the farm and field database used by the job picker
+#[derive(Parser)]
+struct Opts {
+    /// database file, defaults to ~/.local/share/popl/popl.db
+    #[clap(long)]
+    db: Option<PathBuf>,
+
+    #[clap(subcommand)]
+    cmd: Command,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// add a field, the farm is created if needed
+    AddField {
+        farm: String,
+        field: String,
+        #[clap(long)]
+        acres: Option<f32>,
+    },
+    /// list fields
+    Fields,
+    /// set a field's boundary from a GeoJSON, KML or Shapefile (.shp)
+    ImportBoundary {
+        /// field id, from the fields listing
+        field: i64,
+        file: PathBuf,
+    },
+    /// set a field's prescription from zones in GeoJSON or a Shapefile with a spacing
+    /// or population attribute, or an ESRI ASCII grid (.asc)
+    ImportPrescription {
+        /// field id, from the fields listing
+        field: i64,
+        file: PathBuf,
+    },
+    /// add a variety with its spacing preset
+    AddVariety {
+        name: String,
+        /// in-row spacing, inches
+        spacing: f32,
+        /// average seed piece weight, ounces
+        #[clap(long)]
+        piece_weight: Option<f32>,
+        #[clap(long)]
+        notes: Option<String>,
+    },
+    /// add a seed lot to a variety
+    AddLot {
+        variety: String,
+        lot: String,
+        #[clap(long)]
+        notes: Option<String>,
+    },
+    /// list varieties and their seed lots
+    Varieties,
+    /// list jobs and their passes
+    Jobs,
+}
+
+fn main() -> Result<(), Box<dyn Error>> {
+    let opts: Opts = Opts::parse();
+    let db = Db::open(&opts.db.unwrap_or_else(db::default_path))?;
+
+    match opts.cmd {
+        Command::AddField { farm, field, acres } => {
+            let id = db.add_field(&farm, &field, acres)?;
+            println!("added field {id}");
+        }
+        Command::Fields => {
+            for f in db.fields()? {
+                let acres = f.acres.map(|a| format!("{a:.1}")).unwrap_or_default();
+                println!("{:>4} {:<20} {:<20} {acres}", f.id, f.farm, f.name);
+            }
+        }
+        Command::ImportBoundary { field, file } => {
+            if !db.fields()?.iter().any(|f| f.id == field) {
+                return Err(format!("no field {field}").into());
+            }
+            let boundary = read_boundary(&file)?;
+            db.set_boundary(field, &boundary)?;
+            println!(
+                "field {field}: {} polygons, {:.1} ac",
+                boundary.polygons.len(),
+                boundary.area_m2() / 4046.856
+            );
+        }
+        Command::ImportPrescription { field, file } => {
+            if !db.fields()?.iter().any(|f| f.id == field) {
+                return Err(format!("no field {field}").into());
+            }
+            let rx = read_prescription(&file)?;
+            db.set_prescription(field, &rx)?;
+            for z in &rx.zones {
+                println!("{:<20} {:?}", z.name, z.rate);
+            }
+            if rx.grid.is_some() {
+                println!("grid");
+            }
+        }
+        Command::AddVariety {
+            name,
+            spacing,
+            piece_weight,
+            notes,
+        } => {
+            let id = db.add_variety(&name, spacing, piece_weight, notes.as_deref())?;
+            println!("added variety {id}");
+        }
+        Command::AddLot {
+            variety,
+            lot,
+            notes,
+        } => {
+            let Some(v) = db.varieties()?.into_iter().find(|v| v.name == variety) else {
+                return Err(format!("no variety named {variety}").into());
+            };
+            let id = db.add_seed_lot(v.id, &lot, notes.as_deref())?;
+            println!("added lot {id}");
+        }
+        Command::Varieties => {
+            let lots = db.seed_lots()?;
+            for v in db.varieties()? {
+                let weight = v
+                    .piece_weight_oz
+                    .map(|w| format!("{w:.1} oz"))
+                    .unwrap_or_default();
+                println!(
+                    "{:>4} {:<20} {:>5.1}\" {weight:>7} {}",
+                    v.id,
+                    v.name,
+                    v.spacing_in,
+                    v.notes.as_deref().unwrap_or("")
+                );
+                for l in lots.iter().filter(|l| l.variety_id == v.id) {
+                    println!("       lot {} {}", l.lot, l.notes.as_deref().unwrap_or(""));
+                }
+            }
+        }
+        Command::Jobs => {
+            for j in db.jobs()? {
+                println!(
+                    "{:>4} {:<20} {:<12} {:<10} {:>5.1}\" {:>7.2} ac {:>7.1} cwt  {} - {}",
+                    j.id,
+                    j.field,
+                    j.variety.as_deref().unwrap_or("-"),
+                    j.seed_lot.as_deref().unwrap_or("-"),
+                    j.spacing_in,
+                    j.acres(),
+                    j.seed_cwt,
+                    j.started_at.as_deref().unwrap_or(""),
+                    j.ended_at.as_deref().unwrap_or(""),
+                );
+                for p in db.passes(j.id)? {
+                    println!(
+                        "       {} - {} {:>8.0} ft {:>4.1} mph {:>6.1} cwt",
+                        p.started_at, p.ended_at, p.row_feet, p.avg_speed_mph, p.seed_cwt
+                    );
+                }
+            }
+        }
+    }
+    Ok(())
+}
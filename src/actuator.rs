@@ -0,0 +1,556 @@
+use adafruit_motorkit::dc::DcMotor;
+use adafruit_motorkit::stepper::{StepDirection, StepStyle, StepperMotor};
+use adafruit_motorkit::Motor;
+use crossbeam_channel::{Receiver, RecvTimeoutError};
+use linux_embedded_hal::I2cdev;
+use pwm_pca9685::{Pca9685, SlaveAddr};
+use rppal::pwm::{Channel, Polarity, Pwm};
+use std::error::Error;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::{Arc, Mutex, PoisonError};
+use std::time::Duration;
+
+type Hat = Arc<Mutex<Pca9685<I2cdev>>>;
+
+// sleeps through part of a move, false once the move has been cut short
+pub type Wait<'a> = &'a mut dyn FnMut(Duration) -> bool;
+
+/// Drives the flow valve, throttle from -1.0 closing to 1.0 opening
+pub trait Actuator: Send {
+    // held until the next command
+    fn set_throttle(&mut self, rate: f32, wait: Wait) -> Result<(), Box<dyn Error>>;
+
+    // throttle applied for a duration then released
+    fn pulse(&mut self, rate: f32, duration: Duration, wait: Wait) -> Result<(), Box<dyn Error>> {
+        self.set_throttle(rate, wait)?;
+        wait(duration);
+        self.set_throttle(0.0, wait)
+    }
+
+    // stops moving; a driven valve stays where it is, a set one is de-energised
+    // until the next move
+    fn stop(&mut self) -> Result<(), Box<dyn Error>>;
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Move {
+    Throttle(f32),
+    Pulse(f32, Duration),
+    Hold,
+}
+
+/// Runs one actuator on its own thread, so a long move holds up no other output,
+/// and the next command cuts it short. Stops the actuator once the channel closes.
+pub fn run(mut actuator: Box<dyn Actuator>, rx: Receiver<Move>, report: impl Fn(Box<dyn Error>)) {
+    let r = panic::catch_unwind(AssertUnwindSafe(|| follow(&mut *actuator, &rx, &report)));
+    if r.is_err() {
+        report("actuator panicked".into());
+    }
+    // stopped on the way out however the commands ended
+    match panic::catch_unwind(AssertUnwindSafe(|| actuator.stop())) {
+        Ok(Ok(())) => {}
+        Ok(Err(e)) => report(e),
+        Err(_) => report("actuator panicked stopping".into()),
+    }
+}
+
+fn follow(actuator: &mut dyn Actuator, rx: &Receiver<Move>, report: &impl Fn(Box<dyn Error>)) {
+    let mut next = None;
+    loop {
+        let Some(m) = next.take().or_else(|| rx.recv().ok()) else {
+            break;
+        };
+        let mut wait = |d: Duration| {
+            if next.is_some() {
+                return false;
+            }
+            match rx.recv_timeout(d) {
+                Ok(m) => {
+                    next = Some(m);
+                    false
+                }
+                Err(RecvTimeoutError::Timeout) => true,
+                Err(RecvTimeoutError::Disconnected) => false,
+            }
+        };
+        let r = match m {
+            Move::Throttle(rate) => actuator.set_throttle(rate, &mut wait),
+            Move::Pulse(rate, d) => actuator.pulse(rate, d, &mut wait),
+            Move::Hold => actuator.stop(),
+        };
+        if let Err(e) = r {
+            report(e);
+        }
+    }
+}
+
+#[derive(Clone)]
+pub enum ActuatorCfg {
+    // dc motor on an adafruit hat, pulsed open and closed
+    Dc {
+        hat_addr: u8,
+        // Motor1 to Motor4
+        motor: u8,
+    },
+    // stepper on an adafruit hat, steps from closed to open
+    Stepper {
+        hat_addr: u8,
+        // Stepper1 or Stepper2
+        stepper: u8,
+        steps: u32,
+        full_travel: Duration,
+    },
+    // proportional hydraulic valve on a hardware pwm channel,
+    // duty between the valve's deadband and full flow
+    Hydraulic {
+        channel: u8,
+        frequency_hz: f64,
+        min_duty: f64,
+        max_duty: f64,
+        full_travel: Duration,
+    },
+    // rc or linear servo on a hardware pwm channel, pulse width sets the position
+    Servo {
+        channel: u8,
+        closed_pulse: Duration,
+        open_pulse: Duration,
+        full_travel: Duration,
+    },
+}
+
+impl Default for ActuatorCfg {
+    fn default() -> Self {
+        ActuatorCfg::Dc {
+            hat_addr: 0x60,
+            motor: 1,
+        }
+    }
+}
+
+/// Hats opened so far, shared by the drives on them
+#[derive(Default)]
+pub struct Hats(Vec<(u8, Hat)>);
+
+impl Hats {
+    fn get(&mut self, addr: u8) -> Result<Hat, Box<dyn Error>> {
+        if let Some((_, hat)) = self.0.iter().find(|(a, _)| *a == addr) {
+            return Ok(hat.clone());
+        }
+        let hat = Arc::new(Mutex::new(init_hat(addr)?));
+        self.0.push((addr, hat.clone()));
+        Ok(hat)
+    }
+}
+
+pub fn open(cfg: &ActuatorCfg, hats: &mut Hats) -> Result<Box<dyn Actuator>, Box<dyn Error>> {
+    Ok(match *cfg {
+        ActuatorCfg::Dc { hat_addr, motor } => {
+            let hat = hats.get(hat_addr)?;
+            let motor = DcMotor::try_new(
+                &mut hat.lock().unwrap_or_else(PoisonError::into_inner),
+                dc_motor(motor)?,
+            )?;
+            Box::new(DcValve { hat, motor })
+        }
+        ActuatorCfg::Stepper {
+            hat_addr,
+            stepper,
+            steps,
+            full_travel,
+        } => {
+            let hat = hats.get(hat_addr)?;
+            let motor = StepperMotor::try_new(
+                &mut hat.lock().unwrap_or_else(PoisonError::into_inner),
+                stepper_motor(stepper)?,
+                None,
+            )?;
+            Box::new(StepperValve {
+                hat,
+                motor,
+                steps: steps as i32,
+                at: 0,
+                travel: Travel::new(full_travel),
+            })
+        }
+        ActuatorCfg::Hydraulic {
+            channel,
+            frequency_hz,
+            min_duty,
+            max_duty,
+            full_travel,
+        } => {
+            let pwm = Pwm::with_frequency(
+                pwm_channel(channel)?,
+                frequency_hz,
+                0.0,
+                Polarity::Normal,
+                true,
+            )?;
+            Box::new(HydraulicValve {
+                pwm,
+                min_duty,
+                max_duty,
+                travel: Travel::new(full_travel),
+            })
+        }
+        ActuatorCfg::Servo {
+            channel,
+            closed_pulse,
+            open_pulse,
+            full_travel,
+        } => {
+            // 50 Hz frame, starting closed
+            let pwm = Pwm::with_period(
+                pwm_channel(channel)?,
+                Duration::from_millis(20),
+                closed_pulse,
+                Polarity::Normal,
+                true,
+            )?;
+            Box::new(ServoValve {
+                pwm,
+                closed_pulse,
+                open_pulse,
+                travel: Travel::new(full_travel),
+            })
+        }
+    })
+}
+
+/// Position of a valve that is set rather than driven, 0.0 closed to 1.0 open.
+/// Throttle moves it as far as a dc valve would move in the same time,
+/// so the controller tuning carries over.
+pub struct Travel {
+    position: f32,
+    full_travel: Duration,
+}
+
+impl Travel {
+    pub fn new(full_travel: Duration) -> Self {
+        Travel {
+            position: 0.0,
+            full_travel,
+        }
+    }
+
+    pub fn position(&self) -> f32 {
+        self.position
+    }
+
+    pub fn after(&mut self, rate: f32, duration: Duration) -> f32 {
+        let moved =
+            rate * duration.as_secs_f32() / self.full_travel.as_secs_f32().max(f32::EPSILON);
+        self.position = (self.position + moved).clamp(0.0, 1.0);
+        self.position
+    }
+
+    // where a move cut short left it
+    pub fn set(&mut self, position: f32) {
+        self.position = position.clamp(0.0, 1.0);
+    }
+
+    // a throttle held with no duration runs to the end of travel
+    pub fn held(&mut self, rate: f32) -> f32 {
+        if rate > 0.0 {
+            self.position = 1.0;
+        } else if rate < 0.0 {
+            self.position = 0.0;
+        }
+        self.position
+    }
+}
+
+pub struct DcValve {
+    hat: Hat,
+    motor: DcMotor,
+}
+
+impl Actuator for DcValve {
+    fn set_throttle(&mut self, rate: f32, _: Wait) -> Result<(), Box<dyn Error>> {
+        self.motor.set_throttle(
+            &mut self.hat.lock().unwrap_or_else(PoisonError::into_inner),
+            rate,
+        )?;
+        Ok(())
+    }
+
+    fn stop(&mut self) -> Result<(), Box<dyn Error>> {
+        self.motor
+            .stop(&mut self.hat.lock().unwrap_or_else(PoisonError::into_inner))?;
+        Ok(())
+    }
+}
+
+pub struct StepperValve {
+    hat: Hat,
+    motor: StepperMotor,
+    // steps from closed to open
+    steps: i32,
+    at: i32,
+    travel: Travel,
+}
+
+impl StepperValve {
+    // steps spread over the time, the hat is released between steps for the other drives.
+    // A move cut short leaves the valve at the step it reached.
+    fn step_to(&mut self, position: f32, over: Duration, wait: Wait) -> Result<(), Box<dyn Error>> {
+        let target = (position * self.steps as f32).round() as i32;
+        let n = (target - self.at).unsigned_abs();
+        let step = (target - self.at).signum();
+        let dir = || {
+            if step > 0 {
+                StepDirection::Forward
+            } else {
+                StepDirection::Backward
+            }
+        };
+        for _ in 0..n {
+            self.motor.step_once(
+                &mut self.hat.lock().unwrap_or_else(PoisonError::into_inner),
+                dir(),
+                StepStyle::Double,
+            )?;
+            self.at += step;
+            if !wait(over / n) {
+                break;
+            }
+        }
+        self.travel.set(self.at as f32 / self.steps.max(1) as f32);
+        Ok(())
+    }
+}
+
+impl Actuator for StepperValve {
+    fn set_throttle(&mut self, rate: f32, wait: Wait) -> Result<(), Box<dyn Error>> {
+        // at the speed a dc valve would travel at the same throttle
+        let from = self.travel.position();
+        let position = self.travel.held(rate);
+        let over = self
+            .travel
+            .full_travel
+            .mul_f32((position - from).abs() / rate.abs().clamp(0.1, 1.0));
+        self.step_to(position, over, wait)
+    }
+
+    fn pulse(&mut self, rate: f32, duration: Duration, wait: Wait) -> Result<(), Box<dyn Error>> {
+        let position = self.travel.after(rate, duration);
+        self.step_to(position, duration, wait)
+    }
+
+    fn stop(&mut self) -> Result<(), Box<dyn Error>> {
+        self.motor
+            .stop(&mut self.hat.lock().unwrap_or_else(PoisonError::into_inner))?;
+        Ok(())
+    }
+}
+
+pub struct HydraulicValve {
+    pwm: Pwm,
+    min_duty: f64,
+    max_duty: f64,
+    travel: Travel,
+}
+
+impl HydraulicValve {
+    fn apply(&self, position: f32) -> Result<(), Box<dyn Error>> {
+        // below the deadband the valve is shut rather than weeping
+        let duty = if position <= 0.0 {
+            0.0
+        } else {
+            self.min_duty + position as f64 * (self.max_duty - self.min_duty)
+        };
+        self.pwm.set_duty_cycle(duty)?;
+        self.pwm.enable()?;
+        Ok(())
+    }
+}
+
+impl Actuator for HydraulicValve {
+    fn set_throttle(&mut self, rate: f32, _: Wait) -> Result<(), Box<dyn Error>> {
+        let position = self.travel.held(rate);
+        self.apply(position)
+    }
+
+    fn pulse(&mut self, rate: f32, duration: Duration, _: Wait) -> Result<(), Box<dyn Error>> {
+        let position = self.travel.after(rate, duration);
+        self.apply(position)
+    }
+
+    // no signal shuts the valve
+    fn stop(&mut self) -> Result<(), Box<dyn Error>> {
+        self.pwm.disable()?;
+        Ok(())
+    }
+}
+
+pub struct ServoValve {
+    pwm: Pwm,
+    closed_pulse: Duration,
+    open_pulse: Duration,
+    travel: Travel,
+}
+
+impl ServoValve {
+    fn apply(&self, position: f32) -> Result<(), Box<dyn Error>> {
+        let closed = self.closed_pulse.as_secs_f64();
+        let open = self.open_pulse.as_secs_f64();
+        let width = closed + position as f64 * (open - closed);
+        self.pwm.set_pulse_width(Duration::from_secs_f64(width))?;
+        self.pwm.enable()?;
+        Ok(())
+    }
+}
+
+impl Actuator for ServoValve {
+    fn set_throttle(&mut self, rate: f32, _: Wait) -> Result<(), Box<dyn Error>> {
+        let position = self.travel.held(rate);
+        self.apply(position)
+    }
+
+    fn pulse(&mut self, rate: f32, duration: Duration, _: Wait) -> Result<(), Box<dyn Error>> {
+        let position = self.travel.after(rate, duration);
+        self.apply(position)
+    }
+
+    // with no pulses the servo stops driving
+    fn stop(&mut self) -> Result<(), Box<dyn Error>> {
+        self.pwm.disable()?;
+        Ok(())
+    }
+}
+
+// a motor hat at its i2c address, set up as init_pwm does for the default one
+fn init_hat(addr: u8) -> Result<Pca9685<I2cdev>, Box<dyn Error>> {
+    let bit = |n: u8| addr & (1 << n) != 0;
+    let address = SlaveAddr::Alternative(bit(5), bit(4), bit(3), bit(2), bit(1), bit(0));
+    let mut pwm = Pca9685::new(I2cdev::new("/dev/i2c-1")?, address);
+    pwm.enable().map_err(|e| format!("hat {addr:#x}: {e:?}"))?;
+    pwm.set_prescale(4)
+        .map_err(|e| format!("hat {addr:#x}: {e:?}"))?;
+    Ok(pwm)
+}
+
+fn dc_motor(n: u8) -> Result<Motor, Box<dyn Error>> {
+    match n {
+        1 => Ok(Motor::Motor1),
+        2 => Ok(Motor::Motor2),
+        3 => Ok(Motor::Motor3),
+        4 => Ok(Motor::Motor4),
+        n => Err(format!("no dc motor {n} on the hat").into()),
+    }
+}
+
+fn stepper_motor(n: u8) -> Result<Motor, Box<dyn Error>> {
+    match n {
+        1 => Ok(Motor::Stepper1),
+        2 => Ok(Motor::Stepper2),
+        n => Err(format!("no stepper {n} on the hat").into()),
+    }
+}
+
+fn pwm_channel(n: u8) -> Result<Channel, Box<dyn Error>> {
+    match n {
+        0 => Ok(Channel::Pwm0),
+        1 => Ok(Channel::Pwm1),
+        n => Err(format!("no hardware pwm channel {n}").into()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn travel_follows_pulses() {
+        let mut t = Travel::new(Duration::from_secs(2));
+        // closing against the end stays closed
+        assert_eq!(t.after(-1.0, Duration::from_millis(50)), 0.0);
+        // half throttle for a second is a quarter of the travel
+        assert!((t.after(0.5, Duration::from_secs(1)) - 0.25).abs() < 1e-6);
+        assert!((t.after(-1.0, Duration::from_millis(100)) - 0.2).abs() < 1e-6);
+        assert_eq!(t.held(0.0), t.position());
+        assert_eq!(t.held(1.0), 1.0);
+        assert_eq!(t.after(1.0, Duration::from_secs(1)), 1.0);
+    }
+
+    // records what it was asked to do, failing on any throttle above 1.0
+    struct Recorder(Arc<Mutex<Vec<Move>>>);
+
+    impl Actuator for Recorder {
+        fn set_throttle(&mut self, rate: f32, _: Wait) -> Result<(), Box<dyn Error>> {
+            self.0.lock().unwrap().push(Move::Throttle(rate));
+            if rate > 1.0 {
+                return Err("out of range".into());
+            }
+            Ok(())
+        }
+
+        fn stop(&mut self) -> Result<(), Box<dyn Error>> {
+            self.0.lock().unwrap().push(Move::Hold);
+            Ok(())
+        }
+    }
+
+    // panics on any throttle, as a backend might on a bus fault
+    struct Panicky(Arc<Mutex<Vec<Move>>>);
+
+    impl Actuator for Panicky {
+        fn set_throttle(&mut self, _: f32, _: Wait) -> Result<(), Box<dyn Error>> {
+            panic!("bus fault");
+        }
+
+        fn stop(&mut self) -> Result<(), Box<dyn Error>> {
+            self.0.lock().unwrap().push(Move::Hold);
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn stopped_after_a_panic() {
+        let moves = Arc::new(Mutex::new(vec![]));
+        let errors = Mutex::new(vec![]);
+        let (tx, rx) = crossbeam_channel::unbounded();
+        tx.send(Move::Throttle(0.5)).unwrap();
+        run(Box::new(Panicky(moves.clone())), rx, |e| {
+            errors.lock().unwrap().push(e.to_string())
+        });
+        assert_eq!(*moves.lock().unwrap(), [Move::Hold]);
+        assert_eq!(*errors.lock().unwrap(), ["actuator panicked"]);
+    }
+
+    #[test]
+    fn next_command_cuts_a_pulse_short() {
+        use std::time::Instant;
+
+        let moves = Arc::new(Mutex::new(vec![]));
+        let errors = Arc::new(Mutex::new(0));
+        let (tx, rx) = crossbeam_channel::unbounded();
+        let runner = {
+            let actuator = Box::new(Recorder(moves.clone()));
+            let errors = errors.clone();
+            std::thread::spawn(move || run(actuator, rx, |_| *errors.lock().unwrap() += 1))
+        };
+
+        let start = Instant::now();
+        tx.send(Move::Throttle(2.0)).unwrap();
+        tx.send(Move::Pulse(1.0, Duration::from_secs(10))).unwrap();
+        std::thread::sleep(Duration::from_millis(50));
+        tx.send(Move::Hold).unwrap();
+        drop(tx);
+        runner.join().unwrap();
+
+        assert!(start.elapsed() < Duration::from_secs(2));
+        // a failed move is reported and the next one still runs
+        assert_eq!(*errors.lock().unwrap(), 1);
+        assert_eq!(
+            *moves.lock().unwrap(),
+            [
+                Move::Throttle(2.0),
+                Move::Throttle(1.0),
+                Move::Throttle(0.0),
+                Move::Hold,
+                Move::Hold
+            ]
+        );
+    }
+}
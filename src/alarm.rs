@@ -0,0 +1,212 @@
+use crate::diagnostics::WheelFault;
+use crate::watchdog::Source;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::time::Duration;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+pub enum Severity {
+    Info,
+    Warning,
+    Critical,
+}
+
+impl Severity {
+    // one cycle of the buzzer, (on, duration) steps repeated while the alarm is unacknowledged
+    pub fn beep_pattern(&self) -> &'static [(bool, Duration)] {
+        use Severity::*;
+        const INFO: &[(bool, Duration)] = &[
+            (true, Duration::from_millis(150)),
+            (false, Duration::from_millis(5000)),
+        ];
+        const WARNING: &[(bool, Duration)] = &[
+            (true, Duration::from_millis(150)),
+            (false, Duration::from_millis(150)),
+            (true, Duration::from_millis(150)),
+            (false, Duration::from_millis(1500)),
+        ];
+        const CRITICAL: &[(bool, Duration)] = &[
+            (true, Duration::from_millis(250)),
+            (false, Duration::from_millis(100)),
+        ];
+        match self {
+            Info => INFO,
+            Warning => WARNING,
+            Critical => CRITICAL,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Alarm {
+    Test(Severity),
+    StaleInput(Source),
+    NoJob,
+    CallTruck,
+    OutsideBoundary,
+    // a drive's seed wheel not following its valve
+    SeedWheel(usize, WheelFault),
+    // a command to a flow actuator failed
+    FlowDrive(usize),
+}
+
+impl Alarm {
+    pub fn severity(&self) -> Severity {
+        match self {
+            Alarm::Test(s) => *s,
+            Alarm::StaleInput(_) | Alarm::SeedWheel(..) | Alarm::FlowDrive(_) => Severity::Critical,
+            Alarm::NoJob | Alarm::CallTruck | Alarm::OutsideBoundary => Severity::Warning,
+        }
+    }
+
+    // alarms that have no condition to clear them go away once acknowledged
+    fn clears_on_ack(&self) -> bool {
+        matches!(
+            self,
+            Alarm::Test(_) | Alarm::NoJob | Alarm::CallTruck | Alarm::FlowDrive(_)
+        )
+    }
+}
+
+impl fmt::Display for Alarm {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Alarm::Test(s) => write!(f, "Test alarm ({s:?})"),
+            Alarm::StaleInput(Source::GroundSpeed) => write!(f, "No ground speed"),
+            Alarm::StaleInput(Source::Lift) => write!(f, "No lift sensor"),
+            Alarm::NoJob => write!(f, "No job selected"),
+            Alarm::CallTruck => write!(f, "Call the seed truck"),
+            Alarm::OutsideBoundary => write!(f, "Outside field boundary"),
+            Alarm::SeedWheel(id, WheelFault::Stalled) => {
+                write!(f, "Seed wheel {} stalled", id + 1)
+            }
+            Alarm::SeedWheel(id, WheelFault::EncoderLost) => {
+                write!(f, "No ticks from seed wheel {} encoder", id + 1)
+            }
+            Alarm::SeedWheel(id, WheelFault::Reversed) => {
+                write!(f, "Seed wheel {} turning backwards", id + 1)
+            }
+            Alarm::FlowDrive(id) => write!(f, "Flow drive {} not responding", id + 1),
+        }
+    }
+}
+
+/// State of the alarm outputs, the buzzer pattern and the beacon relay
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct AlarmOutputs {
+    pub buzzer: Option<Severity>,
+    pub beacon: bool,
+}
+
+/// Active alarms, each flagged once acknowledged by the operator
+#[derive(Default)]
+pub struct Alarms {
+    active: Vec<(Alarm, bool)>,
+}
+
+impl Alarms {
+    // returns true when the alarm was not already active
+    pub fn raise(&mut self, alarm: Alarm) -> bool {
+        if self.active.iter().any(|(a, _)| *a == alarm) {
+            return false;
+        }
+        self.active.push((alarm, false));
+        true
+    }
+
+    pub fn clear(&mut self, alarm: &Alarm) -> bool {
+        let len = self.active.len();
+        self.active.retain(|(a, _)| a != alarm);
+        len != self.active.len()
+    }
+
+    pub fn acknowledge(&mut self) {
+        self.active.retain(|(a, _)| !a.clears_on_ack());
+        self.active.iter_mut().for_each(|(_, ack)| *ack = true);
+    }
+
+    pub fn is_active(&self, alarm: &Alarm) -> bool {
+        self.active.iter().any(|(a, _)| a == alarm)
+    }
+
+    // most severe active alarm, unacknowledged alarms first
+    pub fn current(&self) -> Option<&Alarm> {
+        self.active
+            .iter()
+            .max_by_key(|(a, ack)| (!ack, a.severity()))
+            .map(|(a, _)| a)
+    }
+
+    pub fn unacknowledged(&self) -> bool {
+        self.active.iter().any(|(_, ack)| !ack)
+    }
+
+    // the buzzer sounds until acknowledged, the beacon stays lit while a warning is active
+    pub fn outputs(&self) -> AlarmOutputs {
+        AlarmOutputs {
+            buzzer: self
+                .active
+                .iter()
+                .filter(|(_, ack)| !ack)
+                .map(|(a, _)| a.severity())
+                .max(),
+            beacon: self
+                .active
+                .iter()
+                .any(|(a, _)| a.severity() >= Severity::Warning),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn raise_acknowledge_clear() {
+        let mut alarms = Alarms::default();
+        assert_eq!(alarms.current(), None);
+        assert_eq!(alarms.outputs(), AlarmOutputs::default());
+
+        let stale = Alarm::StaleInput(Source::Lift);
+        assert!(alarms.raise(Alarm::NoJob));
+        assert!(alarms.raise(stale.clone()));
+        assert!(!alarms.raise(Alarm::NoJob));
+        assert_eq!(alarms.current(), Some(&stale));
+        assert_eq!(
+            alarms.outputs(),
+            AlarmOutputs {
+                buzzer: Some(Severity::Critical),
+                beacon: true,
+            }
+        );
+
+        // the job alarm goes, the stale input stays until the sensor is heard again
+        alarms.acknowledge();
+        assert!(!alarms.is_active(&Alarm::NoJob));
+        assert!(alarms.is_active(&stale));
+        assert!(!alarms.unacknowledged());
+        assert_eq!(
+            alarms.outputs(),
+            AlarmOutputs {
+                buzzer: None,
+                beacon: true,
+            }
+        );
+
+        // a new alarm comes before an acknowledged one, whatever its severity
+        alarms.raise(Alarm::Test(Severity::Info));
+        assert_eq!(alarms.current(), Some(&Alarm::Test(Severity::Info)));
+        assert_eq!(alarms.outputs().buzzer, Some(Severity::Info));
+
+        assert!(alarms.clear(&stale));
+        assert!(!alarms.clear(&stale));
+        assert_eq!(
+            alarms.outputs(),
+            AlarmOutputs {
+                buzzer: Some(Severity::Info),
+                beacon: false,
+            }
+        );
+    }
+}
@@ -1,13 +1,51 @@
-use iced::{executor, subscription, Application, Command, Element, Renderer, Subscription, Theme};
-use std::time::Duration;
+use iced::futures::future;
+use iced::{
+    event, executor, subscription, time, window, Application, Command, Element, Renderer,
+    Subscription, Theme,
+};
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::signal::unix::{signal, SignalKind};
 
-use crate::gui::{make_dash_page, make_io_page};
-use crate::monitor::Monitor;
+use crate::alarm::Alarm;
+use crate::coverage::Coverage;
+use crate::db::{BulkFill, Field, Job, SeedLot, Variety};
+use crate::flowmap::StartCfg;
+use crate::geo::Boundary;
+use crate::geometry::GeometryCfg;
+use crate::gui::{make_dash_page, make_io_page, make_jobs_page};
+use crate::hopper::Prediction;
+use crate::isoxml;
+use crate::keypad::{Key, Keypad, KeypadTarget};
+use crate::log;
+use crate::map::{make_map_page, MapView};
+use crate::mode::{Mode, ModeInput};
+use crate::monitor::{Monitor, ROWS};
 use crate::msg::Message;
+use crate::prescription::PrescriptionCfg;
+use crate::section::SectionCfg;
+use crate::seed::{self, SeedCfg};
+use crate::settings::{self, Settings};
+use crate::util::{population_to_spacing, secs_or, spacing_to_population};
+
+// how often input freshness is checked
+const WATCHDOG_INTERVAL: Duration = Duration::from_millis(250);
+
+// settings are written this long after the last change, to spare the SD card
+const SETTINGS_SAVE_DELAY: Duration = Duration::from_secs(5);
+
+// session logs are written out before an export reads them
+const EXPORT_FLUSH_TIMEOUT: Duration = Duration::from_secs(1);
+
+// bulk fills used to reconcile the seed estimate
+const RECONCILE_FILLS: usize = 10;
 
 pub enum Page {
     Dashboard,
     SoftIO,
+    Jobs,
+    Map,
 }
 
 /// Potato planting dashboard
@@ -15,6 +53,25 @@ pub struct Dash {
     monitor: Monitor,
     pub page: Page,
     pub in_between_seed: f32,
+    pub keypad: Option<Keypad>,
+    fields: Vec<Field>,
+    varieties: Vec<Variety>,
+    seed_lots: Vec<SeedLot>,
+    // recent bulk fills, for reconciling the seed estimate
+    fills: Vec<BulkFill>,
+    // applied to jobs started from here on
+    variety: Option<(Variety, Option<SeedLot>)>,
+    // field the planter was last found in, and one offered for a job switch
+    last_in_field: Option<i64>,
+    suggested_field: Option<i64>,
+    map: MapView,
+    settings: Settings,
+    settings_path: PathBuf,
+    // last change not yet written, saved once the spacing buttons are left alone
+    settings_changed: Option<Instant>,
+    // sessions read by the last export, shared with the export thread
+    exported: Arc<Mutex<isoxml::SessionCache>>,
+    device: Arc<isoxml::DeviceCfg>,
 }
 
 impl Dash {
@@ -31,7 +88,11 @@ impl Dash {
     }
 
     pub fn row_feet_planted(&self) -> f32 {
-        self.monitor.feet_planted
+        self.monitor.counters.feet_planted
+    }
+
+    pub fn tally_feet(&self, id: usize) -> f32 {
+        self.monitor.counters.tally_feet[id]
     }
 
     pub fn ground_speed_mph(&self) -> f32 {
@@ -39,7 +100,296 @@ impl Dash {
     }
 
     pub fn seed_wheel_speed_rpm(&self) -> f32 {
-        self.monitor.seed_wheel_speed_rpm
+        self.monitor.seed_wheel_speed_rpm()
+    }
+
+    pub fn drive_count(&self) -> usize {
+        self.monitor.drives.len()
+    }
+
+    pub fn drive_rpm(&self, id: usize) -> f32 {
+        self.monitor.drives[id].seed_wheel_speed_rpm
+    }
+
+    // open share of the flow valve, once homed
+    pub fn valve_position(&self, id: usize) -> Option<f32> {
+        self.monitor.drives[id].valve.position()
+    }
+
+    pub fn homing(&self, id: usize) -> bool {
+        self.monitor.is_homing(id)
+    }
+
+    pub fn mode(&self) -> Mode {
+        self.monitor.mode
+    }
+
+    pub fn current_alarm(&self) -> Option<&Alarm> {
+        self.monitor.alarms.current()
+    }
+
+    pub fn alarm_unacknowledged(&self) -> bool {
+        self.monitor.alarms.unacknowledged()
+    }
+
+    pub fn job(&self) -> Option<&Job> {
+        self.monitor.job.as_ref()
+    }
+
+    pub fn fields(&self) -> &[Field] {
+        &self.fields
+    }
+
+    pub fn varieties(&self) -> &[Variety] {
+        &self.varieties
+    }
+
+    pub fn seed_lots(&self, variety_id: i64) -> impl Iterator<Item = &SeedLot> {
+        self.seed_lots
+            .iter()
+            .filter(move |l| l.variety_id == variety_id)
+    }
+
+    pub fn variety(&self) -> Option<&(Variety, Option<SeedLot>)> {
+        self.variety.as_ref()
+    }
+
+    pub fn suggested_field(&self) -> Option<&Field> {
+        let id = self.suggested_field?;
+        self.fields.iter().find(|f| f.id == id)
+    }
+
+    pub fn map_view(&self) -> &MapView {
+        &self.map
+    }
+
+    pub fn coverage(&self) -> &Coverage {
+        &self.monitor.coverage
+    }
+
+    pub fn boundaries(&self) -> impl Iterator<Item = &Boundary> {
+        self.monitor.locator.boundaries()
+    }
+
+    // planted width, metres
+    pub fn swath_m(&self) -> f32 {
+        self.settings.row_width_in * ROWS as f32 * 0.0254
+    }
+
+    // prescription zone ahead and its spacing
+    pub fn zone(&self) -> Option<(&str, f32)> {
+        let vr = &self.monitor.variable_rate;
+        Some((vr.zone.as_deref()?, vr.prescribed_in?))
+    }
+
+    pub fn row_on(&self, id: usize) -> bool {
+        self.monitor.sections.is_on(id)
+    }
+
+    // spacing error on the row and whether it is within tolerance
+    pub fn row_spacing_error(&self, id: usize) -> Option<(f32, bool)> {
+        let ratio = self.monitor.row_spacing_ratio(id)?;
+        Some((ratio - 1.0, self.monitor.row_in_tolerance(id)))
+    }
+
+    pub fn hopper_duty(&self, id: usize) -> Option<f32> {
+        self.monitor.hoppers.duty(id)
+    }
+
+    pub fn bin_prediction(&self) -> Option<Prediction> {
+        self.monitor.bin_prediction(Instant::now())
+    }
+
+    pub fn seed_cwt_onboard(&self) -> f32 {
+        self.monitor.counters.onboard_cwt
+    }
+
+    pub fn seed_cwt_truck(&self) -> f32 {
+        self.monitor.counters.truck_cwt
+    }
+
+    // loaded over estimated, above 1 the estimate is low
+    pub fn seed_estimate_ratio(&self) -> Option<f32> {
+        seed::reconcile(&self.fills)
+    }
+
+    pub fn population(&self) -> f32 {
+        spacing_to_population(self.in_between_seed, self.settings.row_width_in)
+    }
+
+    // takes effect on the controller target immediately and is saved for the next start
+    pub fn set_spacing(&mut self, in_between: f32) {
+        let in_between = self.settings.clamp_spacing(in_between);
+        self.in_between_seed = in_between;
+        self.monitor.set_spacing(in_between);
+
+        self.settings.seed_spacing_in = in_between;
+        self.settings_changed = Some(Instant::now());
+    }
+
+    fn save_settings(&mut self) {
+        self.settings_changed = None;
+        if let Err(e) = self.settings.save(&self.settings_path) {
+            eprintln!("failed to save settings: {e}");
+        }
+    }
+
+    fn load_fields(&mut self) {
+        let Some(db) = &self.monitor.db else {
+            return;
+        };
+        match db.fields() {
+            Ok(fields) => self.fields = fields,
+            Err(e) => eprintln!("failed to load fields: {e}"),
+        }
+        match db.varieties().and_then(|v| Ok((v, db.seed_lots()?))) {
+            Ok((varieties, lots)) => {
+                self.varieties = varieties;
+                self.seed_lots = lots;
+            }
+            Err(e) => eprintln!("failed to load varieties: {e}"),
+        }
+        self.monitor.load_boundaries();
+        self.map.redraw();
+    }
+
+    fn load_fills(&mut self) {
+        let Some(db) = &self.monitor.db else {
+            return;
+        };
+        match db.bulk_fills(RECONCILE_FILLS) {
+            Ok(fills) => self.fills = fills,
+            Err(e) => eprintln!("failed to load bulk fills: {e}"),
+        }
+    }
+
+    fn select_variety(&mut self, variety_id: i64, lot_id: Option<i64>) {
+        let Some(variety) = self.varieties.iter().find(|v| v.id == variety_id).cloned() else {
+            return;
+        };
+        let lot = lot_id.and_then(|id| self.seed_lots.iter().find(|l| l.id == id).cloned());
+        self.monitor.set_job_variety(&variety, lot.as_ref());
+        self.monitor.seed_meter.cfg.piece_weight_oz = variety
+            .piece_weight_oz
+            .unwrap_or(self.settings.seed_piece_weight_oz);
+        self.set_spacing(variety.spacing_in);
+        self.variety = Some((variety, lot));
+    }
+
+    // entering a field other than the job's switches to it, or offers to
+    fn follow_field(&mut self) {
+        let in_field = self.monitor.in_field;
+        if in_field == self.last_in_field {
+            return;
+        }
+        self.last_in_field = in_field;
+        self.suggested_field = None;
+        let Some(id) = in_field.filter(|id| self.job().map(|j| j.field_id) != Some(*id)) else {
+            return;
+        };
+        if self.settings.auto_select_job && self.mode() != Mode::Planting {
+            self.select_field(id);
+        } else {
+            self.suggested_field = Some(id);
+        }
+    }
+
+    // continues the open job on the field, or starts one at the current spacing
+    fn select_field(&mut self, field_id: i64) {
+        self.suggested_field = None;
+        let Some(db) = &self.monitor.db else {
+            return;
+        };
+        let job = db.open_job(field_id).and_then(|job| match job {
+            Some(job) => Ok((job, false)),
+            None => db
+                .add_job(
+                    field_id,
+                    None,
+                    self.in_between_seed,
+                    self.settings.row_width_in,
+                )
+                .and_then(|id| Ok((db.job(id)?, true))),
+        });
+        match job {
+            Ok((job, new)) => {
+                let spacing = job.spacing_in;
+                self.monitor.select_job(job);
+                match self.variety.clone() {
+                    Some((variety, lot)) if new => {
+                        self.monitor.set_job_variety(&variety, lot.as_ref());
+                        self.set_spacing(variety.spacing_in);
+                    }
+                    _ => self.set_spacing(spacing),
+                }
+            }
+            Err(e) => eprintln!("failed to open job: {e}"),
+        }
+    }
+
+    // all session logs as ISOXML onto the USB stick, off the ui thread
+    fn export_taskdata(&mut self) -> Command<Message> {
+        let Some(usb) = isoxml::usb_mounts().into_iter().next() else {
+            self.monitor.note("export: no USB stick".to_string());
+            return Command::none();
+        };
+        self.monitor.log.flush(EXPORT_FLUSH_TIMEOUT);
+        let fields = self.fields.clone();
+        let row_width = self.settings.row_width_in;
+        let cache = self.exported.clone();
+        let device = self.device.clone();
+        Command::perform(
+            async move {
+                tokio::task::spawn_blocking(move || {
+                    // a second export waits for the first
+                    let mut cache = cache.lock().unwrap_or_else(|e| e.into_inner());
+                    isoxml::export_sessions(
+                        &mut cache,
+                        &log::default_dir(),
+                        &usb,
+                        row_width,
+                        &fields,
+                        &device,
+                    )
+                })
+                .await
+                .map_err(|e| e.to_string())
+                .and_then(|r| r.map_err(|e| e.to_string()))
+                .map(|dir| dir.display().to_string())
+            },
+            Message::Exported,
+        )
+    }
+
+    fn shutdown(&mut self) {
+        self.save_settings();
+        self.monitor.shutdown();
+    }
+
+    fn keypad_press(&mut self, key: Key) {
+        let Some(keypad) = self.keypad.as_mut() else {
+            return;
+        };
+        match key {
+            Key::Cancel => self.keypad = None,
+            Key::Enter => {
+                if let Some(v) = keypad.value() {
+                    match keypad.target {
+                        KeypadTarget::Spacing => self.set_spacing(v),
+                        KeypadTarget::Population => {
+                            self.set_spacing(population_to_spacing(v, self.settings.row_width_in))
+                        }
+                        KeypadTarget::TruckLoad => self.monitor.truck_load(v),
+                        KeypadTarget::BulkFill => {
+                            self.monitor.bulk_fill(v);
+                            self.load_fills();
+                        }
+                    }
+                }
+                self.keypad = None;
+            }
+            k => keypad.press(k),
+        }
     }
 }
 
@@ -50,14 +400,77 @@ impl Application for Dash {
     type Flags = Monitor;
 
     fn new(flags: Self::Flags) -> (Self, Command<Self::Message>) {
-        (
-            Dash {
-                monitor: flags,
-                page: Page::Dashboard,
-                in_between_seed: 10.0,
-            },
-            Command::none(),
-        )
+        let settings_path = settings::default_path();
+        let settings = Settings::load(&settings_path);
+        let mut monitor = flags;
+        monitor.set_spacing(settings.seed_spacing_in);
+        monitor.seed_meter.cfg = SeedCfg {
+            pick_efficiency: settings.pick_efficiency,
+            piece_weight_oz: settings.seed_piece_weight_oz,
+        };
+        let sections = SectionCfg::default();
+        monitor.sections.cfg = SectionCfg {
+            enabled: settings.section_control,
+            // without a clutch on each row only the whole planter can be stopped
+            per_row: settings.section_per_row && monitor.io.row_clutches,
+            look_ahead_off: secs_or(settings.look_ahead_off_s, sections.look_ahead_off),
+            look_ahead_on: secs_or(settings.look_ahead_on_s, sections.look_ahead_on),
+            row_width_m: settings.row_width_in as f64 * 0.0254,
+        };
+        monitor.variable_rate.cfg = PrescriptionCfg {
+            look_ahead: secs_or(
+                settings.rx_look_ahead_s,
+                PrescriptionCfg::default().look_ahead,
+            ),
+            row_width_in: settings.row_width_in,
+            min_spacing_in: settings.min_spacing_in,
+            max_spacing_in: settings.max_spacing_in,
+        };
+        monitor.planter.cfg = GeometryCfg {
+            antenna_to_hitch_m: settings.antenna_to_hitch_m as f64,
+            antenna_offset_m: settings.antenna_offset_m as f64,
+            hitch_to_rows_m: settings.hitch_to_rows_m as f64,
+            row_offsets_m: [settings.row_offset_1_m, settings.row_offset_2_m].map(f64::from),
+        };
+        let start = StartCfg::default();
+        monitor.start_cfg = StartCfg {
+            lead_ft: settings.start_lead_ft,
+            lead_time: secs_or(settings.start_lead_s, start.lead_time),
+            close_on_raise: settings.close_on_raise,
+            ..start
+        };
+        monitor.resume_job();
+        let mut dash = Dash {
+            monitor,
+            page: Page::Dashboard,
+            in_between_seed: settings.seed_spacing_in,
+            keypad: None,
+            fields: vec![],
+            varieties: vec![],
+            seed_lots: vec![],
+            fills: vec![],
+            variety: None,
+            last_in_field: None,
+            suggested_field: None,
+            map: MapView::default(),
+            settings,
+            settings_path,
+            settings_changed: None,
+            exported: Arc::default(),
+            device: Arc::new(isoxml::DeviceCfg::local()),
+        };
+        dash.load_fields();
+        dash.load_fills();
+        let resumed = dash.job().and_then(|j| j.variety.as_ref());
+        if let Some(oz) = dash
+            .varieties
+            .iter()
+            .find(|v| Some(&v.name) == resumed)
+            .and_then(|v| v.piece_weight_oz)
+        {
+            dash.monitor.seed_meter.cfg.piece_weight_oz = oz;
+        }
+        (dash, Command::none())
     }
 
     fn title(&self) -> String {
@@ -73,10 +486,52 @@ impl Application for Dash {
                 self.monitor.enable_seed_belt(id, self.monitor.priming[id]);
             }
             Halt => self.monitor.halt(),
+            ResetTally(id) => self.monitor.counters.reset_tally(id),
+            Shutdown => {
+                self.shutdown();
+                return window::close();
+            }
+            SelectMode(mode) => self.monitor.transition(ModeInput::Select(mode)),
+            IncreaseSpacing => {
+                self.set_spacing(self.in_between_seed + self.settings.spacing_step_in)
+            }
+            DecreaseSpacing => {
+                self.set_spacing(self.in_between_seed - self.settings.spacing_step_in)
+            }
+            OpenKeypad(target) => self.keypad = Some(Keypad::new(target)),
+            KeypadPress(key) => self.keypad_press(key),
             TabSelected(i) if i == 0 => self.page = Page::Dashboard,
             TabSelected(i) if i == 1 => self.page = Page::SoftIO,
+            TabSelected(i) if i == 2 => {
+                self.load_fields();
+                self.page = Page::Jobs;
+            }
+            TabSelected(i) if i == 3 => self.page = Page::Map,
+            SelectField(id) => self.select_field(id),
+            SelectVariety(id, lot) => self.select_variety(id, lot),
+            FinishJob => self.monitor.finish_job(),
+            ExportTaskData => return self.export_taskdata(),
+            Exported(Ok(dir)) => self.monitor.note(format!("exported {dir}")),
+            Exported(Err(e)) => self.monitor.note(format!("export failed: {e}")),
+            MapPan(centre) => self.map.centre = Some(centre),
+            MapZoom(factor) => self.map.zoom_by(factor),
+            MapFollow => self.map.follow(),
+            HomeValve(id) => self.monitor.home_valve(id),
             IOEvent(e) => self.monitor.handle_event(e),
-            SimulateCmd(cmd) => self.monitor.io.tx.send(cmd).unwrap(),
+            Tick(now) => {
+                self.monitor.tick(now);
+                if let Some(at) = self.settings_changed {
+                    if now.saturating_duration_since(at) >= SETTINGS_SAVE_DELAY {
+                        self.save_settings();
+                    }
+                }
+                self.follow_field();
+                // the prescription moves the target without going through set_spacing
+                self.in_between_seed = self.monitor.spacing();
+            }
+            SimulateCmd(cmd) => self.monitor.simulate(cmd),
+            AcknowledgeAlarms => self.monitor.acknowledge_alarms(),
+            RaiseAlarm(alarm) => self.monitor.raise_alarm(alarm),
             _ => {}
         };
         Command::none()
@@ -86,16 +541,43 @@ impl Application for Dash {
         match self.page {
             Page::Dashboard => make_dash_page(self).into(),
             Page::SoftIO => make_io_page(self).into(),
+            Page::Jobs => make_jobs_page(self).into(),
+            Page::Map => make_map_page(self).into(),
         }
     }
 
     fn subscription(&self) -> Subscription<Self::Message> {
         let rx = self.monitor.io.rx.clone();
-        subscription::unfold("foo", (rx), move |(rx)| async {
-            match rx.recv_timeout(Duration::from_secs(1)) {
-                Ok(e) => (Message::IOEvent(e), (rx)),
-                _ => (Message::Halt, (rx)),
+        let events = subscription::unfold("io-events", rx, move |rx| async {
+            let (e, rx) = tokio::task::spawn_blocking(move || (rx.recv(), rx))
+                .await
+                .expect("io event reader");
+            match e {
+                Ok(e) => (Message::IOEvent(e), rx),
+                // the io threads are gone, nothing more will arrive
+                Err(_) => future::pending().await,
             }
-        })
+        });
+
+        // ctrl-c, systemd stop and the window close button all take the shutdown path
+        let signals = subscription::unfold("signals", (), |_| async {
+            let mut term = signal(SignalKind::terminate()).expect("sigterm handler");
+            tokio::select! {
+                _ = tokio::signal::ctrl_c() => {}
+                _ = term.recv() => {}
+            }
+            (Message::Shutdown, ())
+        });
+        let close = subscription::events_with(|e, _| match e {
+            event::Event::Window(window::Event::CloseRequested) => Some(Message::Shutdown),
+            _ => None,
+        });
+
+        Subscription::batch([
+            events,
+            signals,
+            close,
+            time::every(WATCHDOG_INTERVAL).map(Message::Tick),
+        ])
     }
 }
@@ -0,0 +1,582 @@
+use crate::geo::{self, LatLon, LocalProjection, Xy};
+use crate::io::Event;
+use crate::log::{Entry, Record};
+use crate::util::tickrate_to_spacing;
+use chrono::{Local, TimeZone};
+use serde_json::json;
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+
+/// One GPS fix while the planter was in the ground
+#[derive(Debug, Clone, PartialEq)]
+pub struct Point {
+    pub pos: LatLon,
+    pub speed_mph: f32,
+    pub target_spacing_in: f32,
+    pub actual_spacing_in: Option<f32>,
+}
+
+/// Coverage from lowering the planter until it is raised
+#[derive(Debug, Clone, PartialEq)]
+pub struct PlantedPass {
+    pub number: usize,
+    pub started_ms: u64,
+    pub field: Option<String>,
+    pub variety: Option<String>,
+    pub seed_lot: Option<String>,
+    pub points: Vec<Point>,
+}
+
+// fixes that all lie this close to the first cover no ground, metres
+const MIN_EXTENT_M: f64 = 0.1;
+
+fn mean(values: impl Iterator<Item = f32>) -> Option<f32> {
+    let (sum, n) = values.fold((0.0, 0), |(s, n), v| (s + v, n + 1));
+    (n > 0).then(|| sum / n as f32)
+}
+
+impl PlantedPass {
+    pub fn speed_mph(&self) -> f32 {
+        mean(self.points.iter().map(|p| p.speed_mph)).unwrap_or_default()
+    }
+
+    pub fn target_spacing_in(&self) -> f32 {
+        mean(self.points.iter().map(|p| p.target_spacing_in)).unwrap_or_default()
+    }
+
+    pub fn actual_spacing_in(&self) -> Option<f32> {
+        mean(self.points.iter().filter_map(|p| p.actual_spacing_in))
+    }
+
+    // standing still with the planter down
+    fn degenerate(&self) -> bool {
+        let Some(first) = self.points.first() else {
+            return true;
+        };
+        let proj = LocalProjection::new(first.pos);
+        self.points
+            .iter()
+            .all(|p| proj.to_local(p.pos).dist(&Xy::default()) <= MIN_EXTENT_M)
+    }
+
+    pub fn started(&self) -> String {
+        Local
+            .timestamp_millis_opt(self.started_ms as i64)
+            .single()
+            .map(|t| t.format("%Y-%m-%d %H:%M:%S").to_string())
+            .unwrap_or_default()
+    }
+}
+
+// the lift switch only reports changes, a first raise means it started in the ground
+pub fn starts_lowered(entries: &[Entry]) -> bool {
+    entries
+        .iter()
+        .find_map(|e| match e.record {
+            Record::Event(Event::PlanterRaised) => Some(true),
+            Record::Event(Event::PlanterLowered) => Some(false),
+            _ => None,
+        })
+        .unwrap_or(false)
+}
+
+// logs with planter positions are mapped from them rather than the antenna
+pub fn has_planter(entries: &[Entry]) -> bool {
+    entries
+        .iter()
+        .any(|e| matches!(e.record, Record::Planter(_)))
+}
+
+/// Splits a session log into passes. A pass ends when the planter is raised
+/// or the variety changes.
+pub fn passes(entries: &[Entry]) -> Vec<PlantedPass> {
+    let mut lowered = starts_lowered(entries);
+    let planter = has_planter(entries);
+
+    let mut out = vec![];
+    let mut current: Option<PlantedPass> = None;
+    let mut field = None;
+    let mut variety = None;
+    let mut seed_lot = None;
+    let mut spacing = 0.0;
+    let mut tickrate = None;
+
+    let end = |pass: Option<PlantedPass>, out: &mut Vec<PlantedPass>| {
+        if let Some(p) = pass.filter(|p| p.points.len() > 1 && !p.degenerate()) {
+            out.push(PlantedPass {
+                number: out.len() + 1,
+                ..p
+            });
+        }
+    };
+
+    for e in entries {
+        match &e.record {
+            Record::Event(Event::PlanterLowered) => lowered = true,
+            Record::Event(Event::PlanterRaised) => {
+                lowered = false;
+                end(current.take(), &mut out);
+            }
+            Record::Job {
+                field: f,
+                variety: v,
+                seed_lot: l,
+                spacing_in,
+                ..
+            } => {
+                if (v, l) != (&variety, &seed_lot) {
+                    end(current.take(), &mut out);
+                }
+                field = f.clone();
+                variety = v.clone();
+                seed_lot = l.clone();
+                spacing = *spacing_in;
+            }
+            Record::Control {
+                actual_tickrate, ..
+            } => tickrate = Some(*actual_tickrate),
+            Record::Fix(fix) | Record::Planter(fix)
+                if lowered && matches!(e.record, Record::Planter(_)) == planter =>
+            {
+                let pass = current.get_or_insert_with(|| PlantedPass {
+                    number: 0,
+                    started_ms: e.wall_ms,
+                    field: field.clone(),
+                    variety: variety.clone(),
+                    seed_lot: seed_lot.clone(),
+                    points: vec![],
+                });
+                pass.points.push(Point {
+                    pos: LatLon {
+                        lat: fix.latitude,
+                        lon: fix.longitude,
+                    },
+                    speed_mph: fix.speed_mph,
+                    target_spacing_in: spacing,
+                    actual_spacing_in: tickrate.and_then(|t| tickrate_to_spacing(t, fix.speed_mph)),
+                });
+            }
+            _ => {}
+        }
+    }
+    end(current, &mut out);
+    out
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Shape {
+    // the path of the planter
+    Line,
+    // the area covered, swath width wide
+    Swath(f64),
+}
+
+// clockwise for swaths
+fn geometry(pass: &PlantedPass, shape: Shape) -> Vec<LatLon> {
+    let line: Vec<LatLon> = pass.points.iter().map(|p| p.pos).collect();
+    match shape {
+        Shape::Line => line,
+        Shape::Swath(width) => {
+            let proj = LocalProjection::new(line[0]);
+            let local: Vec<_> = line.iter().map(|p| proj.to_local(*p)).collect();
+            geo::swath(&local, width)
+                .into_iter()
+                .map(|p| proj.to_geo(p))
+                .collect()
+        }
+    }
+}
+
+fn round(v: f32) -> f64 {
+    (v as f64 * 100.0).round() / 100.0
+}
+
+pub fn write_geojson(passes: &[PlantedPass], shape: Shape, out: &mut impl Write) -> io::Result<()> {
+    let features: Vec<_> = passes
+        .iter()
+        .map(|pass| {
+            let mut coords: Vec<_> = geometry(pass, shape)
+                .iter()
+                .map(|p| json!([p.lon, p.lat]))
+                .collect();
+            let geometry = match shape {
+                Shape::Line => json!({"type": "LineString", "coordinates": coords}),
+                Shape::Swath(_) => {
+                    // RFC 7946 outer rings are counter clockwise
+                    coords.reverse();
+                    json!({"type": "Polygon", "coordinates": [coords]})
+                }
+            };
+            json!({
+                "type": "Feature",
+                "geometry": geometry,
+                "properties": {
+                    "pass": pass.number,
+                    "started": pass.started(),
+                    "field": pass.field,
+                    "variety": pass.variety,
+                    "seed_lot": pass.seed_lot,
+                    "target_spacing_in": round(pass.target_spacing_in()),
+                    "actual_spacing_in": pass.actual_spacing_in().map(round),
+                    "speed_mph": round(pass.speed_mph()),
+                },
+            })
+        })
+        .collect();
+    let collection = json!({"type": "FeatureCollection", "features": features});
+    serde_json::to_writer_pretty(&mut *out, &collection)?;
+    writeln!(out)
+}
+
+pub(crate) fn xml_escape(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+pub fn write_kml(passes: &[PlantedPass], shape: Shape, out: &mut impl Write) -> io::Result<()> {
+    writeln!(out, r#"<?xml version="1.0" encoding="UTF-8"?>"#)?;
+    writeln!(out, r#"<kml xmlns="http://www.opengis.net/kml/2.2">"#)?;
+    writeln!(out, "<Document><name>As planted</name>")?;
+    for pass in passes {
+        let mut ring = geometry(pass, shape);
+        // KML outer rings are counter clockwise
+        if matches!(shape, Shape::Swath(_)) {
+            ring.reverse();
+        }
+        let coords: Vec<String> = ring
+            .iter()
+            .map(|p| format!("{},{},0", p.lon, p.lat))
+            .collect();
+        let coords = coords.join(" ");
+        writeln!(out, "<Placemark><name>Pass {}</name>", pass.number)?;
+        writeln!(out, "<ExtendedData>")?;
+        let data = [
+            ("started", pass.started()),
+            ("field", pass.field.clone().unwrap_or_default()),
+            ("variety", pass.variety.clone().unwrap_or_default()),
+            ("seed_lot", pass.seed_lot.clone().unwrap_or_default()),
+            (
+                "target_spacing_in",
+                format!("{:.2}", pass.target_spacing_in()),
+            ),
+            (
+                "actual_spacing_in",
+                pass.actual_spacing_in()
+                    .map(|s| format!("{s:.2}"))
+                    .unwrap_or_default(),
+            ),
+            ("speed_mph", format!("{:.2}", pass.speed_mph())),
+        ];
+        for (name, value) in data {
+            writeln!(
+                out,
+                r#"<Data name="{name}"><value>{}</value></Data>"#,
+                xml_escape(&value)
+            )?;
+        }
+        writeln!(out, "</ExtendedData>")?;
+        let (open, close) = match shape {
+            Shape::Line => ("<LineString><tessellate>1</tessellate>", "</LineString>"),
+            Shape::Swath(_) => (
+                "<Polygon><outerBoundaryIs><LinearRing>",
+                "</LinearRing></outerBoundaryIs></Polygon>",
+            ),
+        };
+        writeln!(out, "{open}<coordinates>{coords}</coordinates>{close}")?;
+        writeln!(out, "</Placemark>")?;
+    }
+    writeln!(out, "</Document></kml>")
+}
+
+const WGS84_PRJ: &str = r#"GEOGCS["GCS_WGS_1984",DATUM["D_WGS_1984",SPHEROID["WGS_1984",6378137.0,298.257223563]],PRIMEM["Greenwich",0.0],UNIT["Degree",0.0174532925199433]]"#;
+
+// dBase field name, type, width, decimals
+const DBF_FIELDS: [(&str, u8, u8, u8); 7] = [
+    ("PASS", b'N', 6, 0),
+    ("STARTED", b'C', 19, 0),
+    ("VARIETY", b'C', 32, 0),
+    ("SEED_LOT", b'C', 16, 0),
+    ("TARGET_SP", b'N', 8, 2),
+    ("ACTUAL_SP", b'N', 8, 2),
+    ("SPEED_MPH", b'N', 8, 2),
+];
+
+/// Writes base.shp, .shx, .dbf and .prj in WGS 84
+pub fn write_shapefile(passes: &[PlantedPass], shape: Shape, base: &Path) -> io::Result<()> {
+    let shape_type: i32 = match shape {
+        Shape::Line => 3,
+        Shape::Swath(_) => 5,
+    };
+    let geometries: Vec<Vec<LatLon>> = passes.iter().map(|p| geometry(p, shape)).collect();
+
+    let bbox = |points: &mut dyn Iterator<Item = &LatLon>| {
+        points.fold(
+            [f64::MAX, f64::MAX, f64::MIN, f64::MIN],
+            |[x0, y0, x1, y1], p| [x0.min(p.lon), y0.min(p.lat), x1.max(p.lon), y1.max(p.lat)],
+        )
+    };
+
+    // record contents, polyline and polygon share a layout
+    let records: Vec<Vec<u8>> = geometries
+        .iter()
+        .map(|g| {
+            let mut r = vec![];
+            r.extend(shape_type.to_le_bytes());
+            for v in bbox(&mut g.iter()) {
+                r.extend(v.to_le_bytes());
+            }
+            r.extend(1i32.to_le_bytes());
+            r.extend((g.len() as i32).to_le_bytes());
+            r.extend(0i32.to_le_bytes());
+            for p in g {
+                r.extend(p.lon.to_le_bytes());
+                r.extend(p.lat.to_le_bytes());
+            }
+            r
+        })
+        .collect();
+
+    let all = if geometries.is_empty() {
+        [0.0; 4]
+    } else {
+        bbox(&mut geometries.iter().flatten())
+    };
+    let header = |file_words: usize| {
+        let mut h = vec![0u8; 100];
+        h[0..4].copy_from_slice(&9994i32.to_be_bytes());
+        h[24..28].copy_from_slice(&(file_words as i32).to_be_bytes());
+        h[28..32].copy_from_slice(&1000i32.to_le_bytes());
+        h[32..36].copy_from_slice(&shape_type.to_le_bytes());
+        for (i, v) in all.iter().enumerate() {
+            h[36 + i * 8..44 + i * 8].copy_from_slice(&v.to_le_bytes());
+        }
+        h
+    };
+
+    // lengths and offsets are in 16 bit words
+    let shp_words = 50 + records.iter().map(|r| 4 + r.len() / 2).sum::<usize>();
+    let mut shp = BufWriter::new(File::create(base.with_extension("shp"))?);
+    let mut shx = BufWriter::new(File::create(base.with_extension("shx"))?);
+    shp.write_all(&header(shp_words))?;
+    shx.write_all(&header(50 + 4 * records.len()))?;
+    let mut offset = 50;
+    for (i, r) in records.iter().enumerate() {
+        let words = (r.len() / 2) as i32;
+        shp.write_all(&(i as i32 + 1).to_be_bytes())?;
+        shp.write_all(&words.to_be_bytes())?;
+        shp.write_all(r)?;
+        shx.write_all(&(offset as i32).to_be_bytes())?;
+        shx.write_all(&words.to_be_bytes())?;
+        offset += 4 + r.len() / 2;
+    }
+    shp.flush()?;
+    shx.flush()?;
+
+    let mut dbf = BufWriter::new(File::create(base.with_extension("dbf"))?);
+    write_dbf(passes, &mut dbf)?;
+    dbf.flush()?;
+
+    std::fs::write(base.with_extension("prj"), WGS84_PRJ)
+}
+
+fn write_dbf(passes: &[PlantedPass], out: &mut impl Write) -> io::Result<()> {
+    let header_len = 32 + 32 * DBF_FIELDS.len() + 1;
+    let record_len = 1 + DBF_FIELDS.iter().map(|f| f.2 as usize).sum::<usize>();
+    let today = Local::now();
+    let mut h = vec![0u8; 32];
+    h[0] = 0x03;
+    h[1] = (chrono::Datelike::year(&today) - 1900) as u8;
+    h[2] = chrono::Datelike::month(&today) as u8;
+    h[3] = chrono::Datelike::day(&today) as u8;
+    h[4..8].copy_from_slice(&(passes.len() as u32).to_le_bytes());
+    h[8..10].copy_from_slice(&(header_len as u16).to_le_bytes());
+    h[10..12].copy_from_slice(&(record_len as u16).to_le_bytes());
+    out.write_all(&h)?;
+    for (name, kind, width, decimals) in DBF_FIELDS {
+        let mut f = [0u8; 32];
+        f[..name.len()].copy_from_slice(name.as_bytes());
+        f[11] = kind;
+        f[16] = width;
+        f[17] = decimals;
+        out.write_all(&f)?;
+    }
+    out.write_all(&[0x0d])?;
+
+    for pass in passes {
+        let values = [
+            Some(pass.number.to_string()),
+            Some(pass.started()),
+            pass.variety.clone(),
+            pass.seed_lot.clone(),
+            Some(format!("{:.2}", pass.target_spacing_in())),
+            pass.actual_spacing_in().map(|s| format!("{s:.2}")),
+            Some(format!("{:.2}", pass.speed_mph())),
+        ];
+        out.write_all(b" ")?;
+        for ((_, kind, width, _), value) in DBF_FIELDS.iter().zip(values) {
+            let width = *width as usize;
+            let mut v: String = value.unwrap_or_default().chars().take(width).collect();
+            v = if *kind == b'N' {
+                format!("{v:>width$}")
+            } else {
+                format!("{v:<width$}")
+            };
+            // non ascii would break the fixed width
+            let bytes: Vec<u8> = v.bytes().map(|b| if b < 128 { b } else { b'?' }).collect();
+            out.write_all(&bytes[..width.min(bytes.len())])?;
+            out.write_all(&vec![b' '; width.saturating_sub(bytes.len())])?;
+        }
+    }
+    out.write_all(&[0x1a])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::gps::Fix;
+    use crate::util::TempDir;
+
+    fn entry(wall_ms: u64, record: Record) -> Entry {
+        Entry {
+            mono_ms: wall_ms,
+            wall_ms,
+            record,
+        }
+    }
+
+    fn fix(i: u64) -> Record {
+        Record::Fix(Fix {
+            latitude: 46.8 + i as f64 * 0.0001,
+            longitude: -100.7,
+            speed_mph: 4.0,
+            heading: Some(0.0),
+        })
+    }
+
+    fn job(variety: &str) -> Record {
+        Record::Job {
+            field: Some("North 40".into()),
+            field_id: None,
+            variety: Some(variety.into()),
+            seed_lot: None,
+            spacing_in: 10.0,
+        }
+    }
+
+    #[test]
+    fn session_to_passes() {
+        let entries = vec![
+            entry(0, job("Russet")),
+            entry(1, fix(0)),
+            entry(2, Record::Event(Event::PlanterLowered)),
+            entry(3, fix(1)),
+            entry(4, fix(2)),
+            entry(5, fix(3)),
+            entry(6, Record::Event(Event::PlanterRaised)),
+            entry(7, fix(4)),
+            entry(8, Record::Event(Event::PlanterLowered)),
+            entry(9, fix(5)),
+            entry(10, job("Norland")),
+            entry(11, fix(6)),
+            entry(12, fix(7)),
+        ];
+        let passes = passes(&entries);
+        assert_eq!(passes.len(), 2);
+        assert_eq!(passes[0].points.len(), 3);
+        assert_eq!(passes[0].variety.as_deref(), Some("Russet"));
+        assert_eq!(passes[0].target_spacing_in(), 10.0);
+        assert_eq!(passes[0].actual_spacing_in(), None);
+        // the single fix before the variety change is not a pass
+        assert_eq!(passes[1].number, 2);
+        assert_eq!(passes[1].variety.as_deref(), Some("Norland"));
+
+        let mut out = vec![];
+        write_geojson(&passes, Shape::Swath(1.7), &mut out).unwrap();
+        let v: serde_json::Value = serde_json::from_slice(&out).unwrap();
+        let ring = &v["features"][0]["geometry"]["coordinates"][0];
+        assert_eq!(ring.as_array().unwrap().len(), 7);
+        assert_eq!(v["features"][1]["properties"]["variety"], "Norland");
+    }
+
+    #[test]
+    fn kml_rings_run_counter_clockwise() {
+        let entries: Vec<_> = std::iter::once(entry(0, Record::Event(Event::PlanterLowered)))
+            .chain((1..=4).map(|i| entry(i, fix(i))))
+            .collect();
+        let mut out = vec![];
+        write_kml(&passes(&entries), Shape::Swath(1.7), &mut out).unwrap();
+        let kml = String::from_utf8(out).unwrap();
+        let coords = kml
+            .split("<coordinates>")
+            .nth(1)
+            .and_then(|s| s.split("</coordinates>").next())
+            .unwrap();
+        let proj = LocalProjection::new(LatLon {
+            lat: 46.8,
+            lon: -100.7,
+        });
+        let ring: Vec<Xy> = coords
+            .split(' ')
+            .map(|c| {
+                let mut v = c.split(',').map(|v| v.parse::<f64>().unwrap());
+                let (lon, lat) = (v.next().unwrap(), v.next().unwrap());
+                proj.to_local(LatLon { lat, lon })
+            })
+            .collect();
+        assert!(geo::signed_area(&ring) > 0.0);
+    }
+
+    #[test]
+    fn standing_still_is_not_a_pass() {
+        let still = Record::Fix(Fix {
+            latitude: 46.8,
+            longitude: -100.7,
+            speed_mph: 0.0,
+            heading: None,
+        });
+        let entries = vec![
+            entry(0, Record::Event(Event::PlanterLowered)),
+            entry(1, still.clone()),
+            entry(2, still.clone()),
+            entry(3, still),
+            entry(4, Record::Event(Event::PlanterRaised)),
+            entry(5, Record::Event(Event::PlanterLowered)),
+            entry(6, fix(1)),
+            entry(7, fix(2)),
+        ];
+        let passes = passes(&entries);
+        assert_eq!(passes.len(), 1);
+        assert_eq!(passes[0].number, 1);
+        assert_eq!(passes[0].points.len(), 2);
+    }
+
+    #[test]
+    fn shapefile_layout() {
+        let entries: Vec<_> = std::iter::once(entry(0, Record::Event(Event::PlanterLowered)))
+            .chain((1..=4).map(|i| entry(i, fix(i))))
+            .collect();
+        let passes = passes(&entries);
+
+        let dir = TempDir::new("shp");
+        let base = dir.path().join("asplanted");
+        write_shapefile(&passes, Shape::Line, &base).unwrap();
+
+        let shp = std::fs::read(base.with_extension("shp")).unwrap();
+        // header, record header, type + bbox + counts + one part + 4 points
+        let expected = 100 + 8 + 4 + 32 + 8 + 4 + 4 * 16;
+        assert_eq!(shp.len(), expected);
+        assert_eq!(
+            i32::from_be_bytes(shp[24..28].try_into().unwrap()) as usize * 2,
+            expected
+        );
+        let shx = std::fs::read(base.with_extension("shx")).unwrap();
+        assert_eq!(shx.len(), 108);
+        let dbf = std::fs::read(base.with_extension("dbf")).unwrap();
+        let header_len = 32 + 32 * DBF_FIELDS.len() + 1;
+        let record_len = u16::from_le_bytes(dbf[10..12].try_into().unwrap()) as usize;
+        assert_eq!(dbf.len(), header_len + record_len + 1);
+    }
+}
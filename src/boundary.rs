@@ -0,0 +1,476 @@
+use crate::geo::{signed_area, Boundary, LatLon, Polygon, Xy};
+use serde_json::{json, Value};
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+fn invalid(msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg.to_string())
+}
+
+/// Reads a boundary from GeoJSON, KML or a polygon Shapefile, every polygon
+/// in the file becomes part of the one boundary
+pub fn read_boundary(path: &Path) -> io::Result<Boundary> {
+    let ext = path
+        .extension()
+        .and_then(|e| e.to_str())
+        .unwrap_or_default()
+        .to_lowercase();
+    let boundary = match ext.as_str() {
+        "geojson" | "json" => from_geojson(&serde_json::from_slice(&fs::read(path)?)?),
+        "kml" => from_kml(&fs::read_to_string(path)?),
+        "shp" => from_shp(&fs::read(path)?),
+        _ => return Err(invalid("expected .geojson, .kml or .shp")),
+    };
+    if boundary.polygons.is_empty() {
+        return Err(invalid("no polygons found"));
+    }
+    Ok(boundary)
+}
+
+fn ring_from_json(ring: &Value) -> Vec<LatLon> {
+    ring.as_array()
+        .map(|points| {
+            points
+                .iter()
+                .filter_map(|p| {
+                    Some(LatLon {
+                        lon: p.get(0)?.as_f64()?,
+                        lat: p.get(1)?.as_f64()?,
+                    })
+                })
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+fn polygon_from_json(rings: &Value) -> Option<Polygon> {
+    let mut rings = rings.as_array()?.iter().map(ring_from_json);
+    Some(Polygon {
+        outer: rings.next()?,
+        holes: rings.collect(),
+    })
+}
+
+// geometries, features and feature collections
+pub fn from_geojson(v: &Value) -> Boundary {
+    let mut polygons = vec![];
+    match v["type"].as_str() {
+        Some("FeatureCollection") => {
+            for f in v["features"].as_array().into_iter().flatten() {
+                polygons.extend(from_geojson(f).polygons);
+            }
+        }
+        Some("Feature") => polygons.extend(from_geojson(&v["geometry"]).polygons),
+        Some("Polygon") => polygons.extend(polygon_from_json(&v["coordinates"])),
+        Some("MultiPolygon") => {
+            for p in v["coordinates"].as_array().into_iter().flatten() {
+                polygons.extend(polygon_from_json(p));
+            }
+        }
+        _ => {}
+    }
+    Boundary { polygons }
+}
+
+pub fn to_geojson(b: &Boundary) -> Value {
+    let ring =
+        |r: &Vec<LatLon>| -> Vec<Value> { r.iter().map(|p| json!([p.lon, p.lat])).collect() };
+    let polygons: Vec<Value> = b
+        .polygons
+        .iter()
+        .map(|p| {
+            let rings: Vec<Value> = std::iter::once(&p.outer)
+                .chain(&p.holes)
+                .map(|r| Value::from(ring(r)))
+                .collect();
+            Value::from(rings)
+        })
+        .collect();
+    json!({"type": "MultiPolygon", "coordinates": polygons})
+}
+
+// text between each open and close tag
+fn tag_contents<'a>(s: &'a str, open: &str, close: &str) -> Vec<&'a str> {
+    let mut out = vec![];
+    let mut rest = s;
+    while let Some(start) = rest.find(open) {
+        let after = &rest[start + open.len()..];
+        // skip the attributes of the opening tag
+        let Some(gt) = after.find('>') else {
+            break;
+        };
+        let body = &after[gt + 1..];
+        let Some(end) = body.find(close) else {
+            break;
+        };
+        out.push(&body[..end]);
+        rest = &body[end + close.len()..];
+    }
+    out
+}
+
+fn kml_coordinates(s: &str) -> Vec<LatLon> {
+    tag_contents(s, "<coordinates", "</coordinates>")
+        .first()
+        .map(|c| {
+            c.split_whitespace()
+                .filter_map(|t| {
+                    let mut v = t.split(',').map(|n| n.parse::<f64>());
+                    Some(LatLon {
+                        lon: v.next()?.ok()?,
+                        lat: v.next()?.ok()?,
+                    })
+                })
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+pub fn from_kml(s: &str) -> Boundary {
+    let polygons = tag_contents(s, "<Polygon", "</Polygon>")
+        .into_iter()
+        .filter_map(|p| {
+            let outer = tag_contents(p, "<outerBoundaryIs", "</outerBoundaryIs>");
+            Some(Polygon {
+                outer: kml_coordinates(outer.first()?),
+                holes: tag_contents(p, "<innerBoundaryIs", "</innerBoundaryIs>")
+                    .into_iter()
+                    .map(kml_coordinates)
+                    .collect(),
+            })
+        })
+        .collect();
+    Boundary { polygons }
+}
+
+// polygon, polygon z and polygon m records, clockwise rings are outer and
+// counter clockwise rings are holes in the outer ring before them
+pub fn from_shp(buf: &[u8]) -> Boundary {
+    Boundary {
+        polygons: shp_records(buf).into_iter().flatten().collect(),
+    }
+}
+
+/// Polygons of each record in file order, other shape types come back empty so
+/// records line up with the dBase rows
+pub fn shp_records(buf: &[u8]) -> Vec<Vec<Polygon>> {
+    let i32_le = |at: usize| {
+        buf.get(at..at + 4)
+            .map(|b| i32::from_le_bytes(b.try_into().unwrap()))
+    };
+    let i32_be = |at: usize| {
+        buf.get(at..at + 4)
+            .map(|b| i32::from_be_bytes(b.try_into().unwrap()))
+    };
+    let f64_le = |at: usize| {
+        buf.get(at..at + 8)
+            .map(|b| f64::from_le_bytes(b.try_into().unwrap()))
+    };
+
+    let mut records = vec![];
+    let mut pos = 100;
+    while let (Some(_), Some(words)) = (i32_be(pos), i32_be(pos + 4)) {
+        let content = pos + 8;
+        // a record running past the end of the file is as good as truncated
+        let end = usize::try_from(words)
+            .ok()
+            .and_then(|w| w.checked_mul(2))
+            .and_then(|len| content.checked_add(len))
+            .filter(|end| *end <= buf.len());
+        let Some(end) = end else {
+            break;
+        };
+        pos = end;
+        let mut polygons: Vec<Polygon> = vec![];
+        if !matches!(i32_le(content), Some(5 | 15 | 25)) {
+            records.push(polygons);
+            continue;
+        }
+        let (Some(num_parts), Some(num_points)) = (i32_le(content + 36), i32_le(content + 40))
+        else {
+            break;
+        };
+        // the part index and the points have to fit in the record
+        let sizes = (usize::try_from(num_parts).ok())
+            .zip(usize::try_from(num_points).ok())
+            .and_then(|(num_parts, num_points)| {
+                let points_at = num_parts.checked_mul(4)?.checked_add(content + 44)?;
+                let points_end = num_points.checked_mul(16)?.checked_add(points_at)?;
+                (points_end <= end).then_some((num_parts, num_points, points_at))
+            });
+        let Some((num_parts, num_points, points_at)) = sizes else {
+            records.push(polygons);
+            continue;
+        };
+        let parts: Vec<usize> = (0..num_parts)
+            .filter_map(|i| i32_le(content + 44 + i * 4))
+            .filter_map(|p| usize::try_from(p).ok().filter(|p| *p <= num_points))
+            .chain(std::iter::once(num_points))
+            .collect();
+        for w in parts.windows(2) {
+            let ring: Vec<LatLon> = (w[0]..w[1])
+                .filter_map(|i| {
+                    Some(LatLon {
+                        lon: f64_le(points_at + i * 16)?,
+                        lat: f64_le(points_at + i * 16 + 8)?,
+                    })
+                })
+                .collect();
+            let xy: Vec<Xy> = ring.iter().map(|p| Xy { x: p.lon, y: p.lat }).collect();
+            match polygons.last_mut() {
+                Some(outer) if signed_area(&xy) > 0.0 => outer.holes.push(ring),
+                _ => polygons.push(Polygon {
+                    outer: ring,
+                    holes: vec![],
+                }),
+            }
+        }
+        records.push(polygons);
+    }
+    records
+}
+
+/// Rows of a dBase table by field name, deleted rows come back empty
+pub fn read_dbf(buf: &[u8]) -> Vec<HashMap<String, String>> {
+    let u16_le = |at: usize| {
+        buf.get(at..at + 2)
+            .map_or(0, |b| u16::from_le_bytes([b[0], b[1]]) as usize)
+    };
+    let (header_len, record_len) = (u16_le(8), u16_le(10));
+    // a count past the end of the file is not believed
+    let count = buf
+        .get(4..8)
+        .map_or(0, |b| u32::from_le_bytes([b[0], b[1], b[2], b[3]]) as usize)
+        .min(buf.len().checked_div(record_len).unwrap_or(0));
+
+    // name and width of each field, the descriptors end with 0x0d
+    let mut fields = vec![];
+    let mut at = 32;
+    while at + 32 <= header_len {
+        let Some(desc) = buf.get(at..at + 32).filter(|d| d[0] != 0x0d) else {
+            break;
+        };
+        let name = String::from_utf8_lossy(&desc[..11]);
+        fields.push((name.trim_end_matches('\0').to_string(), desc[16] as usize));
+        at += 32;
+    }
+
+    (0..count)
+        .map(|i| {
+            let start = header_len + i * record_len;
+            let mut row = HashMap::new();
+            if buf.get(start) != Some(&b' ') {
+                return row;
+            }
+            let mut at = start + 1;
+            for (name, width) in &fields {
+                let value = buf.get(at..at + width).unwrap_or_default();
+                row.insert(
+                    name.clone(),
+                    String::from_utf8_lossy(value).trim().to_string(),
+                );
+                at += width;
+            }
+            row
+        })
+        .collect()
+}
+
+// fixes in a row that must agree before the field changes
+const FIELD_CHANGE_FIXES: u32 = 3;
+
+/// Finds the field the tractor is in from its position
+#[derive(Default)]
+pub struct FieldLocator {
+    fields: Vec<(i64, Boundary)>,
+    // the first fix is taken as it is, there is nothing yet to hold on to
+    located: bool,
+    current: Option<i64>,
+    candidate: Option<i64>,
+    count: u32,
+}
+
+impl FieldLocator {
+    pub fn new(fields: Vec<(i64, Boundary)>) -> Self {
+        FieldLocator {
+            fields,
+            ..Default::default()
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.fields.is_empty()
+    }
+
+    pub fn boundaries(&self) -> impl Iterator<Item = &Boundary> {
+        self.fields.iter().map(|(_, b)| b)
+    }
+
+    // the field only changes after a few fixes so gps jitter on the edge does not flap
+    pub fn locate(&mut self, p: LatLon) -> Option<i64> {
+        let found = self
+            .fields
+            .iter()
+            .find(|(_, b)| b.contains(p))
+            .map(|(id, _)| *id);
+        if !self.located {
+            self.located = true;
+            self.current = found;
+        } else if found == self.current {
+            self.count = 0;
+        } else if found == self.candidate {
+            self.count += 1;
+            if self.count >= FIELD_CHANGE_FIXES {
+                self.current = found;
+                self.count = 0;
+            }
+        } else {
+            self.candidate = found;
+            self.count = 1;
+        }
+        self.current
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::asplanted::{passes, write_shapefile, Shape};
+    use crate::gps::Fix;
+    use crate::io::Event;
+    use crate::log::{Entry, Record};
+    use crate::util::TempDir;
+
+    const KML: &str = r#"<kml><Placemark><Polygon>
+        <outerBoundaryIs><LinearRing><coordinates>
+          -100.0,46.0,0 -99.99,46.0,0 -99.99,46.01,0 -100.0,46.01,0 -100.0,46.0,0
+        </coordinates></LinearRing></outerBoundaryIs>
+        <innerBoundaryIs><LinearRing><coordinates>
+          -99.996,46.004 -99.994,46.004 -99.994,46.006 -99.996,46.006
+        </coordinates></LinearRing></innerBoundaryIs>
+    </Polygon></Placemark></kml>"#;
+
+    #[test]
+    fn kml_and_geojson() {
+        let b = from_kml(KML);
+        assert_eq!(b.polygons.len(), 1);
+        assert_eq!(b.polygons[0].outer.len(), 5);
+        assert_eq!(b.polygons[0].holes.len(), 1);
+        let inside = LatLon {
+            lat: 46.001,
+            lon: -99.999,
+        };
+        let in_hole = LatLon {
+            lat: 46.005,
+            lon: -99.995,
+        };
+        assert!(b.contains(inside));
+        assert!(!b.contains(in_hole));
+
+        // stored as geojson
+        let round_trip = from_geojson(&to_geojson(&b));
+        assert_eq!(round_trip, b);
+        let feature = json!({"type": "Feature", "geometry": to_geojson(&b)});
+        assert_eq!(from_geojson(&feature), b);
+    }
+
+    #[test]
+    fn shapefile_swaths() {
+        let entries: Vec<Entry> = std::iter::once(Record::Event(Event::PlanterLowered))
+            .chain((0..5).map(|i| {
+                Record::Fix(Fix {
+                    latitude: 46.0 + i as f64 * 0.0005,
+                    longitude: -100.0,
+                    speed_mph: 4.0,
+                    heading: None,
+                })
+            }))
+            .enumerate()
+            .map(|(i, record)| Entry {
+                mono_ms: i as u64,
+                wall_ms: i as u64,
+                record,
+            })
+            .collect();
+        let dir = TempDir::new("boundary");
+        let base = dir.path().join("swaths");
+        write_shapefile(&passes(&entries), Shape::Swath(10.0), &base).unwrap();
+
+        let b = read_boundary(&base.with_extension("shp")).unwrap();
+        assert_eq!(b.polygons.len(), 1);
+        let rows = read_dbf(&fs::read(base.with_extension("dbf")).unwrap());
+        assert_eq!(rows.len(), 1);
+        assert_eq!(rows[0]["PASS"], "1");
+        assert!(b.contains(LatLon {
+            lat: 46.001,
+            lon: -100.00003
+        }));
+        assert!(!b.contains(LatLon {
+            lat: 46.001,
+            lon: -100.001
+        }));
+    }
+
+    #[test]
+    fn shp_out_of_bounds() {
+        let record = |words: i32, parts: i32, points: i32| {
+            let mut buf = vec![0u8; 100];
+            buf.extend(1i32.to_be_bytes());
+            buf.extend(words.to_be_bytes());
+            buf.extend(5i32.to_le_bytes());
+            buf.extend([0; 32]);
+            buf.extend(parts.to_le_bytes());
+            buf.extend(points.to_le_bytes());
+            buf.extend(0i32.to_le_bytes());
+            buf
+        };
+        // a length past the end, or negative
+        assert!(shp_records(&record(1000, 1, 0)).is_empty());
+        assert!(shp_records(&record(-2, 1, 0)).is_empty());
+        // counts that do not fit the record leave it empty
+        assert_eq!(shp_records(&record(24, i32::MAX, 0)), vec![vec![]]);
+        assert_eq!(shp_records(&record(24, 1, -1)), vec![vec![]]);
+        assert_eq!(shp_records(&record(24, 1, 1000)), vec![vec![]]);
+        // a single part without points fits
+        assert_eq!(shp_records(&record(24, 1, 0)).len(), 1);
+    }
+
+    #[test]
+    fn dbf_out_of_bounds() {
+        assert!(read_dbf(&[]).is_empty());
+        // a huge record count, a header that runs past the end, no descriptor terminator
+        let mut buf = vec![0u8; 40];
+        buf[4..8].copy_from_slice(&u32::MAX.to_le_bytes());
+        buf[8..10].copy_from_slice(&200u16.to_le_bytes());
+        buf[10..12].copy_from_slice(&4u16.to_le_bytes());
+        let rows = read_dbf(&buf);
+        assert_eq!(rows.len(), 10);
+        assert!(rows.iter().all(|r| r.is_empty()));
+        // a zero record length
+        buf[10..12].copy_from_slice(&0u16.to_le_bytes());
+        assert!(read_dbf(&buf).is_empty());
+    }
+
+    #[test]
+    fn locator_hysteresis() {
+        let mut loc = FieldLocator::new(vec![(7, from_kml(KML))]);
+        let inside = LatLon {
+            lat: 46.001,
+            lon: -99.999,
+        };
+        let outside = LatLon {
+            lat: 46.02,
+            lon: -99.999,
+        };
+        // in the field from the first fix
+        assert_eq!(loc.locate(inside), Some(7));
+        assert_eq!(loc.locate(outside), Some(7));
+        assert_eq!(loc.locate(inside), Some(7));
+        for _ in 0..3 {
+            loc.locate(outside);
+        }
+        assert_eq!(loc.locate(outside), None);
+    }
+}
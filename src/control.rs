@@ -0,0 +1,178 @@
+use crate::diagnostics::Diagnostics;
+use crate::flowmap::FlowMap;
+use crate::io::Cmd;
+use crate::util::{fps_to_sps, mph_to_fps, sps_to_tickrate, TickRate};
+use crate::valve::{Homing, Valve};
+use std::time::{Duration, Instant};
+
+pub struct FlowCfg {
+    // throttle applied for each correction pulse
+    pub throttle_rate: f32,
+    pub throttle_time: Duration,
+    // tick rate error that is tolerated without correction
+    pub deadband: TickRate,
+}
+
+impl Default for FlowCfg {
+    fn default() -> Self {
+        FlowCfg {
+            throttle_rate: 1.0,
+            throttle_time: Duration::from_millis(50),
+            // about 1 rpm, finer than the 50 ms pulses can correct without hunting
+            deadband: 6,
+        }
+    }
+}
+
+/// Seed wheel speed control, pulses the flow valve toward the tick rate
+/// required for the target spacing at the current ground speed
+pub struct FlowController {
+    pub cfg: FlowCfg,
+    in_between_seed: f32,
+}
+
+impl Default for FlowController {
+    fn default() -> Self {
+        FlowController::new(FlowCfg::default(), 10.0)
+    }
+}
+
+impl FlowController {
+    pub fn new(cfg: FlowCfg, in_between_seed: f32) -> Self {
+        FlowController {
+            cfg,
+            in_between_seed,
+        }
+    }
+
+    pub fn spacing(&self) -> f32 {
+        self.in_between_seed
+    }
+
+    pub fn set_spacing(&mut self, in_between_seed: f32) {
+        self.in_between_seed = in_between_seed;
+    }
+
+    pub fn target_tickrate(&self, mph: f32) -> TickRate {
+        sps_to_tickrate(fps_to_sps(mph_to_fps(mph), self.in_between_seed))
+    }
+
+    pub fn update(&mut self, drive: usize, mph: f32, tickrate: TickRate) -> Option<Cmd> {
+        let target = self.target_tickrate(mph);
+        match tickrate {
+            tps if tps + self.cfg.deadband < target => Some(Cmd::FlowPulse(
+                drive,
+                self.cfg.throttle_rate,
+                self.cfg.throttle_time,
+            )),
+            tps if tps > target + self.cfg.deadband => Some(Cmd::FlowPulse(
+                drive,
+                -self.cfg.throttle_rate,
+                self.cfg.throttle_time,
+            )),
+            _ => None,
+        }
+    }
+}
+
+/// Flow actuator turning the seed wheels of some rows, with its own encoder and controller
+#[derive(Default)]
+pub struct Drive {
+    pub rows: Vec<usize>,
+    pub controller: FlowController,
+    pub tick_meter: TickMeter,
+    pub seed_wheel_speed_rpm: f32,
+    pub valve: Valve,
+    // raw position pot reading, if the drive has one
+    pub pot: Option<f32>,
+    pub homing: Option<Homing>,
+    pub flow_map: FlowMap,
+    // last time the valve was moved, the wheel speed is learned once it holds
+    pub moved_at: Option<Instant>,
+    // throttle of the last pulse and when it ends
+    pub pulse: Option<(f32, Instant)>,
+    // target speed the valve was last sent straight to, error control waits for it to settle
+    pub feed_forward_rpm: Option<f32>,
+    pub settle_until: Option<Instant>,
+    pub diagnostics: Diagnostics,
+}
+
+impl Drive {
+    pub fn new(rows: Vec<usize>) -> Self {
+        Drive {
+            rows,
+            ..Default::default()
+        }
+    }
+
+    // the next command cuts a pulse short, the valve model gives back the part not made
+    pub fn cut_short(&mut self, now: Instant) {
+        if let Some((rate, until)) = self.pulse.take() {
+            if until > now {
+                self.valve.moved(-rate, until - now);
+                self.moved_at = Some(now);
+            }
+        }
+    }
+}
+
+/// Aggregates encoder ticks into a tick per second measurement
+#[derive(Default)]
+pub struct TickMeter {
+    count: usize,
+    since: Option<Instant>,
+    rate: TickRate,
+}
+
+impl TickMeter {
+    pub fn tick(&mut self) {
+        self.count += 1;
+    }
+
+    pub fn rate(&self) -> TickRate {
+        self.rate
+    }
+
+    // reports the rate once per second while the encoder is turning,
+    // and once more when it stops
+    pub fn update(&mut self, now: Instant) -> Option<TickRate> {
+        let since = *self.since.get_or_insert(now);
+        let elapsed = now.saturating_duration_since(since);
+        if elapsed < Duration::from_secs(1) {
+            return None;
+        }
+
+        let rate = (self.count as f32 / elapsed.as_secs_f32()) as TickRate;
+        let report = self.count > 0 || self.rate > 0;
+        self.rate = rate;
+        self.count = 0;
+        self.since = Some(now);
+        report.then_some(rate)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn pulses_outside_the_deadband() {
+        let mut c = FlowController::default();
+        // 3 mph at 10 in is 5.3 seeds/s
+        let target = c.target_tickrate(3.0);
+        assert_eq!(target, 74);
+        let pulse = |c: &mut FlowController, tickrate| match c.update(0, 3.0, tickrate) {
+            Some(Cmd::FlowPulse(0, rate, _)) => Some(rate),
+            _ => None,
+        };
+        assert_eq!(pulse(&mut c, target), None);
+        assert_eq!(pulse(&mut c, target - 6), None);
+        assert_eq!(pulse(&mut c, target + 6), None);
+        assert_eq!(pulse(&mut c, target - 7), Some(1.0));
+        assert_eq!(pulse(&mut c, target + 7), Some(-1.0));
+
+        // closer spacing wants the wheel faster
+        c.set_spacing(5.0);
+        assert!(c.target_tickrate(3.0) > target);
+    }
+}
@@ -0,0 +1,414 @@
+use crate::geo::{dist_to_segment, LatLon, LocalProjection, Xy};
+use crate::gps::Fix;
+use crate::util;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+// closer fixes than this add nothing to the map, metres
+const MIN_STEP_M: f64 = 0.5;
+
+// finished passes are simplified to within this of the path driven, metres
+const SIMPLIFY_M: f64 = 0.1;
+
+// side of the grid squares the planted segments are indexed by, metres
+const CELL_M: f64 = 10.0;
+
+type Cell = (i64, i64);
+
+fn cell(p: Xy) -> Cell {
+    ((p.x / CELL_M).floor() as i64, (p.y / CELL_M).floor() as i64)
+}
+
+// the cells under the box with corners a and b
+fn cells(a: Xy, b: Xy) -> impl Iterator<Item = Cell> {
+    let (x0, y0) = cell(Xy {
+        x: a.x.min(b.x),
+        y: a.y.min(b.y),
+    });
+    let (x1, y1) = cell(Xy {
+        x: a.x.max(b.x),
+        y: a.y.max(b.y),
+    });
+    (x0..=x1).flat_map(move |x| (y0..=y1).map(move |y| (x, y)))
+}
+
+/// How close the spacing in the ground is to the target
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Accuracy {
+    Good,
+    Fair,
+    Poor,
+    Unknown,
+}
+
+impl Accuracy {
+    // actual spacing over target, within 5% is good and within 15% fair
+    pub fn from_ratio(ratio: Option<f32>) -> Self {
+        match ratio.map(|r| (r - 1.0).abs()) {
+            Some(e) if e <= 0.05 => Accuracy::Good,
+            Some(e) if e <= 0.15 => Accuracy::Fair,
+            Some(_) => Accuracy::Poor,
+            None => Accuracy::Unknown,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct Sample {
+    pub at: Xy,
+    pub accuracy: Accuracy,
+}
+
+/// Planted coverage this session on a plane around the first fix, following the planter
+#[derive(Default)]
+pub struct Coverage {
+    proj: Option<LocalProjection>,
+    passes: Vec<Vec<Sample>>,
+    // segments by the cells they cross, as the pass and the index of their first sample
+    index: HashMap<Cell, Vec<(usize, usize)>>,
+    // the last pass is still being planted
+    open: bool,
+    // changes whenever a finished pass does
+    revision: u64,
+    // field the coverage was planted on
+    pub field_id: Option<i64>,
+    pub position: Option<Xy>,
+    // degrees from north
+    pub heading: Option<f32>,
+}
+
+impl Coverage {
+    pub fn projection(&self) -> Option<&LocalProjection> {
+        self.proj.as_ref()
+    }
+
+    pub fn finished(&self) -> &[Vec<Sample>] {
+        &self.passes[..self.passes.len() - usize::from(self.open)]
+    }
+
+    pub fn current(&self) -> Option<&[Sample]> {
+        self.passes.last().filter(|_| self.open).map(Vec::as_slice)
+    }
+
+    pub fn revision(&self) -> u64 {
+        self.revision
+    }
+
+    // the fix on the plane, which is set up around the first one
+    pub fn to_local(&mut self, fix: &Fix) -> Xy {
+        let p = LatLon {
+            lat: fix.latitude,
+            lon: fix.longitude,
+        };
+        self.proj
+            .get_or_insert_with(|| LocalProjection::new(p))
+            .to_local(p)
+    }
+
+    /// Moves the planter to at, and records the ground it covers while planting;
+    /// true when a pass has just been finished
+    pub fn update(
+        &mut self,
+        at: Xy,
+        heading: Option<f32>,
+        accuracy: Accuracy,
+        planting: bool,
+    ) -> bool {
+        self.position = Some(at);
+        self.heading = heading.or(self.heading);
+
+        if !planting {
+            if self.open {
+                self.finish();
+                return true;
+            }
+            return false;
+        }
+        if !self.open {
+            self.passes.push(vec![]);
+            self.open = true;
+        }
+        let n = self.passes.len() - 1;
+        let pass = &mut self.passes[n];
+        match pass.last_mut() {
+            // keep the newest accuracy for a point that has not moved
+            Some(last) if last.at.dist(&at) < MIN_STEP_M => last.accuracy = accuracy,
+            Some(last) => {
+                let from = last.at;
+                for c in cells(from, at) {
+                    self.index.entry(c).or_default().push((n, pass.len() - 1));
+                }
+                pass.push(Sample { at, accuracy });
+            }
+            None => pass.push(Sample { at, accuracy }),
+        }
+        false
+    }
+
+    // the pass is simplified and indexed again in its place
+    fn finish(&mut self) {
+        self.open = false;
+        self.revision += 1;
+        let n = self.passes.len() - 1;
+        let driven = std::mem::take(&mut self.passes[n]);
+        for w in driven.windows(2) {
+            for c in cells(w[0].at, w[1].at) {
+                if let Some(segments) = self.index.get_mut(&c) {
+                    segments.retain(|&(p, _)| p != n);
+                }
+            }
+        }
+        self.passes[n] = simplify(&driven);
+        self.index_pass(n);
+    }
+
+    fn index_pass(&mut self, n: usize) {
+        for (i, w) in self.passes[n].windows(2).enumerate() {
+            for c in cells(w[0].at, w[1].at) {
+                self.index.entry(c).or_default().push((n, i));
+            }
+        }
+    }
+
+    // within a swath of width metres of a finished pass, the pass being planted never counts
+    pub fn covered(&self, p: Xy, width: f64) -> bool {
+        let done = self.passes.len() - usize::from(self.open);
+        let r = width / 2.0;
+        let (a, b) = (
+            Xy {
+                x: p.x - r,
+                y: p.y - r,
+            },
+            Xy {
+                x: p.x + r,
+                y: p.y + r,
+            },
+        );
+        cells(a, b)
+            .filter_map(|c| self.index.get(&c))
+            .flatten()
+            .any(|&(n, i)| {
+                let pass = &self.passes[n];
+                n < done && dist_to_segment(p, pass[i].at, pass[i + 1].at) < r
+            })
+    }
+
+    pub fn clear(&mut self) {
+        self.passes.clear();
+        self.index.clear();
+        self.open = false;
+        self.revision += 1;
+    }
+}
+
+// Douglas-Peucker within each run of one accuracy, a segment takes the accuracy of its
+// end so the colours on the map stay where they were
+fn simplify(samples: &[Sample]) -> Vec<Sample> {
+    let n = samples.len();
+    if n < 3 {
+        return samples.to_vec();
+    }
+    let mut keep = vec![false; n];
+    let mut start = 0;
+    for i in 1..n {
+        if i == n - 1 || samples[i + 1].accuracy != samples[i].accuracy {
+            keep[start] = true;
+            keep[i] = true;
+            let mut spans = vec![(start, i)];
+            while let Some((a, b)) = spans.pop() {
+                let far = (a + 1..b)
+                    .map(|j| {
+                        (
+                            j,
+                            dist_to_segment(samples[j].at, samples[a].at, samples[b].at),
+                        )
+                    })
+                    .max_by(|x, y| x.1.total_cmp(&y.1));
+                if let Some((j, _)) = far.filter(|(_, d)| *d > SIMPLIFY_M) {
+                    keep[j] = true;
+                    spans.push((a, j));
+                    spans.push((j, b));
+                }
+            }
+            start = i;
+        }
+    }
+    samples
+        .iter()
+        .zip(keep)
+        .filter_map(|(s, k)| k.then_some(*s))
+        .collect()
+}
+
+// finished passes on the plane around origin
+#[derive(Serialize, Deserialize)]
+struct Saved {
+    field_id: Option<i64>,
+    origin: Option<LatLon>,
+    passes: Vec<Vec<Sample>>,
+}
+
+pub fn default_path() -> PathBuf {
+    util::data_dir().join("coverage.json")
+}
+
+// a missing or unreadable file starts from scratch
+pub fn load(path: &Path) -> Coverage {
+    let Some(saved) = fs::read_to_string(path)
+        .ok()
+        .and_then(|s| serde_json::from_str::<Saved>(&s).ok())
+    else {
+        return Coverage::default();
+    };
+    let mut c = Coverage {
+        proj: saved.origin.map(LocalProjection::new),
+        passes: saved.passes,
+        field_id: saved.field_id,
+        ..Coverage::default()
+    };
+    for n in 0..c.passes.len() {
+        c.index_pass(n);
+    }
+    c
+}
+
+pub fn save(path: &Path, c: &Coverage) -> io::Result<()> {
+    let saved = Saved {
+        field_id: c.field_id,
+        origin: c.proj.map(|p| p.origin()),
+        passes: c.finished().to_vec(),
+    };
+    util::atomic_write(path, serde_json::to_string(&saved)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::util::TempDir;
+
+    #[test]
+    fn passes_split_on_raise() {
+        let fix = |n: u32| Fix {
+            latitude: 46.0 + n as f64 * 0.00001,
+            longitude: -100.0,
+            speed_mph: 4.0,
+            heading: Some(0.0),
+        };
+        let mut c = Coverage::default();
+        let update = |c: &mut Coverage, n, accuracy, planting| {
+            let at = c.to_local(&fix(n));
+            c.update(at, Some(0.0), accuracy, planting);
+        };
+        update(&mut c, 0, Accuracy::Unknown, false);
+        assert!(c.finished().is_empty());
+        assert!(c.current().is_none());
+        for n in 1..4 {
+            update(&mut c, n, Accuracy::from_ratio(Some(1.02)), true);
+        }
+        // standing still, merged into the last point
+        update(&mut c, 3, Accuracy::from_ratio(Some(1.1)), true);
+        update(&mut c, 4, Accuracy::Poor, false);
+        update(&mut c, 5, Accuracy::from_ratio(Some(0.8)), true);
+
+        let passes = c.finished();
+        assert_eq!(passes.len(), 1);
+        // the change of accuracy is kept
+        assert_eq!(passes[0].len(), 3);
+        assert_eq!(passes[0][0].accuracy, Accuracy::Good);
+        assert_eq!(passes[0][2].accuracy, Accuracy::Fair);
+        assert_eq!(c.current().unwrap()[0].accuracy, Accuracy::Poor);
+        assert!((c.position.unwrap().y - 5.56).abs() < 0.01);
+
+        // only the first pass is finished
+        assert!(c.covered(Xy { x: 1.0, y: 2.0 }, 3.0));
+        assert!(!c.covered(Xy { x: 2.0, y: 2.0 }, 3.0));
+        assert!(!c.covered(Xy { x: 0.0, y: 5.56 }, 3.0));
+    }
+
+    #[test]
+    fn covered_across_cells() {
+        let mut c = Coverage::default();
+        // a long gap between fixes, then raised
+        for y in [-5.0, 95.0, 96.0] {
+            c.update(Xy { x: 0.0, y }, Some(0.0), Accuracy::Good, true);
+        }
+        c.update(Xy { x: 0.0, y: 96.0 }, Some(0.0), Accuracy::Good, false);
+
+        // beside the middle of the gap, on either side of a cell edge
+        assert!(c.covered(Xy { x: 0.9, y: 45.0 }, 2.0));
+        assert!(c.covered(Xy { x: -0.9, y: 45.0 }, 2.0));
+        assert!(!c.covered(Xy { x: 1.1, y: 45.0 }, 2.0));
+        assert!(c.covered(Xy { x: 0.0, y: 96.5 }, 2.0));
+        assert!(!c.covered(Xy { x: 0.0, y: 98.0 }, 2.0));
+
+        c.clear();
+        assert!(!c.covered(Xy { x: 0.0, y: 45.0 }, 2.0));
+    }
+
+    #[test]
+    fn finished_passes_are_simplified() {
+        let mut c = Coverage::default();
+        // a long straight run, a bend, and a stretch off the target
+        for i in 0..200 {
+            let y = i as f64;
+            let x = if i < 100 { 0.0 } else { (i - 100) as f64 };
+            let accuracy = if i < 150 {
+                Accuracy::Good
+            } else {
+                Accuracy::Poor
+            };
+            assert!(!c.update(Xy { x, y }, Some(0.0), accuracy, true));
+        }
+        let revision = c.revision();
+        assert!(c.update(Xy { x: 99.0, y: 199.0 }, Some(0.0), Accuracy::Good, false));
+        assert!(c.revision() > revision);
+
+        let pass = &c.finished()[0];
+        let at: Vec<(f64, f64)> = pass.iter().map(|s| (s.at.x, s.at.y)).collect();
+        assert_eq!(
+            at,
+            vec![(0.0, 0.0), (0.0, 100.0), (49.0, 149.0), (99.0, 199.0)]
+        );
+        assert_eq!(pass[2].accuracy, Accuracy::Good);
+        assert_eq!(pass[3].accuracy, Accuracy::Poor);
+
+        // the index follows the simplified pass
+        assert!(c.covered(Xy { x: 0.5, y: 50.0 }, 2.0));
+        assert!(c.covered(Xy { x: 75.0, y: 175.5 }, 2.0));
+        assert!(!c.covered(Xy { x: 20.0, y: 50.0 }, 2.0));
+    }
+
+    #[test]
+    fn survives_a_restart() {
+        let dir = TempDir::new("coverage");
+        let path = dir.path().join("coverage.json");
+        let mut c = Coverage {
+            field_id: Some(4),
+            ..Coverage::default()
+        };
+        let at = c.to_local(&Fix {
+            latitude: 46.0,
+            longitude: -100.0,
+            speed_mph: 4.0,
+            heading: None,
+        });
+        for y in [0.0, 10.0, 20.0] {
+            c.update(Xy { x: at.x, y }, None, Accuracy::Good, true);
+        }
+        c.update(Xy { x: 0.0, y: 20.0 }, None, Accuracy::Good, false);
+        // the pass being planted is not kept
+        c.update(Xy { x: 5.0, y: 0.0 }, None, Accuracy::Good, true);
+        c.update(Xy { x: 5.0, y: 10.0 }, None, Accuracy::Good, true);
+        save(&path, &c).unwrap();
+
+        let loaded = load(&path);
+        assert_eq!(loaded.field_id, Some(4));
+        assert_eq!(loaded.finished(), c.finished());
+        assert!(loaded.current().is_none());
+        assert!(loaded.projection().is_some());
+        assert!(loaded.covered(Xy { x: 0.5, y: 15.0 }, 2.0));
+    }
+}
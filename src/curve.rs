@@ -0,0 +1,94 @@
+use crate::monitor::ROWS;
+use crate::util::Speed;
+use std::time::{Duration, Instant};
+
+const MPH_PER_MPS: f64 = 2.23694;
+// fixes further apart than this start the turn rate over
+const MAX_GAP: Duration = Duration::from_secs(3);
+// weight of the newest heading change, gps headings are noisy
+const SMOOTHING: f32 = 0.5;
+
+pub struct CurveCfg {
+    // spacing error tolerated on a row before it is reported, 0.1 is 10%
+    pub tolerance: f32,
+}
+
+impl Default for CurveCfg {
+    fn default() -> Self {
+        CurveCfg { tolerance: 0.1 }
+    }
+}
+
+/// Turn rate of the planter, so rows on the inside of a curve are known to travel slower
+#[derive(Default)]
+pub struct Curve {
+    pub cfg: CurveCfg,
+    last: Option<(f32, Instant)>,
+    // degrees per second, positive turning right
+    rate_dps: f32,
+}
+
+impl Curve {
+    pub fn update(&mut self, heading: Option<f32>, now: Instant) {
+        let Some(heading) = heading else {
+            return;
+        };
+        if let Some((prev, at)) = self.last {
+            let dt = now.saturating_duration_since(at);
+            if dt > MAX_GAP {
+                self.rate_dps = 0.0;
+            } else if !dt.is_zero() {
+                let turned = (heading - prev + 540.0).rem_euclid(360.0) - 180.0;
+                let rate = turned / dt.as_secs_f32();
+                self.rate_dps += SMOOTHING * (rate - self.rate_dps);
+            }
+        }
+        self.last = Some((heading, now));
+    }
+
+    pub fn rate_dps(&self) -> f32 {
+        self.rate_dps
+    }
+
+    /// Ground speed of each row, offsets in metres right of the planter centreline
+    pub fn row_speeds(&self, mph: Speed, offsets_m: [f64; ROWS]) -> [Speed; ROWS] {
+        let rate = (self.rate_dps as f64).to_radians();
+        offsets_m.map(|off| (mph as f64 - rate * off * MPH_PER_MPS).max(0.0) as Speed)
+    }
+
+    pub fn clear(&mut self) {
+        self.last = None;
+        self.rate_dps = 0.0;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn inside_row_slows_in_a_turn() {
+        let mut c = Curve::default();
+        let offsets = [-0.5, 0.5];
+        let t = Instant::now();
+
+        c.update(Some(350.0), t);
+        assert_eq!(c.row_speeds(4.0, offsets), [4.0, 4.0]);
+
+        // a steady right turn through north at 10 degrees a second
+        for n in 1..10 {
+            let heading = (350.0 + 10.0 * n as f32).rem_euclid(360.0);
+            c.update(Some(heading), t + Duration::from_secs(n));
+        }
+        assert!((c.rate_dps() - 10.0).abs() < 0.1);
+        let [left, right] = c.row_speeds(4.0, offsets);
+        assert!(left > 4.0 && right < 4.0);
+        assert!((left + right - 8.0).abs() < 1e-3);
+        // 10 degrees a second is 0.1745 m/s per metre of offset
+        assert!((left - 4.0 - 0.1745 * 0.5 * 2.237).abs() < 1e-3);
+
+        // a long gap starts over
+        c.update(Some(90.0), t + Duration::from_secs(20));
+        assert_eq!(c.rate_dps(), 0.0);
+    }
+}
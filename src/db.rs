@@ -0,0 +1,645 @@
+use crate::boundary::{from_geojson, to_geojson};
+use crate::geo::Boundary;
+use crate::prescription::Prescription;
+use crate::util::{self, row_feet_to_acres_at};
+use chrono::Local;
+use rusqlite::{params, Connection, OptionalExtension, Result, Row};
+use std::path::{Path, PathBuf};
+
+const SCHEMA: &str = "
+CREATE TABLE IF NOT EXISTS farms (
+    id INTEGER PRIMARY KEY,
+    name TEXT NOT NULL UNIQUE
+);
+CREATE TABLE IF NOT EXISTS fields (
+    id INTEGER PRIMARY KEY,
+    farm_id INTEGER NOT NULL REFERENCES farms(id),
+    name TEXT NOT NULL,
+    acres REAL,
+    UNIQUE(farm_id, name)
+);
+CREATE TABLE IF NOT EXISTS jobs (
+    id INTEGER PRIMARY KEY,
+    field_id INTEGER NOT NULL REFERENCES fields(id),
+    variety TEXT,
+    spacing_in REAL NOT NULL,
+    row_width_in REAL NOT NULL,
+    row_feet REAL NOT NULL DEFAULT 0,
+    started_at TEXT,
+    ended_at TEXT
+);
+CREATE TABLE IF NOT EXISTS passes (
+    id INTEGER PRIMARY KEY,
+    job_id INTEGER NOT NULL REFERENCES jobs(id),
+    started_at TEXT NOT NULL,
+    ended_at TEXT NOT NULL,
+    row_feet REAL NOT NULL,
+    avg_speed_mph REAL NOT NULL,
+    spacing_in REAL NOT NULL
+);
+CREATE TABLE IF NOT EXISTS varieties (
+    id INTEGER PRIMARY KEY,
+    name TEXT NOT NULL UNIQUE,
+    spacing_in REAL NOT NULL,
+    piece_weight_oz REAL,
+    notes TEXT
+);
+CREATE TABLE IF NOT EXISTS seed_lots (
+    id INTEGER PRIMARY KEY,
+    variety_id INTEGER NOT NULL REFERENCES varieties(id),
+    lot TEXT NOT NULL,
+    notes TEXT,
+    UNIQUE(variety_id, lot)
+);
+CREATE TABLE IF NOT EXISTS bulk_fills (
+    id INTEGER PRIMARY KEY,
+    job_id INTEGER REFERENCES jobs(id),
+    at TEXT NOT NULL,
+    cwt REAL NOT NULL,
+    estimated_used_cwt REAL NOT NULL
+);
+CREATE TABLE IF NOT EXISTS boundaries (
+    field_id INTEGER PRIMARY KEY REFERENCES fields(id),
+    geojson TEXT NOT NULL
+);
+CREATE TABLE IF NOT EXISTS prescriptions (
+    field_id INTEGER PRIMARY KEY REFERENCES fields(id),
+    json TEXT NOT NULL
+);
+";
+
+// applied in order to databases older than the schema above, tracked by user_version
+const MIGRATIONS: &[&str] = &[
+    "ALTER TABLE jobs ADD COLUMN seed_lot TEXT",
+    "ALTER TABLE jobs ADD COLUMN seed_cwt REAL NOT NULL DEFAULT 0;
+     ALTER TABLE passes ADD COLUMN seed_cwt REAL NOT NULL DEFAULT 0",
+    "ALTER TABLE passes ADD COLUMN start_spacing_in REAL",
+];
+
+pub fn default_path() -> PathBuf {
+    util::data_dir().join("popl.db")
+}
+
+// local time, as stored in the database
+pub fn timestamp() -> String {
+    Local::now().format("%Y-%m-%dT%H:%M:%S").to_string()
+}
+
+#[derive(Debug, Clone)]
+pub struct Field {
+    pub id: i64,
+    pub farm: String,
+    pub name: String,
+    pub acres: Option<f32>,
+}
+
+#[derive(Debug, Clone)]
+pub struct Job {
+    pub id: i64,
+    pub field_id: i64,
+    pub field: String,
+    pub variety: Option<String>,
+    pub seed_lot: Option<String>,
+    pub spacing_in: f32,
+    pub row_width_in: f32,
+    pub row_feet: f32,
+    // estimated seed used
+    pub seed_cwt: f32,
+    pub started_at: Option<String>,
+    pub ended_at: Option<String>,
+}
+
+impl Job {
+    pub fn acres(&self) -> f32 {
+        row_feet_to_acres_at(self.row_feet, self.row_width_in)
+    }
+
+    pub fn cwt_per_acre(&self) -> Option<f32> {
+        let acres = self.acres();
+        (acres > 0.0).then(|| self.seed_cwt / acres)
+    }
+
+    fn from_row(r: &Row) -> Result<Job> {
+        Ok(Job {
+            id: r.get(0)?,
+            field_id: r.get(1)?,
+            field: r.get(2)?,
+            variety: r.get(3)?,
+            seed_lot: r.get(4)?,
+            spacing_in: r.get(5)?,
+            row_width_in: r.get(6)?,
+            row_feet: r.get(7)?,
+            seed_cwt: r.get(8)?,
+            started_at: r.get(9)?,
+            ended_at: r.get(10)?,
+        })
+    }
+}
+
+const JOB_COLUMNS: &str =
+    "j.id, j.field_id, f.name, j.variety, j.seed_lot, j.spacing_in, j.row_width_in,
+    j.row_feet, j.seed_cwt, j.started_at, j.ended_at FROM jobs j JOIN fields f ON f.id = j.field_id";
+
+/// Spacing and seed piece presets for a variety
+#[derive(Debug, Clone, PartialEq)]
+pub struct Variety {
+    pub id: i64,
+    pub name: String,
+    pub spacing_in: f32,
+    // expected average seed piece weight, for estimating seed use
+    pub piece_weight_oz: Option<f32>,
+    pub notes: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct SeedLot {
+    pub id: i64,
+    pub variety_id: i64,
+    pub lot: String,
+    pub notes: Option<String>,
+}
+
+/// Summary of one pass, from planter lowered to raised
+#[derive(Debug, Clone, PartialEq)]
+pub struct PassSummary {
+    pub started_at: String,
+    pub ended_at: String,
+    pub row_feet: f32,
+    pub avg_speed_mph: f32,
+    pub spacing_in: f32,
+    pub seed_cwt: f32,
+    // measured over the first feet of the pass, while the seed wheel comes up to speed
+    pub start_spacing_in: Option<f32>,
+}
+
+/// Seed loaded into the planter from the truck
+#[derive(Debug, Clone, PartialEq)]
+pub struct BulkFill {
+    pub job_id: Option<i64>,
+    pub at: String,
+    pub cwt: f32,
+    // lifetime estimate of seed used at the time of the fill
+    pub estimated_used_cwt: f32,
+}
+
+// each migration goes in with its version, so one that fails is tried again on the next open
+fn migrate(conn: &mut Connection, migrations: &[&str]) -> Result<()> {
+    let version: usize = conn.query_row("PRAGMA user_version", [], |r| r.get(0))?;
+    for (i, m) in migrations.iter().enumerate().skip(version) {
+        let tx = conn.transaction()?;
+        tx.execute_batch(m)?;
+        tx.pragma_update(None, "user_version", i + 1)?;
+        tx.commit()?;
+    }
+    Ok(())
+}
+
+/// Farms, fields and planting jobs
+pub struct Db {
+    conn: Connection,
+}
+
+impl Db {
+    pub fn open(path: &Path) -> Result<Db> {
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir).ok();
+        }
+        Db::init(Connection::open(path)?)
+    }
+
+    pub fn open_in_memory() -> Result<Db> {
+        Db::init(Connection::open_in_memory()?)
+    }
+
+    fn init(mut conn: Connection) -> Result<Db> {
+        conn.execute_batch(SCHEMA)?;
+        migrate(&mut conn, MIGRATIONS)?;
+        Ok(Db { conn })
+    }
+
+    // existing farm by name, or a new one
+    pub fn farm(&self, name: &str) -> Result<i64> {
+        self.conn.execute(
+            "INSERT OR IGNORE INTO farms (name) VALUES (?1)",
+            params![name],
+        )?;
+        self.conn
+            .query_row("SELECT id FROM farms WHERE name = ?1", params![name], |r| {
+                r.get(0)
+            })
+    }
+
+    pub fn add_field(&self, farm: &str, name: &str, acres: Option<f32>) -> Result<i64> {
+        let farm_id = self.farm(farm)?;
+        self.conn.execute(
+            "INSERT INTO fields (farm_id, name, acres) VALUES (?1, ?2, ?3)",
+            params![farm_id, name, acres],
+        )?;
+        Ok(self.conn.last_insert_rowid())
+    }
+
+    pub fn fields(&self) -> Result<Vec<Field>> {
+        let mut stmt = self.conn.prepare(
+            "SELECT f.id, farms.name, f.name, f.acres FROM fields f
+             JOIN farms ON farms.id = f.farm_id ORDER BY farms.name, f.name",
+        )?;
+        let rows = stmt.query_map([], |r| {
+            Ok(Field {
+                id: r.get(0)?,
+                farm: r.get(1)?,
+                name: r.get(2)?,
+                acres: r.get(3)?,
+            })
+        })?;
+        rows.collect()
+    }
+
+    pub fn add_job(
+        &self,
+        field_id: i64,
+        variety: Option<&str>,
+        spacing_in: f32,
+        row_width_in: f32,
+    ) -> Result<i64> {
+        self.conn.execute(
+            "INSERT INTO jobs (field_id, variety, spacing_in, row_width_in) VALUES (?1, ?2, ?3, ?4)",
+            params![field_id, variety, spacing_in, row_width_in],
+        )?;
+        Ok(self.conn.last_insert_rowid())
+    }
+
+    pub fn job(&self, id: i64) -> Result<Job> {
+        self.conn.query_row(
+            &format!("SELECT {JOB_COLUMNS} WHERE j.id = ?1"),
+            params![id],
+            Job::from_row,
+        )
+    }
+
+    // the unfinished job for a field, if there is one
+    pub fn open_job(&self, field_id: i64) -> Result<Option<Job>> {
+        self.conn
+            .query_row(
+                &format!(
+                    "SELECT {JOB_COLUMNS} WHERE j.field_id = ?1 AND j.ended_at IS NULL
+                     ORDER BY j.id DESC LIMIT 1"
+                ),
+                params![field_id],
+                Job::from_row,
+            )
+            .optional()
+    }
+
+    pub fn jobs(&self) -> Result<Vec<Job>> {
+        let mut stmt = self
+            .conn
+            .prepare(&format!("SELECT {JOB_COLUMNS} ORDER BY j.id DESC"))?;
+        let rows = stmt.query_map([], Job::from_row)?;
+        rows.collect()
+    }
+
+    // the variety and lot planted on the job, for traceability
+    pub fn set_job_variety(
+        &self,
+        id: i64,
+        variety: &str,
+        seed_lot: Option<&str>,
+        spacing_in: f32,
+    ) -> Result<()> {
+        self.conn.execute(
+            "UPDATE jobs SET variety = ?2, seed_lot = ?3, spacing_in = ?4 WHERE id = ?1",
+            params![id, variety, seed_lot, spacing_in],
+        )?;
+        Ok(())
+    }
+
+    pub fn add_variety(
+        &self,
+        name: &str,
+        spacing_in: f32,
+        piece_weight_oz: Option<f32>,
+        notes: Option<&str>,
+    ) -> Result<i64> {
+        self.conn.execute(
+            "INSERT INTO varieties (name, spacing_in, piece_weight_oz, notes) VALUES (?1, ?2, ?3, ?4)",
+            params![name, spacing_in, piece_weight_oz, notes],
+        )?;
+        Ok(self.conn.last_insert_rowid())
+    }
+
+    pub fn varieties(&self) -> Result<Vec<Variety>> {
+        let mut stmt = self.conn.prepare(
+            "SELECT id, name, spacing_in, piece_weight_oz, notes FROM varieties ORDER BY name",
+        )?;
+        let rows = stmt.query_map([], |r| {
+            Ok(Variety {
+                id: r.get(0)?,
+                name: r.get(1)?,
+                spacing_in: r.get(2)?,
+                piece_weight_oz: r.get(3)?,
+                notes: r.get(4)?,
+            })
+        })?;
+        rows.collect()
+    }
+
+    pub fn add_seed_lot(&self, variety_id: i64, lot: &str, notes: Option<&str>) -> Result<i64> {
+        self.conn.execute(
+            "INSERT INTO seed_lots (variety_id, lot, notes) VALUES (?1, ?2, ?3)",
+            params![variety_id, lot, notes],
+        )?;
+        Ok(self.conn.last_insert_rowid())
+    }
+
+    pub fn seed_lots(&self) -> Result<Vec<SeedLot>> {
+        let mut stmt = self
+            .conn
+            .prepare("SELECT id, variety_id, lot, notes FROM seed_lots ORDER BY variety_id, lot")?;
+        let rows = stmt.query_map([], |r| {
+            Ok(SeedLot {
+                id: r.get(0)?,
+                variety_id: r.get(1)?,
+                lot: r.get(2)?,
+                notes: r.get(3)?,
+            })
+        })?;
+        rows.collect()
+    }
+
+    pub fn start_job(&self, id: i64, at: &str) -> Result<()> {
+        self.conn.execute(
+            "UPDATE jobs SET started_at = ?2 WHERE id = ?1 AND started_at IS NULL",
+            params![id, at],
+        )?;
+        Ok(())
+    }
+
+    pub fn end_job(&self, id: i64, at: &str) -> Result<()> {
+        self.conn.execute(
+            "UPDATE jobs SET ended_at = ?2 WHERE id = ?1",
+            params![id, at],
+        )?;
+        Ok(())
+    }
+
+    pub fn record_pass(&self, job_id: i64, pass: &PassSummary) -> Result<()> {
+        self.conn.execute(
+            "INSERT INTO passes (job_id, started_at, ended_at, row_feet, avg_speed_mph, spacing_in, seed_cwt, start_spacing_in)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)",
+            params![
+                job_id,
+                pass.started_at,
+                pass.ended_at,
+                pass.row_feet,
+                pass.avg_speed_mph,
+                pass.spacing_in,
+                pass.seed_cwt,
+                pass.start_spacing_in
+            ],
+        )?;
+        self.conn.execute(
+            "UPDATE jobs SET row_feet = row_feet + ?2, seed_cwt = seed_cwt + ?3 WHERE id = ?1",
+            params![job_id, pass.row_feet, pass.seed_cwt],
+        )?;
+        Ok(())
+    }
+
+    pub fn passes(&self, job_id: i64) -> Result<Vec<PassSummary>> {
+        let mut stmt = self.conn.prepare(
+            "SELECT started_at, ended_at, row_feet, avg_speed_mph, spacing_in, seed_cwt, start_spacing_in
+             FROM passes WHERE job_id = ?1 ORDER BY id",
+        )?;
+        let rows = stmt.query_map(params![job_id], |r| {
+            Ok(PassSummary {
+                started_at: r.get(0)?,
+                ended_at: r.get(1)?,
+                row_feet: r.get(2)?,
+                avg_speed_mph: r.get(3)?,
+                spacing_in: r.get(4)?,
+                seed_cwt: r.get(5)?,
+                start_spacing_in: r.get(6)?,
+            })
+        })?;
+        rows.collect()
+    }
+
+    pub fn record_bulk_fill(&self, fill: &BulkFill) -> Result<()> {
+        self.conn.execute(
+            "INSERT INTO bulk_fills (job_id, at, cwt, estimated_used_cwt) VALUES (?1, ?2, ?3, ?4)",
+            params![fill.job_id, fill.at, fill.cwt, fill.estimated_used_cwt],
+        )?;
+        Ok(())
+    }
+
+    // the most recent fills, oldest first
+    pub fn bulk_fills(&self, limit: usize) -> Result<Vec<BulkFill>> {
+        let mut stmt = self.conn.prepare(
+            "SELECT job_id, at, cwt, estimated_used_cwt FROM
+             (SELECT * FROM bulk_fills ORDER BY id DESC LIMIT ?1) ORDER BY id",
+        )?;
+        let rows = stmt.query_map(params![limit], |r| {
+            Ok(BulkFill {
+                job_id: r.get(0)?,
+                at: r.get(1)?,
+                cwt: r.get(2)?,
+                estimated_used_cwt: r.get(3)?,
+            })
+        })?;
+        rows.collect()
+    }
+
+    // replaces the field's boundary, acres come from the boundary when not set
+    pub fn set_boundary(&self, field_id: i64, boundary: &Boundary) -> Result<()> {
+        self.conn.execute(
+            "INSERT OR REPLACE INTO boundaries (field_id, geojson) VALUES (?1, ?2)",
+            params![field_id, to_geojson(boundary).to_string()],
+        )?;
+        let acres = boundary.area_m2() / 4046.856;
+        self.conn.execute(
+            "UPDATE fields SET acres = ?2 WHERE id = ?1 AND acres IS NULL",
+            params![field_id, acres],
+        )?;
+        Ok(())
+    }
+
+    pub fn boundaries(&self) -> Result<Vec<(i64, Boundary)>> {
+        let mut stmt = self
+            .conn
+            .prepare("SELECT field_id, geojson FROM boundaries")?;
+        let rows = stmt.query_map([], |r| Ok((r.get::<_, i64>(0)?, r.get::<_, String>(1)?)))?;
+        let mut out = vec![];
+        for row in rows {
+            let (id, json) = row?;
+            match serde_json::from_str(&json) {
+                Ok(v) => out.push((id, from_geojson(&v))),
+                Err(e) => eprintln!("boundary for field {id}: {e}"),
+            }
+        }
+        Ok(out)
+    }
+
+    pub fn set_prescription(&self, field_id: i64, rx: &Prescription) -> Result<()> {
+        let json = serde_json::to_string(rx).expect("prescription serializes");
+        self.conn.execute(
+            "INSERT OR REPLACE INTO prescriptions (field_id, json) VALUES (?1, ?2)",
+            params![field_id, json],
+        )?;
+        Ok(())
+    }
+
+    pub fn prescription(&self, field_id: i64) -> Result<Option<Prescription>> {
+        let json: Option<String> = self
+            .conn
+            .query_row(
+                "SELECT json FROM prescriptions WHERE field_id = ?1",
+                params![field_id],
+                |r| r.get(0),
+            )
+            .optional()?;
+        Ok(json.and_then(|j| {
+            serde_json::from_str(&j)
+                .map_err(|e| eprintln!("prescription for field {field_id}: {e}"))
+                .ok()
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::geo::{LatLon, Polygon};
+    use crate::prescription::{Rate, Zone};
+
+    #[test]
+    fn failed_migration_is_rolled_back() {
+        let mut conn = Connection::open_in_memory().unwrap();
+        conn.execute_batch("CREATE TABLE t (a INTEGER)").unwrap();
+        let version = |conn: &Connection| -> usize {
+            conn.query_row("PRAGMA user_version", [], |r| r.get(0))
+                .unwrap()
+        };
+        let columns = |conn: &Connection| {
+            let mut stmt = conn.prepare("SELECT * FROM t").unwrap();
+            stmt.column_count()
+        };
+
+        // the second statement of the second migration fails
+        let broken = [
+            "ALTER TABLE t ADD COLUMN b",
+            "ALTER TABLE t ADD COLUMN c; nonsense",
+        ];
+        assert!(migrate(&mut conn, &broken).is_err());
+        assert_eq!(version(&conn), 1);
+        assert_eq!(columns(&conn), 2);
+
+        // fixed in the next release, only the missing one runs
+        let fixed = ["ALTER TABLE t ADD COLUMN b", "ALTER TABLE t ADD COLUMN c"];
+        migrate(&mut conn, &fixed).unwrap();
+        assert_eq!(version(&conn), 2);
+        assert_eq!(columns(&conn), 3);
+        migrate(&mut conn, &fixed).unwrap();
+        assert_eq!(columns(&conn), 3);
+    }
+
+    #[test]
+    fn jobs_and_passes() {
+        let db = Db::open_in_memory().unwrap();
+        let north = db.add_field("Home", "North 40", Some(40.0)).unwrap();
+        db.add_field("Home", "Creek", None).unwrap();
+        assert_eq!(db.fields().unwrap().len(), 2);
+        assert!(db.open_job(north).unwrap().is_none());
+
+        let job = db.add_job(north, Some("Russet"), 12.0, 36.0).unwrap();
+        db.start_job(job, "2023-05-14T08:00:00").unwrap();
+        let pass = PassSummary {
+            started_at: "2023-05-14T08:00:00".into(),
+            ended_at: "2023-05-14T08:05:00".into(),
+            row_feet: 14520.0,
+            avg_speed_mph: 3.5,
+            spacing_in: 12.0,
+            seed_cwt: 10.0,
+            start_spacing_in: Some(14.5),
+        };
+        db.record_pass(job, &pass).unwrap();
+        db.record_pass(job, &pass).unwrap();
+
+        let j = db.open_job(north).unwrap().unwrap();
+        assert_eq!(j.field, "North 40");
+        assert_eq!(j.acres(), 2.0);
+        assert_eq!(j.cwt_per_acre(), Some(10.0));
+        assert_eq!(db.passes(job).unwrap(), vec![pass.clone(), pass]);
+
+        db.end_job(job, "2023-05-14T17:00:00").unwrap();
+        assert!(db.open_job(north).unwrap().is_none());
+
+        for i in 0..3 {
+            db.record_bulk_fill(&BulkFill {
+                job_id: Some(job),
+                at: String::new(),
+                cwt: 50.0,
+                estimated_used_cwt: i as f32 * 40.0,
+            })
+            .unwrap();
+        }
+        let fills = db.bulk_fills(2).unwrap();
+        assert_eq!(fills.len(), 2);
+        assert_eq!(fills[1].estimated_used_cwt, 80.0);
+    }
+
+    #[test]
+    fn varieties_and_lots() {
+        let db = Db::open_in_memory().unwrap();
+        let field = db.add_field("Home", "North 40", None).unwrap();
+        let job = db.add_job(field, None, 10.0, 34.0).unwrap();
+
+        let russet = db
+            .add_variety("Russet Burbank", 12.0, Some(2.0), None)
+            .unwrap();
+        db.add_variety("Red Norland", 9.0, None, Some("seed crop"))
+            .unwrap();
+        db.add_seed_lot(russet, "RB-2301", None).unwrap();
+        assert_eq!(db.varieties().unwrap()[1].name, "Russet Burbank");
+        assert_eq!(db.seed_lots().unwrap()[0].variety_id, russet);
+
+        db.set_job_variety(job, "Russet Burbank", Some("RB-2301"), 12.0)
+            .unwrap();
+        let j = db.job(job).unwrap();
+        assert_eq!(j.variety.as_deref(), Some("Russet Burbank"));
+        assert_eq!(j.seed_lot.as_deref(), Some("RB-2301"));
+        assert_eq!(j.spacing_in, 12.0);
+    }
+
+    #[test]
+    fn boundaries_and_prescriptions() {
+        let db = Db::open_in_memory().unwrap();
+        let field = db.add_field("Home", "North 40", None).unwrap();
+        let corner = |lat, lon| LatLon { lat, lon };
+        let b = Boundary {
+            polygons: vec![Polygon {
+                outer: vec![
+                    corner(46.0, -100.0),
+                    corner(46.0, -99.99),
+                    corner(46.01, -99.99),
+                    corner(46.01, -100.0),
+                ],
+                holes: vec![],
+            }],
+        };
+        db.set_boundary(field, &b).unwrap();
+        assert_eq!(db.boundaries().unwrap(), vec![(field, b.clone())]);
+        let acres = db.fields().unwrap()[0].acres.unwrap();
+        assert!((acres - 212.2).abs() < 1.0, "{acres}");
+
+        assert_eq!(db.prescription(field).unwrap(), None);
+        let rx = Prescription {
+            zones: vec![Zone {
+                name: "good".into(),
+                rate: Rate::SpacingIn(9.0),
+                boundary: b,
+            }],
+            grid: None,
+        };
+        db.set_prescription(field, &rx).unwrap();
+        assert_eq!(db.prescription(field).unwrap(), Some(rx));
+    }
+}
@@ -0,0 +1,199 @@
+use std::time::{Duration, Instant};
+
+pub struct DiagnosticsCfg {
+    // share open above which the seed wheel is expected to turn
+    pub open_position: f32,
+    // wheel speed under this share of what the valve should give is a stall
+    pub stall_ratio: f32,
+    // share of backward ticks that counts as reversed, with enough ticks to go on
+    pub reverse_share: f32,
+    pub reverse_min_ticks: u32,
+    // a condition is raised once it has held this long
+    pub hold: Duration,
+    // longest gap in the encoder signal while the wheel should turn
+    pub encoder_timeout: Duration,
+}
+
+impl Default for DiagnosticsCfg {
+    fn default() -> Self {
+        DiagnosticsCfg {
+            open_position: 0.2,
+            stall_ratio: 0.3,
+            reverse_share: 0.5,
+            reverse_min_ticks: 5,
+            hold: Duration::from_secs(3),
+            encoder_timeout: Duration::from_secs(2),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WheelFault {
+    // turning, but well short of the speed the valve opening gives
+    Stalled,
+    // no encoder signal while the wheel should turn, a wheel jammed solid reads the same
+    EncoderLost,
+    // the quadrature encoder says the wheel runs backwards
+    Reversed,
+}
+
+impl WheelFault {
+    pub const ALL: [WheelFault; 3] = [
+        WheelFault::Stalled,
+        WheelFault::EncoderLost,
+        WheelFault::Reversed,
+    ];
+}
+
+/// Checks that a drive's seed wheel follows its valve, while planting
+#[derive(Default)]
+pub struct Diagnostics {
+    pub cfg: DiagnosticsCfg,
+    // when each fault's condition was first seen, in WheelFault order
+    since: [Option<Instant>; 3],
+    forward: u32,
+    backward: u32,
+    reversed: bool,
+    // the encoder signal, and the end of any grace given for the wheel to start
+    last_signal: Option<Instant>,
+    grace_until: Option<Instant>,
+}
+
+impl Diagnostics {
+    pub fn tick(&mut self, backward: bool, now: Instant) {
+        if backward {
+            self.backward += 1;
+        } else {
+            self.forward += 1;
+        }
+        self.signal(now);
+    }
+
+    // anything heard from the encoder, a measured speed included
+    pub fn signal(&mut self, now: Instant) {
+        self.last_signal = Some(now);
+    }
+
+    // the wheel is not expected to be turning before `until`
+    pub fn rearm(&mut self, until: Instant) {
+        self.grace_until = Some(until);
+    }
+
+    // position is the valve model, expected the wheel speed it should give if known,
+    // rpm the measured wheel speed either way round; returns the faults that have held
+    pub fn update(
+        &mut self,
+        now: Instant,
+        position: Option<f32>,
+        expected: Option<f32>,
+        rpm: f32,
+    ) -> Vec<WheelFault> {
+        let open = position.map_or(false, |p| p >= self.cfg.open_position);
+        let stalled =
+            open && rpm > 0.0 && expected.map_or(false, |e| rpm < e * self.cfg.stall_ratio);
+        // before homing the valve may be anywhere, so the wheel is expected to turn
+        let last = *self.last_signal.get_or_insert(now);
+        let from = self.grace_until.map_or(last, |g| g.max(last));
+        let silent = now.saturating_duration_since(from) > self.cfg.encoder_timeout;
+        let lost = position.map_or(true, |p| p >= self.cfg.open_position) && silent;
+        // the direction is judged again each time enough ticks have come in
+        let ticks = self.forward + self.backward;
+        if ticks >= self.cfg.reverse_min_ticks {
+            self.reversed = self.backward as f32 > ticks as f32 * self.cfg.reverse_share;
+            self.forward = 0;
+            self.backward = 0;
+        }
+        if rpm <= 0.0 {
+            self.reversed = false;
+        }
+        let reversed = self.reversed;
+
+        // the encoder timeout is its own hold
+        let hold = [self.cfg.hold, Duration::ZERO, self.cfg.hold];
+        WheelFault::ALL
+            .into_iter()
+            .zip([stalled, lost, reversed])
+            .zip(self.since.iter_mut().zip(hold))
+            .filter_map(|((fault, seen), (since, hold))| {
+                if !seen {
+                    *since = None;
+                    return None;
+                }
+                let at = *since.get_or_insert(now);
+                (now.saturating_duration_since(at) >= hold).then_some(fault)
+            })
+            .collect()
+    }
+
+    // some fault's condition has been seen, held long enough or not
+    pub fn pending(&self) -> bool {
+        self.since.iter().any(Option::is_some) || self.reversed
+    }
+
+    pub fn reset(&mut self) {
+        self.since = [None; 3];
+        self.forward = 0;
+        self.backward = 0;
+        self.reversed = false;
+        // the timeout starts again once the checks resume
+        self.last_signal = None;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn faults_hold_before_raising() {
+        let mut d = Diagnostics::default();
+        let t = Instant::now();
+        let s = Duration::from_secs(1);
+
+        // half open and turning as the flow map expects
+        assert!(d.update(t, Some(0.5), Some(30.0), 29.0).is_empty());
+        // the chain jumps, the wheel drags round at a fraction of the speed
+        d.signal(t + s);
+        assert!(d.update(t + s, Some(0.5), Some(30.0), 5.0).is_empty());
+        d.signal(t + 4 * s);
+        assert_eq!(
+            d.update(t + 4 * s, Some(0.5), Some(30.0), 5.0),
+            vec![WheelFault::Stalled]
+        );
+        // nothing from the encoder
+        assert!(d.update(t + 6 * s, Some(0.5), Some(30.0), 0.0).is_empty());
+        assert_eq!(
+            d.update(t + 7 * s, Some(0.5), None, 0.0),
+            vec![WheelFault::EncoderLost]
+        );
+        // closed, no ticks are expected
+        assert!(d.update(t + 8 * s, Some(0.1), None, 0.0).is_empty());
+        // but they are before homing
+        assert_eq!(
+            d.update(t + 9 * s, None, None, 0.0),
+            vec![WheelFault::EncoderLost]
+        );
+
+        // a start grace holds the timeout off
+        d.reset();
+        d.rearm(t + 12 * s);
+        assert!(d.update(t + 10 * s, Some(0.5), None, 0.0).is_empty());
+        assert!(d.update(t + 14 * s, Some(0.5), None, 0.0).is_empty());
+        assert_eq!(
+            d.update(t + 15 * s, Some(0.5), None, 0.0),
+            vec![WheelFault::EncoderLost]
+        );
+
+        // mostly backward ticks, held between judgements
+        for _ in 0..20 {
+            d.tick(true, t + 16 * s);
+        }
+        d.tick(false, t + 16 * s);
+        assert!(d.update(t + 16 * s, Some(0.5), None, 20.0).is_empty());
+        d.tick(true, t + 19 * s);
+        assert_eq!(
+            d.update(t + 19 * s, Some(0.5), None, 20.0),
+            vec![WheelFault::Reversed]
+        );
+    }
+}
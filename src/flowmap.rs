@@ -0,0 +1,222 @@
+use crate::util::{self, mph_to_fps};
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+pub struct FlowMapCfg {
+    // weight of a new sample in its bin
+    pub learn_rate: f32,
+    // share of a bin's change applied to the others, oil temperature and engine speed move them together
+    pub drift: f32,
+    // share the target speed must change by before the valve is sent straight to the map
+    pub jump_threshold: f32,
+    // wait after a jump before error control takes over
+    pub settle: Duration,
+    // wait after any valve move before the wheel speed is learned
+    pub steady: Duration,
+}
+
+impl Default for FlowMapCfg {
+    fn default() -> Self {
+        FlowMapCfg {
+            learn_rate: 0.2,
+            drift: 0.5,
+            jump_threshold: 0.1,
+            settle: Duration::from_millis(1500),
+            steady: Duration::from_secs(2),
+        }
+    }
+}
+
+pub struct StartCfg {
+    // the openers reach the ground this far, or this long, after the lift switch,
+    // whichever is longer, and the seed wheel is started at the switch
+    pub lead_ft: f32,
+    pub lead_time: Duration,
+    // close the valve as soon as the planter is raised rather than holding it
+    pub close_on_raise: bool,
+    // spacing over the start of each pass is kept apart in the pass summary
+    pub measure_ft: f32,
+}
+
+impl Default for StartCfg {
+    fn default() -> Self {
+        StartCfg {
+            lead_ft: 6.0,
+            lead_time: Duration::from_secs(1),
+            close_on_raise: true,
+            measure_ft: 20.0,
+        }
+    }
+}
+
+impl StartCfg {
+    // time the seed wheel has to come up to speed at the current ground speed
+    pub fn lead(&self, mph: f32) -> Duration {
+        let fps = mph_to_fps(mph);
+        if fps <= 0.0 {
+            return self.lead_time;
+        }
+        self.lead_time
+            .max(Duration::try_from_secs_f32(self.lead_ft / fps).unwrap_or_default())
+    }
+}
+
+const BINS: usize = 20;
+
+/// Seed wheel speed at each valve position, learned while the wheel runs steady
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FlowMap {
+    // mean position and rpm of the samples in each twentieth of the travel
+    bins: Vec<Option<(f32, f32)>>,
+}
+
+impl Default for FlowMap {
+    fn default() -> Self {
+        FlowMap {
+            bins: vec![None; BINS],
+        }
+    }
+}
+
+impl FlowMap {
+    pub fn learn(&mut self, cfg: &FlowMapCfg, position: f32, rpm: f32) {
+        let n = self.bins.len();
+        if n == 0 {
+            return;
+        }
+        let bin = ((position.clamp(0.0, 1.0) * n as f32) as usize).min(n - 1);
+        let a = cfg.learn_rate;
+        let (pos, old) = self.bins[bin].unwrap_or((position, rpm));
+        let new = old + a * (rpm - old);
+        self.bins[bin] = Some((pos + a * (position - pos), new));
+        if old > 0.0 {
+            let scale = 1.0 + (new / old - 1.0) * cfg.drift;
+            for (i, b) in self.bins.iter_mut().enumerate() {
+                if let (true, Some((_, r))) = (i != bin, b.as_mut()) {
+                    *r *= scale;
+                }
+            }
+        }
+    }
+
+    /// Valve position for a seed wheel speed, between learned points only
+    pub fn position_for(&self, rpm: f32) -> Option<f32> {
+        let points: Vec<(f32, f32)> = self.bins.iter().flatten().copied().collect();
+        points.windows(2).find_map(|w| {
+            let ((p0, r0), (p1, r1)) = (w[0], w[1]);
+            let (lo, hi) = (r0.min(r1), r0.max(r1));
+            if rpm < lo || rpm > hi || (r1 - r0).abs() < f32::EPSILON {
+                return None;
+            }
+            Some(p0 + (rpm - r0) / (r1 - r0) * (p1 - p0))
+        })
+    }
+
+    /// Seed wheel speed expected at a valve position, between learned points only
+    pub fn rpm_at(&self, position: f32) -> Option<f32> {
+        let points: Vec<(f32, f32)> = self.bins.iter().flatten().copied().collect();
+        points.windows(2).find_map(|w| {
+            let ((p0, r0), (p1, r1)) = (w[0], w[1]);
+            if position < p0 || position > p1 || p1 - p0 < f32::EPSILON {
+                return None;
+            }
+            Some(r0 + (position - p0) / (p1 - p0) * (r1 - r0))
+        })
+    }
+}
+
+pub fn default_path() -> PathBuf {
+    util::data_dir().join("flow_map.json")
+}
+
+// one map per drive, a missing or unreadable file, or a map of another size,
+// starts from scratch
+pub fn load(path: &Path) -> Vec<FlowMap> {
+    let maps: Vec<FlowMap> = fs::read_to_string(path)
+        .ok()
+        .and_then(|s| serde_json::from_str(&s).ok())
+        .unwrap_or_default();
+    maps.into_iter()
+        .map(|m| {
+            if m.bins.len() == BINS {
+                m
+            } else {
+                FlowMap::default()
+            }
+        })
+        .collect()
+}
+
+pub fn save(path: &Path, maps: &[&FlowMap]) -> io::Result<()> {
+    util::atomic_write(path, serde_json::to_string(maps)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::util::TempDir;
+
+    #[test]
+    fn learns_and_drifts() {
+        let cfg = FlowMapCfg::default();
+        let mut map = FlowMap::default();
+        assert_eq!(map.position_for(20.0), None);
+
+        // 50 rpm fully open, little flow under 10% open
+        let rpm = |p: f32| (p - 0.1).max(0.0) * 50.0 / 0.9;
+        for _ in 0..30 {
+            for p in [0.2, 0.4, 0.6, 0.8] {
+                map.learn(&cfg, p, rpm(p));
+            }
+        }
+        let p = map.position_for(rpm(0.5)).unwrap();
+        assert!((p - 0.5).abs() < 0.01, "{p}");
+        assert!((map.rpm_at(0.5).unwrap() - rpm(0.5)).abs() < 0.5);
+        // outside what has been seen
+        assert_eq!(map.position_for(49.0), None);
+
+        // warm oil, the same opening turns the wheel 10% faster everywhere,
+        // seen at one position the rest of the map follows part way
+        let before = map.position_for(rpm(0.6) * 1.1).unwrap();
+        for _ in 0..30 {
+            map.learn(&cfg, 0.4, rpm(0.4) * 1.1);
+        }
+        let p = map.position_for(rpm(0.6) * 1.1).unwrap();
+        assert!(
+            (p - 0.6).abs() < 0.03 && (p - 0.6).abs() < (before - 0.6).abs(),
+            "{p}"
+        );
+
+        let json = serde_json::to_string(&map).unwrap();
+        let back: FlowMap = serde_json::from_str(&json).unwrap();
+        assert_eq!(back.position_for(25.0), map.position_for(25.0));
+
+        // a map of the wrong size is dropped, not learned into
+        let dir = TempDir::new("flowmap");
+        let path = dir.path().join("flow_map.json");
+        fs::write(&path, r#"[{"bins":[]},{"bins":[null,[0.5,20.0]]}]"#).unwrap();
+        let mut loaded = load(&path);
+        assert_eq!(loaded.len(), 2);
+        assert!(loaded.iter().all(|m| m.bins.len() == BINS));
+        loaded[0].learn(&cfg, 0.5, 20.0);
+    }
+
+    #[test]
+    fn lead_covers_distance_or_time() {
+        let cfg = StartCfg::default();
+        // 6 ft at 3 mph is 1.36 s
+        assert!((cfg.lead(3.0).as_secs_f32() - 1.364).abs() < 1e-3);
+        // fast enough that the time is longer
+        assert_eq!(cfg.lead(6.0), cfg.lead_time);
+        assert_eq!(cfg.lead(0.0), cfg.lead_time);
+        // a bad setting falls back to the time
+        let cfg = StartCfg {
+            lead_ft: -6.0,
+            ..Default::default()
+        };
+        assert_eq!(cfg.lead(3.0), cfg.lead_time);
+    }
+}
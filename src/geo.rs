@@ -0,0 +1,286 @@
+use serde::{Deserialize, Serialize};
+
+// mean earth radius, metres
+const EARTH_RADIUS_M: f64 = 6_371_008.8;
+
+pub const M_PER_FT: f64 = 0.3048;
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct LatLon {
+    pub lat: f64,
+    pub lon: f64,
+}
+
+/// Position on the local plane, metres east and north of the origin
+#[derive(Debug, Clone, Copy, PartialEq, Default, Serialize, Deserialize)]
+pub struct Xy {
+    pub x: f64,
+    pub y: f64,
+}
+
+impl Xy {
+    pub fn dist(&self, o: &Xy) -> f64 {
+        (self.x - o.x).hypot(self.y - o.y)
+    }
+}
+
+/// East-north plane tangent at an origin, well under a metre of error across a field
+#[derive(Debug, Clone, Copy)]
+pub struct LocalProjection {
+    origin: LatLon,
+    cos_lat: f64,
+}
+
+impl LocalProjection {
+    pub fn new(origin: LatLon) -> Self {
+        LocalProjection {
+            origin,
+            cos_lat: origin.lat.to_radians().cos(),
+        }
+    }
+
+    pub fn origin(&self) -> LatLon {
+        self.origin
+    }
+
+    pub fn to_local(&self, p: LatLon) -> Xy {
+        Xy {
+            x: (p.lon - self.origin.lon).to_radians() * EARTH_RADIUS_M * self.cos_lat,
+            y: (p.lat - self.origin.lat).to_radians() * EARTH_RADIUS_M,
+        }
+    }
+
+    pub fn to_geo(&self, p: Xy) -> LatLon {
+        LatLon {
+            lat: self.origin.lat + (p.y / EARTH_RADIUS_M).to_degrees(),
+            lon: self.origin.lon + (p.x / (EARTH_RADIUS_M * self.cos_lat)).to_degrees(),
+        }
+    }
+}
+
+// unit vector to the left of travel at each point, averaged over the adjoining segments
+fn normals(line: &[Xy]) -> Vec<Xy> {
+    let dir = |a: &Xy, b: &Xy| {
+        let d = a.dist(b);
+        Xy {
+            x: (b.x - a.x) / d,
+            y: (b.y - a.y) / d,
+        }
+    };
+    (0..line.len())
+        .map(|i| {
+            let prev = (i > 0).then(|| dir(&line[i - 1], &line[i]));
+            let next = (i + 1 < line.len()).then(|| dir(&line[i], &line[i + 1]));
+            let d = match (prev, next) {
+                (Some(p), Some(n)) => Xy {
+                    x: p.x + n.x,
+                    y: p.y + n.y,
+                },
+                (Some(d), None) | (None, Some(d)) => d,
+                (None, None) => Xy { x: 0.0, y: 1.0 },
+            };
+            let len = d.x.hypot(d.y).max(f64::EPSILON);
+            Xy {
+                x: -d.y / len,
+                y: d.x / len,
+            }
+        })
+        .collect()
+}
+
+// drops points closer than min to the previous one
+pub fn simplify(line: &[Xy], min: f64) -> Vec<Xy> {
+    let mut out: Vec<Xy> = vec![];
+    for p in line {
+        if out.last().map_or(true, |l| l.dist(p) >= min) {
+            out.push(*p);
+        }
+    }
+    out
+}
+
+/// Closed clockwise ring covering width metres centred on the line
+pub fn swath(line: &[Xy], width: f64) -> Vec<Xy> {
+    let line = simplify(line, 0.1);
+    let half = width / 2.0;
+    let n = normals(&line);
+    let left = line.iter().zip(&n).map(|(p, n)| Xy {
+        x: p.x + n.x * half,
+        y: p.y + n.y * half,
+    });
+    let right = line.iter().zip(&n).rev().map(|(p, n)| Xy {
+        x: p.x - n.x * half,
+        y: p.y - n.y * half,
+    });
+    let mut ring: Vec<Xy> = left.chain(right).collect();
+    if let Some(first) = ring.first().copied() {
+        ring.push(first);
+    }
+    ring
+}
+
+/// Positive when the ring runs counter clockwise
+pub fn signed_area(ring: &[Xy]) -> f64 {
+    ring.windows(2)
+        .map(|w| w[0].x * w[1].y - w[1].x * w[0].y)
+        .sum::<f64>()
+        / 2.0
+}
+
+// distance from p to the segment a-b
+pub fn dist_to_segment(p: Xy, a: Xy, b: Xy) -> f64 {
+    let (dx, dy) = (b.x - a.x, b.y - a.y);
+    let len2 = dx * dx + dy * dy;
+    if len2 == 0.0 {
+        return p.dist(&a);
+    }
+    let t = (((p.x - a.x) * dx + (p.y - a.y) * dy) / len2).clamp(0.0, 1.0);
+    p.dist(&Xy {
+        x: a.x + t * dx,
+        y: a.y + t * dy,
+    })
+}
+
+pub fn line_length(line: &[Xy]) -> f64 {
+    line.windows(2).map(|w| w[0].dist(&w[1])).sum()
+}
+
+// even-odd rule, the ring may or may not repeat its first point
+pub fn ring_contains(ring: &[Xy], p: Xy) -> bool {
+    let mut inside = false;
+    let mut j = ring.len().wrapping_sub(1);
+    for i in 0..ring.len() {
+        let (a, b) = (ring[i], ring[j]);
+        if (a.y > p.y) != (b.y > p.y) && p.x < (b.x - a.x) * (p.y - a.y) / (b.y - a.y) + a.x {
+            inside = !inside;
+        }
+        j = i;
+    }
+    inside
+}
+
+/// Outer ring and holes
+#[derive(Debug, Clone, PartialEq, Default, Serialize, Deserialize)]
+pub struct Polygon {
+    pub outer: Vec<LatLon>,
+    pub holes: Vec<Vec<LatLon>>,
+}
+
+/// Field boundary, one or more polygons
+#[derive(Debug, Clone, PartialEq, Default, Serialize, Deserialize)]
+pub struct Boundary {
+    pub polygons: Vec<Polygon>,
+}
+
+impl Boundary {
+    pub fn contains(&self, p: LatLon) -> bool {
+        // projected around the point itself, so it sits at the origin
+        let proj = LocalProjection::new(p);
+        let local =
+            |ring: &Vec<LatLon>| -> Vec<Xy> { ring.iter().map(|q| proj.to_local(*q)).collect() };
+        self.polygons.iter().any(|poly| {
+            ring_contains(&local(&poly.outer), Xy::default())
+                && !poly
+                    .holes
+                    .iter()
+                    .any(|h| ring_contains(&local(h), Xy::default()))
+        })
+    }
+
+    pub fn area_m2(&self) -> f64 {
+        let Some(origin) = self.polygons.first().and_then(|p| p.outer.first()) else {
+            return 0.0;
+        };
+        let proj = LocalProjection::new(*origin);
+        let area = |ring: &Vec<LatLon>| {
+            let mut xy: Vec<Xy> = ring.iter().map(|q| proj.to_local(*q)).collect();
+            if let Some(first) = xy.first().copied() {
+                xy.push(first);
+            }
+            signed_area(&xy).abs()
+        };
+        self.polygons
+            .iter()
+            .map(|p| area(&p.outer) - p.holes.iter().map(area).sum::<f64>())
+            .sum()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn projection_round_trip() {
+        let proj = LocalProjection::new(LatLon {
+            lat: 46.8,
+            lon: -100.7,
+        });
+        let p = LatLon {
+            lat: 46.801,
+            lon: -100.699,
+        };
+        let xy = proj.to_local(p);
+        // 0.001 deg of latitude is about 111 m
+        assert!((xy.y - 111.2).abs() < 0.5);
+        assert!((xy.x - 76.2).abs() < 0.5);
+        let back = proj.to_geo(xy);
+        assert!((back.lat - p.lat).abs() < 1e-9);
+        assert!((back.lon - p.lon).abs() < 1e-9);
+    }
+
+    #[test]
+    fn swath_ring() {
+        let line = [Xy { x: 0.0, y: 0.0 }, Xy { x: 0.0, y: 100.0 }];
+        let ring = swath(&line, 2.0);
+        assert_eq!(ring.len(), 5);
+        assert_eq!(ring.first(), ring.last());
+        // out along the left side and back along the right runs clockwise
+        assert!((signed_area(&ring) + 200.0).abs() < 1e-9);
+        assert_eq!(line_length(&line), 100.0);
+        assert_eq!(
+            dist_to_segment(Xy { x: 3.0, y: 50.0 }, line[0], line[1]),
+            3.0
+        );
+        assert_eq!(
+            dist_to_segment(Xy { x: 3.0, y: 104.0 }, line[0], line[1]),
+            5.0
+        );
+    }
+
+    #[test]
+    fn boundary_with_hole() {
+        let square = |lat: f64, lon: f64, d: f64| {
+            vec![
+                LatLon { lat, lon },
+                LatLon { lat, lon: lon + d },
+                LatLon {
+                    lat: lat + d,
+                    lon: lon + d,
+                },
+                LatLon { lat: lat + d, lon },
+            ]
+        };
+        let b = Boundary {
+            polygons: vec![Polygon {
+                outer: square(46.0, -100.0, 0.01),
+                holes: vec![square(46.004, -99.996, 0.002)],
+            }],
+        };
+        assert!(b.contains(LatLon {
+            lat: 46.001,
+            lon: -99.999
+        }));
+        assert!(!b.contains(LatLon {
+            lat: 46.005,
+            lon: -99.995
+        }));
+        assert!(!b.contains(LatLon {
+            lat: 46.02,
+            lon: -99.999
+        }));
+        // about 1112 m by 773 m less the hole
+        let acres = b.area_m2() / 4046.856;
+        assert!((acres - 203.9).abs() < 1.0, "{acres}");
+    }
+}
@@ -0,0 +1,161 @@
+use crate::geo::Xy;
+use crate::monitor::ROWS;
+
+// a jump this far between fixes puts the planter straight behind again, metres
+const RESET_JUMP_M: f64 = 10.0;
+
+/// Where the row units are relative to the antenna, metres
+pub struct GeometryCfg {
+    // along the tractor from the antenna back to the hitch pin
+    pub antenna_to_hitch_m: f64,
+    // antenna right of the tractor centreline
+    pub antenna_offset_m: f64,
+    // from the hitch pin back to the line of row units
+    pub hitch_to_rows_m: f64,
+    // each row right of the planter centreline, row 0 on the left
+    pub row_offsets_m: [f64; ROWS],
+}
+
+impl Default for GeometryCfg {
+    fn default() -> Self {
+        GeometryCfg {
+            antenna_to_hitch_m: 2.5,
+            antenna_offset_m: 0.0,
+            hitch_to_rows_m: 3.0,
+            row_offsets_m: [-0.43, 0.43],
+        }
+    }
+}
+
+fn unit(heading_deg: f64) -> Xy {
+    let (sin, cos) = heading_deg.to_radians().sin_cos();
+    Xy { x: sin, y: cos }
+}
+
+/// Trailed planter, the row line follows the hitch like a wheelbarrow pulled by its handles
+#[derive(Default)]
+pub struct Planter {
+    pub cfg: GeometryCfg,
+    hitch: Option<Xy>,
+    centre: Option<Xy>,
+    // tractor heading, kept while stopped when the fix has none
+    heading: Option<f64>,
+}
+
+impl Planter {
+    /// Moves the planter for a new antenna position, heading in degrees from north
+    pub fn update(&mut self, antenna: Xy, heading: Option<f32>) {
+        let Some(heading) = heading.map(f64::from).or(self.heading) else {
+            return;
+        };
+        self.heading = Some(heading);
+        let fwd = unit(heading);
+        let right = Xy {
+            x: fwd.y,
+            y: -fwd.x,
+        };
+        let hitch = Xy {
+            x: antenna.x
+                - fwd.x * self.cfg.antenna_to_hitch_m
+                - right.x * self.cfg.antenna_offset_m,
+            y: antenna.y
+                - fwd.y * self.cfg.antenna_to_hitch_m
+                - right.y * self.cfg.antenna_offset_m,
+        };
+        let len = self.cfg.hitch_to_rows_m;
+        let behind = Xy {
+            x: hitch.x - fwd.x * len,
+            y: hitch.y - fwd.y * len,
+        };
+        let jumped = self.hitch.map_or(true, |h| h.dist(&hitch) > RESET_JUMP_M);
+        self.centre = match self.centre {
+            Some(prev) if !jumped => {
+                // the row line stays the drawbar length from the hitch, on the line to where it was
+                let d = prev.dist(&hitch);
+                if d < f64::EPSILON {
+                    Some(prev)
+                } else {
+                    Some(Xy {
+                        x: hitch.x + (prev.x - hitch.x) * len / d,
+                        y: hitch.y + (prev.y - hitch.y) * len / d,
+                    })
+                }
+            }
+            _ => Some(behind),
+        };
+        self.hitch = Some(hitch);
+    }
+
+    /// Middle of the row line
+    pub fn centre(&self) -> Option<Xy> {
+        self.centre
+    }
+
+    /// Direction the planter points, degrees from north
+    pub fn heading(&self) -> Option<f32> {
+        let (h, c) = (self.hitch?, self.centre?);
+        if h.dist(&c) < f64::EPSILON {
+            return self.heading.map(|h| h as f32);
+        }
+        let deg = (h.x - c.x).atan2(h.y - c.y).to_degrees();
+        Some(deg.rem_euclid(360.0) as f32)
+    }
+
+    pub fn rows(&self) -> Option<[Xy; ROWS]> {
+        let c = self.centre?;
+        let fwd = unit(self.heading()? as f64);
+        Some(self.cfg.row_offsets_m.map(|off| Xy {
+            x: c.x + fwd.y * off,
+            y: c.y - fwd.x * off,
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn trails_through_a_turn() {
+        let mut p = Planter::default();
+        let len = p.cfg.antenna_to_hitch_m + p.cfg.hitch_to_rows_m;
+
+        // heading north the planter is straight behind
+        for y in 0..20 {
+            p.update(
+                Xy {
+                    x: 0.0,
+                    y: y as f64,
+                },
+                Some(0.0),
+            );
+        }
+        let c = p.centre().unwrap();
+        assert!(c.x.abs() < 1e-9 && (c.y - (19.0 - len)).abs() < 1e-9);
+        assert!(p.heading().unwrap().abs() < 1e-3);
+        let rows = p.rows().unwrap();
+        assert!(rows[0].x < 0.0 && rows[1].x > 0.0);
+
+        // a sharp turn east, the planter lags behind the tractor
+        p.update(Xy { x: 1.0, y: 19.0 }, Some(90.0));
+        let h = p.heading().unwrap();
+        assert!((h - 90.0).abs() > 30.0, "{h}");
+        for x in 2..40 {
+            p.update(
+                Xy {
+                    x: x as f64,
+                    y: 19.0,
+                },
+                Some(90.0),
+            );
+        }
+        // straightened out behind again
+        assert!((p.heading().unwrap() - 90.0).abs() < 1.0);
+        let c = p.centre().unwrap();
+        assert!((c.y - 19.0).abs() < 0.1 && (c.x - (39.0 - len)).abs() < 0.1);
+
+        // no heading while stopped keeps the last one
+        p.update(Xy { x: 39.0, y: 19.0 }, None);
+        assert!((p.heading().unwrap() - 90.0).abs() < 1.0);
+    }
+}
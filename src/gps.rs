@@ -1,4 +1,6 @@
+use crate::util::knots_to_mph;
 use nmea::SentenceType;
+use serde::{Deserialize, Serialize};
 use std::time::Duration;
 use std::{io, thread};
 use tokio::sync::mpsc::Sender;
@@ -9,6 +11,56 @@ pub enum GroundSpeed {
     Gps(f32),
 }
 
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct Fix {
+    pub latitude: f64,
+    pub longitude: f64,
+    pub speed_mph: f32,
+    // degrees from true north, only while moving
+    pub heading: Option<f32>,
+}
+
+// reads complete NMEA sentences and reports each position fix
+pub fn read_fixes<F: FnMut(Fix)>(port_name: &str, mut on_fix: F) -> io::Result<()> {
+    let mut port = serialport::new(port_name, 9600)
+        .timeout(Duration::from_millis(10))
+        .open()?;
+
+    let mut nmea = nmea::Nmea::default();
+    let mut serial_buf: Vec<u8> = vec![0; 1000];
+    let mut line = String::new();
+    let mut timed_out_counter = 0;
+    loop {
+        match port.read(serial_buf.as_mut_slice()) {
+            Ok(t) => {
+                line.push_str(&String::from_utf8_lossy(&serial_buf[..t]));
+                while let Some(end) = line.find('\n') {
+                    let sentence: String = line.drain(..=end).collect();
+                    if let Ok(SentenceType::RMC) = nmea.parse(sentence.trim()) {
+                        if let (Some(latitude), Some(longitude)) = (nmea.latitude, nmea.longitude) {
+                            on_fix(Fix {
+                                latitude,
+                                longitude,
+                                speed_mph: knots_to_mph(nmea.speed_over_ground.unwrap_or(0.0)),
+                                heading: nmea.true_course,
+                            });
+                        }
+                    }
+                }
+                timed_out_counter = 0;
+            }
+            Err(e) if e.kind() == io::ErrorKind::TimedOut => {
+                timed_out_counter += 1;
+                if timed_out_counter > 100 {
+                    return Err(e);
+                }
+                thread::sleep(Duration::from_millis(100));
+            }
+            Err(e) => eprintln!("{:?}", e),
+        }
+    }
+}
+
 pub async fn read_speed(tx: Sender<GroundSpeed>, port_name: &str) -> io::Result<()> {
     let mut port = serialport::new(port_name, 9600)
         .timeout(Duration::from_millis(10))
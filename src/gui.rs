@@ -1,12 +1,22 @@
+use crate::alarm::{Alarm, Severity};
 use crate::app::{Dash, Page};
 use crate::io::Cmd::{LowerPlanter, RaisePlanter, SeedBeltControl};
 use crate::io::Event::{GroundSpeed, PlanterLowered, PlanterRaised, SeedWheelSpeed};
+use crate::journal::TALLY_REGISTERS;
+use crate::keypad::{make_keypad, KeypadTarget};
+use crate::mode::Mode;
 use crate::msg::Message;
-use crate::msg::Message::{IOEvent, SimulateCmd};
+use crate::msg::Message::{
+    AcknowledgeAlarms, FinishJob, HomeValve, IOEvent, RaiseAlarm, ResetTally, SelectField,
+    SelectVariety, SimulateCmd,
+};
 use crate::row_ui::make_row;
-use crate::util::{fps_to_sps, mph_to_fps, row_feet_to_acres, rpm_to_seed_per_second};
+use crate::util::{
+    fps_to_sps, mph_to_fps, row_feet_to_acres, rpm_to_seed_per_second, seed_cwt_per_acre,
+};
 use iced::widget::{
-    horizontal_space, row, slider, Button, Column, Container, Row, Slider, Space, Text, Toggler,
+    horizontal_space, pick_list, row, scrollable, slider, Button, Column, Container, Row, Slider,
+    Space, Text, Toggler,
 };
 use iced::{alignment, Alignment, Length, Renderer, Theme};
 use iced_aw::graphics::IconText;
@@ -20,9 +30,17 @@ const TAB_HEIGHT: u16 = 25;
 pub const BODY_HEIGHT: u16 = SCREEN_HEIGHT - TAB_HEIGHT - HEAD_HEIGHT - FOOT_HEIGHT;
 
 pub fn make_tabs(dash: &Dash) -> Container<Message> {
-    let mut tabs = TabBar::new(0, Message::TabSelected);
+    let active = match dash.page {
+        Page::Dashboard => 0,
+        Page::SoftIO => 1,
+        Page::Jobs => 2,
+        Page::Map => 3,
+    };
+    let mut tabs = TabBar::new(active, Message::TabSelected);
     tabs = tabs.push(TabLabel::Text("monitor".to_string()));
     tabs = tabs.push(TabLabel::Text("io".to_string()));
+    tabs = tabs.push(TabLabel::Text("jobs".to_string()));
+    tabs = tabs.push(TabLabel::Text("map".to_string()));
     Container::new(tabs)
 }
 
@@ -37,6 +55,9 @@ pub fn make_dash_page(dash: &Dash) -> Container<Message> {
 }
 
 fn body(dash: &Dash) -> Container<Message> {
+    if let Some(keypad) = &dash.keypad {
+        return make_keypad(keypad);
+    }
     let col = Column::new()
         .push(make_row(dash, 0))
         .push(make_row(dash, 1));
@@ -51,7 +72,24 @@ fn header(dash: &Dash) -> Container<Message> {
     let target_sps = fps_to_sps(fps, dash.in_between_seed);
     let actual_sps = rpm_to_seed_per_second(dash.seed_wheel_speed_rpm());
 
-    let row = Row::new()
+    let mut row = Row::new()
+        .push(Text::new(match (dash.job(), dash.zone()) {
+            (Some(job), Some((zone, spacing))) => {
+                format!("{} | {} | {zone} {spacing:.1}\"", dash.mode(), job.field)
+            }
+            (Some(job), None) => format!("{} | {}", dash.mode(), job.field),
+            (None, _) => format!("{}", dash.mode()),
+        }))
+        .push(Space::new(Length::Fill, Length::Fill));
+    if let Some(field) = dash.suggested_field() {
+        row = row
+            .push(
+                Button::new(Text::new(format!("In {}?", field.name)))
+                    .on_press(SelectField(field.id)),
+            )
+            .push(Space::new(Length::Fill, Length::Fill));
+    }
+    row = row
         .push(Text::new(format!("Acres: {acres:<.2} | Rows: {rowft}'")))
         .push(Space::new(Length::Fill, Length::Fill))
         .push(if dash.planter_raised() {
@@ -59,13 +97,33 @@ fn header(dash: &Dash) -> Container<Message> {
         } else {
             IconText::new(Icon::ArrowDown)
         })
-        .push(Space::new(Length::Fill, Length::Fill))
-        .push(Text::new(format!(
+        .push(Space::new(Length::Fill, Length::Fill));
+
+    row = match dash.current_alarm() {
+        Some(alarm) => row.push(alarm_banner(alarm, dash.alarm_unacknowledged())),
+        None => row.push(Text::new(format!(
             "{mph:<.1} MPH  |  {fps:<.1} FPS  |  {target_sps:<.1} SPS | {actual_sps}"
-        )));
+        ))),
+    };
     Container::new(row).width(Length::Fill)
 }
 
+fn alarm_banner(alarm: &Alarm, unacknowledged: bool) -> Row<Message> {
+    let icon = match alarm.severity() {
+        Severity::Info => Icon::InfoCircle,
+        _ => Icon::ExclamationTriangle,
+    };
+    let row = Row::new()
+        .spacing(10)
+        .push(IconText::new(icon))
+        .push(Text::new(alarm.to_string()));
+    if unacknowledged {
+        row.push(Button::new("Silence").on_press(AcknowledgeAlarms))
+    } else {
+        row
+    }
+}
+
 fn footer(dash: &Dash) -> Container<Message> {
     let row = Row::new()
         .push(
@@ -74,13 +132,32 @@ fn footer(dash: &Dash) -> Container<Message> {
                 .width(FOOT_HEIGHT)
                 .on_press(Message::IncreaseSpacing),
         )
-        .push(Text::new("10").size(24))
+        .push(
+            Button::new(Text::new(format!("{:.1}\"", dash.in_between_seed)).size(24))
+                .height(FOOT_HEIGHT)
+                .on_press(Message::OpenKeypad(KeypadTarget::Spacing)),
+        )
         .push(
             Button::new("-")
                 .height(FOOT_HEIGHT)
                 .width(FOOT_HEIGHT)
                 .on_press(Message::DecreaseSpacing),
         )
+        .push(
+            Button::new(Text::new(format!("{:.0} /ac", dash.population())).size(24))
+                .height(FOOT_HEIGHT)
+                .on_press(Message::OpenKeypad(KeypadTarget::Population)),
+        )
+        .push(Space::new(Length::Fill, Length::Fill))
+        .push((0..dash.drive_count()).fold(Column::new(), |col, id| {
+            col.push(Text::new(valve_text(dash, id)))
+        }))
+        .push(Space::new(Length::Fill, Length::Fill))
+        .push(pick_list(
+            &Mode::SELECTABLE[..],
+            Some(dash.mode()),
+            Message::SelectMode,
+        ))
         .push(
             Button::new("X")
                 .height(FOOT_HEIGHT)
@@ -90,6 +167,13 @@ fn footer(dash: &Dash) -> Container<Message> {
     Container::new(row).width(Length::Fill)
 }
 
+fn valve_text(dash: &Dash, id: usize) -> String {
+    match dash.valve_position(id) {
+        Some(p) => format!("Valve {:.0}%", p * 100.0),
+        None => "Valve -".to_string(),
+    }
+}
+
 pub fn make_io_page(dash: &Dash) -> Container<Message> {
     let body = Column::new()
         .push(make_tabs(dash))
@@ -115,13 +199,170 @@ pub fn make_io_page(dash: &Dash) -> Container<Message> {
             dash.priming(1),
             move |b| SimulateCmd(SeedBeltControl(1, b)),
         ))
+        .push((0..dash.drive_count()).fold(Column::new(), |col, id| {
+            col.push(row![
+                Text::new(format!("Seed wheel {} speed", id + 1)),
+                slider(0.0..=50.0, dash.drive_rpm(id), move |v| {
+                    IOEvent(SeedWheelSpeed(id, v))
+                })
+                .step(0.1),
+                Text::new(valve_text(dash, id)),
+                if dash.homing(id) {
+                    Button::new("Homing")
+                } else {
+                    Button::new("Home").on_press(HomeValve(id))
+                }
+            ])
+        }))
+        .push(
+            (0..TALLY_REGISTERS).fold(Row::new().spacing(10), |row, id| {
+                row.push(
+                    Button::new(Text::new(format!(
+                        "Tally {}: {:.2} ac",
+                        id + 1,
+                        row_feet_to_acres(dash.tally_feet(id))
+                    )))
+                    .on_press(ResetTally(id)),
+                )
+            }),
+        )
         .push(row![
-            Text::new("Seed wheel speed"),
-            slider(0.0..=50.0, dash.seed_wheel_speed_rpm(), |v| {
-                IOEvent(SeedWheelSpeed(v))
-            })
-            .step(0.1)
+            Button::new("Test warning").on_press(RaiseAlarm(Alarm::Test(Severity::Warning))),
+            Button::new("Test critical").on_press(RaiseAlarm(Alarm::Test(Severity::Critical))),
         ]);
 
     Container::new(body).width(Length::Fill)
 }
+
+pub fn make_jobs_page(dash: &Dash) -> Container<Message> {
+    let active = dash.job().map(|j| j.field_id);
+    let fields = dash
+        .fields()
+        .iter()
+        .fold(Column::new().spacing(5), |col, field| {
+            let acres = field
+                .acres
+                .map(|a| format!(" ({a:.1} ac)"))
+                .unwrap_or_default();
+            let marker = if active == Some(field.id) { "> " } else { "" };
+            col.push(
+                Button::new(Text::new(format!(
+                    "{marker}{} / {}{acres}",
+                    field.farm, field.name
+                )))
+                .width(Length::Fill)
+                .on_press(SelectField(field.id)),
+            )
+        });
+
+    let selected = dash
+        .variety()
+        .map(|(v, l)| (v.id, l.as_ref().map(|l| l.id)));
+    let varieties = dash
+        .varieties()
+        .iter()
+        .fold(Column::new().spacing(5), |col, variety| {
+            let mut lots: Vec<_> = dash
+                .seed_lots(variety.id)
+                .map(|l| (Some(l.id), format!(" {}", l.lot)))
+                .collect();
+            if lots.is_empty() {
+                lots.push((None, String::new()));
+            }
+            lots.into_iter().fold(col, |col, (lot, label)| {
+                let marker = if selected == Some((variety.id, lot)) {
+                    "> "
+                } else {
+                    ""
+                };
+                col.push(
+                    Button::new(Text::new(format!(
+                        "{marker}{}{label} ({:.1}\")",
+                        variety.name, variety.spacing_in
+                    )))
+                    .width(Length::Fill)
+                    .on_press(SelectVariety(variety.id, lot)),
+                )
+            })
+        });
+
+    // seed needed at the current spacing
+    let cwt = dash
+        .variety()
+        .and_then(|(v, _)| v.piece_weight_oz)
+        .map(|oz| format!(" | ~{:.1} cwt/ac", seed_cwt_per_acre(dash.population(), oz)))
+        .unwrap_or_default();
+
+    let job = match dash.job() {
+        Some(job) => Row::new()
+            .spacing(10)
+            .align_items(Alignment::Center)
+            .push(Text::new(format!(
+                "{} | {} {} | {:.1}\"{cwt} | {:.2} ac planted",
+                job.field,
+                job.variety.as_deref().unwrap_or("no variety"),
+                job.seed_lot.as_deref().unwrap_or(""),
+                job.spacing_in,
+                job.acres()
+            )))
+            .push(Space::new(Length::Fill, Length::Shrink))
+            .push(Button::new("Finish job").on_press(FinishJob))
+            .push(Button::new("Export").on_press(Message::ExportTaskData)),
+        None => Row::new()
+            .align_items(Alignment::Center)
+            .push(Text::new("Select a field before lowering the planter"))
+            .push(Space::new(Length::Fill, Length::Shrink))
+            .push(Button::new("Export").on_press(Message::ExportTaskData)),
+    };
+
+    let body = Column::new()
+        .push(make_tabs(dash))
+        .push(Container::new(job).height(HEAD_HEIGHT))
+        .push(seed_row(dash).height(HEAD_HEIGHT));
+    let body = match &dash.keypad {
+        Some(keypad) => body.push(make_keypad(keypad)),
+        None => body.push(
+            Row::new()
+                .spacing(10)
+                .push(scrollable(fields).width(Length::FillPortion(1)))
+                .push(scrollable(varieties).width(Length::FillPortion(1)))
+                .height(Length::Fill),
+        ),
+    };
+
+    Container::new(body).width(Length::Fill)
+}
+
+fn seed_row(dash: &Dash) -> Container<Message> {
+    let used = match dash.job() {
+        Some(job) => format!(
+            "Seed: {:.1} cwt{}",
+            job.seed_cwt,
+            job.cwt_per_acre()
+                .map(|c| format!(" ({c:.1} cwt/ac)"))
+                .unwrap_or_default()
+        ),
+        None => "Seed:".to_string(),
+    };
+    let empty_in = dash
+        .bin_prediction()
+        .map(|p| format!(" | empty in {:.0} min, {:.0} ft", p.minutes, p.row_feet))
+        .unwrap_or_default();
+    let ratio = dash
+        .seed_estimate_ratio()
+        .map(|r| format!(" | loaded/est {r:.2}"))
+        .unwrap_or_default();
+
+    let row = Row::new()
+        .spacing(10)
+        .align_items(Alignment::Center)
+        .push(Text::new(format!(
+            "{used} | planter {:.1} cwt{empty_in} | truck {:.1} cwt{ratio}",
+            dash.seed_cwt_onboard(),
+            dash.seed_cwt_truck()
+        )))
+        .push(Space::new(Length::Fill, Length::Shrink))
+        .push(Button::new("Truck load").on_press(Message::OpenKeypad(KeypadTarget::TruckLoad)))
+        .push(Button::new("Bulk fill").on_press(Message::OpenKeypad(KeypadTarget::BulkFill)));
+    Container::new(row).width(Length::Fill)
+}
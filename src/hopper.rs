@@ -0,0 +1,182 @@
+use crate::monitor::ROWS;
+use crate::util::{mph_to_fps, Speed};
+use std::time::{Duration, Instant};
+
+pub struct HopperCfg {
+    // time the truck needs to get to the planter
+    pub truck_lead_time: Duration,
+    // a fill running this many times longer than usual means the bulk bin is empty
+    pub starve_factor: f32,
+    // weight of the newest sample in the running averages
+    pub smoothing: f32,
+}
+
+impl Default for HopperCfg {
+    fn default() -> Self {
+        HopperCfg {
+            truck_lead_time: Duration::from_secs(20 * 60),
+            starve_factor: 3.0,
+            smoothing: 0.2,
+        }
+    }
+}
+
+/// Fill cycles of one row hopper, the seed belt runs from empty to full
+#[derive(Default)]
+struct RowHopper {
+    filling_since: Option<Instant>,
+    last_empty: Option<Instant>,
+    // running averages
+    fill_secs: Option<f32>,
+    cycle_secs: Option<f32>,
+}
+
+/// Time left before the bulk bin runs out
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Prediction {
+    pub minutes: f32,
+    pub row_feet: f32,
+}
+
+/// Tracks the hopper fill duty cycle per row and the rate seed leaves the bulk bin
+#[derive(Default)]
+pub struct Hoppers {
+    pub cfg: HopperCfg,
+    rows: [RowHopper; ROWS],
+    cwt_per_min: Option<f32>,
+    // share of the planting time each row is engaged by section control
+    on_share: [Option<f32>; ROWS],
+    // the truck alert fires once each time the prediction drops below the lead time
+    truck_called: bool,
+}
+
+fn smooth(avg: Option<f32>, sample: f32, k: f32) -> Option<f32> {
+    Some(avg.map_or(sample, |a| a + (sample - a) * k))
+}
+
+impl Hoppers {
+    pub fn empty(&mut self, row: usize, now: Instant) {
+        let k = self.cfg.smoothing;
+        let h = &mut self.rows[row];
+        if let Some(last) = h.last_empty {
+            h.cycle_secs = smooth(h.cycle_secs, (now - last).as_secs_f32(), k);
+        }
+        h.last_empty = Some(now);
+        h.filling_since = Some(now);
+    }
+
+    pub fn full(&mut self, row: usize, now: Instant) {
+        let k = self.cfg.smoothing;
+        let h = &mut self.rows[row];
+        if let Some(since) = h.filling_since.take() {
+            h.fill_secs = smooth(h.fill_secs, (now - since).as_secs_f32(), k);
+        }
+    }
+
+    // share of the time the seed belt runs
+    pub fn duty(&self, row: usize) -> Option<f32> {
+        let h = &self.rows[row];
+        Some((h.fill_secs? / h.cycle_secs?).min(1.0))
+    }
+
+    // the belt has been running far longer than a normal fill
+    pub fn starving(&self, now: Instant) -> bool {
+        self.rows
+            .iter()
+            .any(|h| match (h.filling_since, h.fill_secs) {
+                (Some(since), Some(fill)) => {
+                    (now - since).as_secs_f32() > fill * self.cfg.starve_factor
+                }
+                _ => false,
+            })
+    }
+
+    // seed used over dt while planting, and the rows that were on
+    pub fn record_usage(&mut self, cwt: f32, dt: Duration, on: [bool; ROWS]) {
+        if dt.is_zero() {
+            return;
+        }
+        let k = self.cfg.smoothing / 10.0;
+        let rate = cwt / (dt.as_secs_f32() / 60.0);
+        self.cwt_per_min = smooth(self.cwt_per_min, rate, k);
+        for (share, on) in self.on_share.iter_mut().zip(on) {
+            *share = smooth(*share, if on { 1.0 } else { 0.0 }, k);
+        }
+    }
+
+    pub fn predict(&self, onboard_cwt: f32, mph: Speed, now: Instant) -> Option<Prediction> {
+        let minutes = if self.starving(now) {
+            0.0
+        } else {
+            onboard_cwt / self.cwt_per_min.filter(|r| *r > 0.0)?
+        };
+        // the seed goes only as far as the rows that are on
+        let rows: f32 = self.on_share.iter().map(|s| s.unwrap_or(1.0)).sum();
+        Some(Prediction {
+            minutes,
+            row_feet: minutes * 60.0 * mph_to_fps(mph) * rows,
+        })
+    }
+
+    // true once when the truck should be called, re-armed when the bin is topped up
+    pub fn call_truck(&mut self, prediction: Option<Prediction>) -> bool {
+        let Some(p) = prediction else {
+            return false;
+        };
+        let low = p.minutes < self.cfg.truck_lead_time.as_secs_f32() / 60.0;
+        let call = low && !self.truck_called;
+        self.truck_called = low;
+        call
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn duty_and_prediction() {
+        let start = Instant::now();
+        let at = |s: u64| start + Duration::from_secs(s);
+        let mut h = Hoppers::default();
+
+        // 10 s fill every 40 s
+        for c in 0..4 {
+            h.empty(0, at(c * 40));
+            h.full(0, at(c * 40 + 10));
+        }
+        assert_eq!(h.duty(0), Some(0.25));
+        assert_eq!(h.duty(1), None);
+
+        assert_eq!(h.predict(10.0, 4.0, at(160)), None);
+        h.record_usage(1.0, Duration::from_secs(60), [true; ROWS]);
+        let p = h.predict(10.0, 4.0, at(160)).unwrap();
+        assert_eq!(p.minutes, 10.0);
+        assert!(h.call_truck(Some(p)));
+        assert!(!h.call_truck(Some(p)));
+
+        // the belt keeps running, the bulk bin is empty
+        h.empty(0, at(160));
+        assert!(!h.starving(at(180)));
+        assert!(h.starving(at(200)));
+        assert_eq!(h.predict(10.0, 4.0, at(200)).unwrap().minutes, 0.0);
+    }
+
+    #[test]
+    fn row_feet_follow_section_control() {
+        let mut h = Hoppers::default();
+        let now = Instant::now();
+        // row 2 released over half the ground, the usage already shows it
+        for i in 0..500 {
+            h.record_usage(0.1, Duration::from_secs(6), [true, i % 2 == 0]);
+        }
+        let p = h.predict(10.0, 4.0, now).unwrap();
+        assert!((p.minutes - 10.0).abs() < 0.01);
+        let all_rows = p.minutes * 60.0 * mph_to_fps(4.0) * ROWS as f32;
+        assert!(
+            (p.row_feet / all_rows - 0.75).abs() < 0.01,
+            "{}",
+            p.row_feet
+        );
+    }
+}
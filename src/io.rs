@@ -1,13 +1,30 @@
+use crate::actuator::{self, ActuatorCfg, Hats, Move};
+use crate::alarm::Severity;
+use crate::gps::{self, Fix};
 use crate::io::Event::{HopperEmpty, HopperFull, PlanterLowered, PlanterRaised};
-use adafruit_motorkit::dc::DcMotor;
-use adafruit_motorkit::{init_pwm, Motor};
-use crossbeam_channel::{Receiver, Sender};
+use crossbeam_channel::{Receiver, RecvTimeoutError, Sender};
 use embedded_hal::digital::OutputPin;
-use rppal::gpio::{Gpio, Trigger};
+use rppal::gpio::{Gpio, Level, Trigger};
+use rppal::i2c::I2c;
+use serde::{Deserialize, Serialize};
 use std::error::Error;
+use std::panic::{self, AssertUnwindSafe};
 use std::thread;
+use std::time::{Duration, Instant};
 
-#[derive(Default)]
+// how long to wait for the io thread to confirm outputs are safe
+pub const SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(2);
+
+const IO_THREAD: &str = "io";
+// one per flow drive, each stops its own actuator
+const FLOW_THREAD: &str = "flow";
+
+const ADC_INTERVAL: Duration = Duration::from_millis(100);
+
+// a button's contacts must be quiet this long before another press counts
+const BUTTON_DEBOUNCE: Duration = Duration::from_millis(50);
+
+#[derive(Default, Clone)]
 pub enum LiftSensor {
     #[default]
     Software,
@@ -16,18 +33,68 @@ pub enum LiftSensor {
     },
 }
 
+/// Potentiometer on the valve read through an ADS1115
+#[derive(Clone)]
+pub struct AdcCfg {
+    // 0x48 to 0x4b
+    pub addr: u16,
+    // AIN0 to AIN3
+    pub channel: u8,
+}
+
+/// Seed wheel drive, with its own encoder
+#[derive(Clone)]
+pub struct DriveCfg {
+    pub actuator: ActuatorCfg,
+    pub encoder_pin: u8,
+    // second quadrature channel, read on each tick for the direction
+    pub encoder_b_pin: Option<u8>,
+    pub position_adc: Option<AdcCfg>,
+    // rows whose seed wheels it turns
+    pub rows: Vec<usize>,
+}
+
+impl Default for DriveCfg {
+    fn default() -> Self {
+        DriveCfg {
+            actuator: Default::default(),
+            encoder_pin: 18,
+            encoder_b_pin: None,
+            position_adc: None,
+            rows: vec![0, 1],
+        }
+    }
+}
+
+#[derive(Clone)]
 pub struct IoCfg {
     pub seed_belt_pins: [u8; 2],
-    pub seed_wheel_speed_pin: u8,
+    // relay per row, energised to release the row's clutch
+    pub row_clutch_pins: [Option<u8>; 2],
+    // the belt, clutch and beacon relay boards energise on a low output, the first
+    // installations switch on high
+    pub relays_active_low: bool,
+    // one drive for the whole planter, or one per section
+    pub flow_drives: Vec<DriveCfg>,
     pub lift_sensor: LiftSensor,
+    pub buzzer_pin: Option<u8>,
+    pub beacon_pin: Option<u8>,
+    pub alarm_ack_pin: Option<u8>,
+    pub gps_port: Option<String>,
 }
 
 impl Default for IoCfg {
     fn default() -> Self {
         IoCfg {
             seed_belt_pins: [4, 5],
-            seed_wheel_speed_pin: 18,
+            row_clutch_pins: [None, None],
+            relays_active_low: false,
+            flow_drives: vec![DriveCfg::default()],
             lift_sensor: Default::default(),
+            buzzer_pin: None,
+            beacon_pin: None,
+            alarm_ack_pin: None,
+            gps_port: Some("/dev/ttyACM0".to_string()),
         }
     }
 }
@@ -38,105 +105,472 @@ impl Default for IO {
     }
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub enum Cmd {
     SeedBeltControl(usize, bool),
-    FlowThrottle(f32),
-    FlowHold,
+    // flow commands are addressed to a drive
+    FlowThrottle(usize, f32),
+    // throttle applied for a duration then released
+    FlowPulse(usize, f32, Duration),
+    FlowHold(usize),
+    // row drive engaged or released, for section shutoff
+    RowClutch(usize, bool),
     RaisePlanter,
     LowerPlanter,
+    Buzzer(Option<Severity>),
+    Beacon(bool),
+    // put all outputs in a safe state and stop the io thread
+    Shutdown,
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub enum Event {
-    SeedWheelTick,
+    // seed wheel events carry the drive
+    SeedWheelTick(usize),
+    // a tick with the wheel turning backwards, from a quadrature encoder
+    SeedWheelReversed(usize),
     PlanterRaised,
     PlanterLowered,
     GroundSpeed(f32),
-    SeedWheelSpeed(f32),
+    SeedWheelSpeed(usize, f32),
+    // raw valve pot reading, 0.0 to 1.0 of the adc range
+    ValvePosition(usize, f32),
+    // a flow actuator command failed
+    FlowError(usize, String),
     HopperEmpty(usize),
     HopperFull(usize),
+    AlarmAck,
+    GpsFix(Fix),
 }
 
 pub struct IO {
     pub tx: Sender<Cmd>,
     pub rx: Receiver<Event>,
+    // every row has a clutch, as per row section control needs
+    pub row_clutches: bool,
+    done: Receiver<()>,
+}
+
+/// Stops the io thread from outside the app, eg. from a panic hook
+#[derive(Clone)]
+pub struct ShutdownHandle {
+    tx: Sender<Cmd>,
+    done: Receiver<()>,
+}
+
+impl ShutdownHandle {
+    // true once the io thread reports the outputs are safe
+    pub fn shutdown(&self, timeout: Duration) -> bool {
+        self.tx.send(Cmd::Shutdown);
+        !matches!(
+            self.done.recv_timeout(timeout),
+            Err(RecvTimeoutError::Timeout)
+        )
+    }
+}
+
+/// Relay output, de-energised from the start
+pub struct Relay {
+    pin: rppal::gpio::OutputPin,
+    active_low: bool,
+}
+
+impl Relay {
+    pub fn new(pin: u8, active_low: bool) -> Result<Self, Box<dyn Error>> {
+        let pin = Gpio::new()?.get(pin)?;
+        let pin = if active_low {
+            pin.into_output_high()
+        } else {
+            pin.into_output_low()
+        };
+        Ok(Relay { pin, active_low })
+    }
+
+    pub fn set(&mut self, energised: bool) {
+        self.pin.write(Level::from(energised != self.active_low));
+    }
+}
+
+// threads that set their own outputs safe when they panic
+pub fn is_io_thread() -> bool {
+    let current = thread::current();
+    let name = current.name().unwrap_or_default();
+    name == IO_THREAD || name.starts_with(FLOW_THREAD)
 }
 
 impl IO {
     pub fn new(cfg: IoCfg) -> Result<Self, Box<dyn Error>> {
-        let mut belt = cfg.seed_belt_pins.map(|p| {
-            Gpio::new()
-                .expect("gpio1")
-                .get(cfg.seed_belt_pins[p as usize])
-                .expect("gpio2")
-                .into_output_low()
-        });
+        let row_clutches = cfg.row_clutch_pins.iter().all(Option::is_some);
+        let active_low = cfg.relays_active_low;
+        let [b0, b1] = cfg.seed_belt_pins;
+        let mut belt = [Relay::new(b0, active_low)?, Relay::new(b1, active_low)?];
 
-        let mut pwm = init_pwm(None)?;
-        let mut dc_motor = DcMotor::try_new(&mut pwm, Motor::Motor1)?;
+        let mut clutches = [None, None];
+        for (clutch, pin) in clutches.iter_mut().zip(cfg.row_clutch_pins) {
+            if let Some(pin) = pin {
+                *clutch = Some(Relay::new(pin, active_low)?);
+            }
+        }
 
         let (tx, crx) = crossbeam_channel::unbounded();
         let (etx, rx) = crossbeam_channel::unbounded();
 
-        let mut speed = Gpio::new()?.get(cfg.seed_wheel_speed_pin)?.into_input();
-        speed
-            .set_async_interrupt(Trigger::RisingEdge, move |x| {
-                etx.send(Event::SeedWheelTick);
-            })
-            .expect("failed to add listener to seed wheel");
-
-        thread::spawn(move || {
-            for cmd in crx {
-                match cmd {
-                    Cmd::SeedBeltControl(id, en) => {
-                        belt[id].set_state(en.into());
+        let mut hats = Hats::default();
+        let mut actuators = vec![];
+        let mut encoders = vec![];
+        // the adc pollers stop once these are dropped
+        let mut pollers = vec![];
+        for (id, drive) in cfg.flow_drives.iter().enumerate() {
+            let actuator = actuator::open(&drive.actuator, &mut hats)?;
+            let (atx, arx) = crossbeam_channel::unbounded();
+            let err_tx = etx.clone();
+            let runner = thread::Builder::new()
+                .name(format!("{FLOW_THREAD} {id}"))
+                .spawn(move || {
+                    actuator::run(actuator, arx, |e| {
+                        eprintln!("flow {id}: {e}");
+                        err_tx.send(Event::FlowError(id, e.to_string()));
+                    })
+                })?;
+            actuators.push((atx, runner));
+
+            let mut encoder = Gpio::new()?.get(drive.encoder_pin)?.into_input();
+            let tick_tx = etx.clone();
+            let b = match drive.encoder_b_pin {
+                Some(pin) => Some(Gpio::new()?.get(pin)?.into_input()),
+                None => None,
+            };
+            // b trails a going forward, so it is still low on a's rising edge
+            encoder.set_async_interrupt(Trigger::RisingEdge, move |_| {
+                if b.as_ref().map_or(false, |b| b.is_high()) {
+                    tick_tx.send(Event::SeedWheelReversed(id));
+                } else {
+                    tick_tx.send(Event::SeedWheelTick(id));
+                }
+            })?;
+            encoders.push(encoder);
+
+            if let Some(adc) = drive.position_adc.clone() {
+                let mut i2c = I2c::new()?;
+                i2c.set_slave_address(adc.addr)?;
+                let etx = etx.clone();
+                let (stop_tx, stop) = crossbeam_channel::bounded::<()>(0);
+                pollers.push(stop_tx);
+                thread::spawn(move || loop {
+                    match read_ads1115(&mut i2c, adc.channel) {
+                        Ok(v) => {
+                            etx.send(Event::ValvePosition(id, v));
+                        }
+                        Err(e) => eprintln!("valve {id} position: {e}"),
                     }
-                    Cmd::FlowThrottle(rate) => {
-                        dc_motor.set_throttle(&mut pwm, rate).unwrap();
+                    if stop.recv_timeout(ADC_INTERVAL) != Err(RecvTimeoutError::Timeout) {
+                        break;
                     }
-                    Cmd::FlowHold => {
-                        dc_motor.stop(&mut pwm);
+                });
+            }
+        }
+
+        let buzzer = match cfg.buzzer_pin {
+            Some(pin) => {
+                let pin = Gpio::new()?.get(pin)?.into_output_low();
+                let (btx, brx) = crossbeam_channel::unbounded();
+                thread::spawn(move || run_buzzer(pin, brx));
+                Some(btx)
+            }
+            None => None,
+        };
+
+        let mut beacon = match cfg.beacon_pin {
+            Some(pin) => Some(Relay::new(pin, active_low)?),
+            None => None,
+        };
+
+        let ack_button = match cfg.alarm_ack_pin {
+            Some(pin) => {
+                let mut button = Gpio::new()?.get(pin)?.into_input_pullup();
+                let etx = etx.clone();
+                let mut debounce = Debounce::new(BUTTON_DEBOUNCE);
+                button.set_async_interrupt(Trigger::FallingEdge, move |_| {
+                    if debounce.press(Instant::now()) {
+                        etx.send(Event::AlarmAck);
                     }
-                    _ => {}
-                }
+                })?;
+                Some(button)
             }
-        });
+            None => None,
+        };
 
-        Ok(IO { tx, rx })
+        if let Some(port) = cfg.gps_port.clone() {
+            let etx = etx.clone();
+            thread::spawn(move || {
+                let r = gps::read_fixes(&port, |fix| {
+                    etx.send(Event::GroundSpeed(fix.speed_mph));
+                    etx.send(Event::GpsFix(fix));
+                });
+                if let Err(e) = r {
+                    eprintln!("gps on {port} stopped: {e}");
+                }
+            });
+        }
+
+        let (done_tx, done) = crossbeam_channel::bounded::<()>(0);
+        thread::Builder::new()
+            .name(IO_THREAD.into())
+            .spawn(move || {
+                // the interrupts are removed when the pins are dropped
+                let _ack_button = ack_button;
+                let _encoders = encoders;
+                let _pollers = pollers;
+
+                let r = panic::catch_unwind(AssertUnwindSafe(|| {
+                    let move_flow = |id: usize, m: Move| match actuators.get(id) {
+                        Some((tx, _)) => {
+                            tx.send(m).ok();
+                        }
+                        None => eprintln!("no flow drive {id}"),
+                    };
+                    for cmd in crx.iter() {
+                        match cmd {
+                            Cmd::SeedBeltControl(id, en) => match belt.get_mut(id) {
+                                Some(belt) => belt.set(en),
+                                None => eprintln!("no seed belt {id}"),
+                            },
+                            Cmd::FlowThrottle(id, rate) => move_flow(id, Move::Throttle(rate)),
+                            Cmd::FlowPulse(id, rate, duration) => {
+                                move_flow(id, Move::Pulse(rate, duration))
+                            }
+                            Cmd::FlowHold(id) => move_flow(id, Move::Hold),
+                            Cmd::RowClutch(id, engaged) => match clutches.get_mut(id) {
+                                Some(Some(clutch)) => clutch.set(!engaged),
+                                Some(None) => {}
+                                None => eprintln!("no row clutch {id}"),
+                            },
+                            Cmd::Buzzer(pattern) => {
+                                if let Some(buzzer) = &buzzer {
+                                    buzzer.send(pattern);
+                                }
+                            }
+                            Cmd::Beacon(on) => {
+                                if let Some(beacon) = beacon.as_mut() {
+                                    beacon.set(on);
+                                }
+                            }
+                            Cmd::Shutdown => break,
+                            _ => {}
+                        }
+                    }
+                }));
+                if r.is_err() {
+                    eprintln!("io thread panicked, setting outputs safe");
+                }
+
+                // flow actuators stop once their channels close, belt relays and beacon
+                // de-energised, the buzzer goes quiet when its channel closes
+                let (senders, runners): (Vec<_>, Vec<_>) = actuators.into_iter().unzip();
+                drop(senders);
+                for runner in runners {
+                    runner.join().ok();
+                }
+                belt.iter_mut().for_each(|b| b.set(false));
+                clutches.iter_mut().flatten().for_each(|c| c.set(false));
+                if let Some(beacon) = beacon.as_mut() {
+                    beacon.set(false);
+                }
+                drop(buzzer);
+                // closing the channel answers every shutdown call, however many are made
+                drop(done_tx);
+            })?;
+
+        Ok(IO {
+            tx,
+            rx,
+            row_clutches,
+            done,
+        })
     }
 
     pub fn fake(cfg: IoCfg) -> Result<Self, Box<dyn Error>> {
+        let row_clutches = cfg.row_clutch_pins.iter().all(Option::is_some);
         let (tx, crx) = crossbeam_channel::unbounded();
         let (etx, rx) = crossbeam_channel::unbounded();
 
         // todo;; timer to simulate wheel speed
 
-        thread::spawn(move || {
-            for cmd in crx {
-                match cmd {
-                    Cmd::SeedBeltControl(id, en) => {
-                        println!("Belt {id} {}", if en { "enabled" } else { "disabled" });
-                        etx.send(if en { HopperEmpty(id) } else { HopperFull(id) });
-                    }
-                    Cmd::FlowThrottle(rate) => {
-                        println!("Flow rate set to {rate}")
-                    }
-                    Cmd::FlowHold => {
-                        println!("Flow rate stopped")
-                    }
-                    Cmd::RaisePlanter => {
-                        etx.send(PlanterRaised);
-                        println!("Raise planter")
-                    }
-                    Cmd::LowerPlanter => {
-                        etx.send(PlanterLowered);
-                        println!("Lower planter")
+        let (done_tx, done) = crossbeam_channel::bounded::<()>(0);
+        thread::Builder::new()
+            .name(IO_THREAD.into())
+            .spawn(move || {
+                for cmd in crx.iter() {
+                    match cmd {
+                        Cmd::SeedBeltControl(id, en) => {
+                            println!("Belt {id} {}", if en { "enabled" } else { "disabled" });
+                            etx.send(if en { HopperEmpty(id) } else { HopperFull(id) });
+                        }
+                        Cmd::FlowThrottle(id, rate) => {
+                            println!("Flow {id} rate set to {rate}")
+                        }
+                        Cmd::FlowPulse(id, rate, duration) => {
+                            println!("Flow {id} pulsed {rate} for {duration:?}")
+                        }
+                        Cmd::FlowHold(id) => {
+                            println!("Flow {id} rate stopped")
+                        }
+                        Cmd::RowClutch(id, engaged) => {
+                            println!("Row {id} {}", if engaged { "engaged" } else { "released" })
+                        }
+                        Cmd::RaisePlanter => {
+                            etx.send(PlanterRaised);
+                            println!("Raise planter")
+                        }
+                        Cmd::LowerPlanter => {
+                            etx.send(PlanterLowered);
+                            println!("Lower planter")
+                        }
+                        Cmd::Buzzer(pattern) => {
+                            println!("Buzzer {pattern:?}")
+                        }
+                        Cmd::Beacon(on) => {
+                            println!("Beacon {}", if on { "on" } else { "off" })
+                        }
+                        Cmd::Shutdown => break,
                     }
                 }
+                println!("Outputs safe");
+                drop(done_tx);
+            })?;
+
+        Ok(IO {
+            tx,
+            rx,
+            row_clutches,
+            done,
+        })
+    }
+
+    pub fn shutdown_handle(&self) -> ShutdownHandle {
+        ShutdownHandle {
+            tx: self.tx.clone(),
+            done: self.done.clone(),
+        }
+    }
+
+    pub fn shutdown(&self, timeout: Duration) -> bool {
+        self.shutdown_handle().shutdown(timeout)
+    }
+
+    // no thread behind the channels, the commands sent are handed back to look at
+    #[cfg(test)]
+    pub fn detached() -> (Self, Sender<Event>, Receiver<Cmd>) {
+        let (tx, crx) = crossbeam_channel::unbounded();
+        let (etx, rx) = crossbeam_channel::unbounded();
+        let (_, done) = crossbeam_channel::bounded(0);
+        let io = IO {
+            tx,
+            rx,
+            row_clutches: true,
+            done,
+        };
+        (io, etx, crx)
+    }
+}
+
+/// Contact bounce filter, one press for a burst of edges
+pub struct Debounce {
+    quiet: Duration,
+    last_edge: Option<Instant>,
+}
+
+impl Debounce {
+    pub fn new(quiet: Duration) -> Self {
+        Debounce {
+            quiet,
+            last_edge: None,
+        }
+    }
+
+    // an edge is a press when none came in the quiet time before it
+    pub fn press(&mut self, now: Instant) -> bool {
+        let quiet = self
+            .last_edge
+            .map_or(true, |t| now.saturating_duration_since(t) >= self.quiet);
+        self.last_edge = Some(now);
+        quiet
+    }
+}
+
+// single shot conversion against ground, +/-4.096 V range
+fn read_ads1115(i2c: &mut I2c, channel: u8) -> Result<f32, Box<dyn Error>> {
+    let mux = 0x4 | (channel as u16 & 0x3);
+    let config: u16 = 0x8000 | mux << 12 | 0x0300 | 0x0080 | 0x0003;
+    let [hi, lo] = config.to_be_bytes();
+    i2c.write(&[0x01, hi, lo])?;
+    // 128 samples per second
+    thread::sleep(Duration::from_millis(9));
+    let mut buf = [0; 2];
+    i2c.write_read(&[0x00], &mut buf)?;
+    Ok(i16::from_be_bytes(buf).max(0) as f32 / i16::MAX as f32)
+}
+
+// plays the beep pattern of the latest severity until silenced with None
+fn run_buzzer(mut pin: rppal::gpio::OutputPin, rx: Receiver<Option<Severity>>) {
+    let mut pattern: &[(bool, Duration)] = &[];
+    let mut step = 0;
+    loop {
+        let next = if pattern.is_empty() {
+            pin.set_low();
+            rx.recv().ok()
+        } else {
+            let (on, duration) = pattern[step % pattern.len()];
+            if on {
+                pin.set_high();
+            } else {
+                pin.set_low();
+            }
+            step += 1;
+            match rx.recv_timeout(duration) {
+                Ok(p) => Some(p),
+                Err(RecvTimeoutError::Timeout) => continue,
+                Err(RecvTimeoutError::Disconnected) => None,
             }
-        });
+        };
+
+        match next {
+            Some(severity) => {
+                pattern = severity.map(|s| s.beep_pattern()).unwrap_or(&[]);
+                step = 0;
+            }
+            None => {
+                pin.set_low();
+                return;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn button_bounce_is_one_press() {
+        let mut d = Debounce::new(BUTTON_DEBOUNCE);
+        let t = Instant::now();
+        let ms = Duration::from_millis;
+        assert!(d.press(t));
+        // the contacts chatter for a while
+        for i in 1..10 {
+            assert!(!d.press(t + ms(i * 10)));
+        }
+        assert!(d.press(t + ms(90) + BUTTON_DEBOUNCE));
+    }
 
-        Ok(IO { tx, rx })
+    #[test]
+    fn shutdown_answers_every_call() {
+        let io = IO::fake(IoCfg::default()).unwrap();
+        let start = Instant::now();
+        assert!(io.shutdown(SHUTDOWN_TIMEOUT));
+        // eg. the panic hook after the app has already shut down
+        assert!(io.shutdown_handle().shutdown(SHUTDOWN_TIMEOUT));
+        assert!(start.elapsed() < SHUTDOWN_TIMEOUT);
     }
 }
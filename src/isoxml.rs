@@ -0,0 +1,591 @@
+use crate::asplanted::{has_planter, starts_lowered, xml_escape};
+use crate::db::Field;
+use crate::io::Event;
+use crate::log::{read_session, Entry, Record};
+use crate::monitor::ROWS;
+use crate::util::{spacing_to_population, tickrate_to_spacing};
+use chrono::{NaiveDate, TimeZone, Utc};
+use std::collections::HashMap;
+use std::fmt::Write as _;
+use std::fs::{self, File};
+use std::io::{self, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+const SQ_M_PER_ACRE: f32 = 4046.856;
+
+// data dictionary identifiers, ISO 11783-11
+const DDI_SETPOINT_COUNT_PER_AREA: u16 = 0x000B;
+const DDI_ACTUAL_COUNT_PER_AREA: u16 = 0x000C;
+const DDI_ACTUAL_WORKING_WIDTH: u16 = 0x0043;
+const DDI_ACTUAL_WORK_STATE: u16 = 0x008D;
+
+// logged with every position, in this order
+const LOGGED: [(u16, &str); 4] = [
+    (DDI_SETPOINT_COUNT_PER_AREA, "Setpoint seed rate"),
+    (DDI_ACTUAL_COUNT_PER_AREA, "Actual seed rate"),
+    (DDI_ACTUAL_WORKING_WIDTH, "Working width"),
+    (DDI_ACTUAL_WORK_STATE, "Work state"),
+];
+
+/// A planting task, one field's session log reduced to what is exported
+#[derive(Clone)]
+pub struct TaskSource {
+    pub field: String,
+    pub field_id: Option<i64>,
+    pub farm: Option<String>,
+    pub area_m2: u64,
+    varieties: Vec<String>,
+    start_ms: u64,
+    stop_ms: u64,
+    records: Vec<LogRecord>,
+}
+
+impl TaskSource {
+    // the field is taken from the job records in the log
+    pub fn from_session(entries: &[Entry], row_width_in: f32) -> TaskSource {
+        let (field, field_id) = entries
+            .iter()
+            .find_map(|e| match &e.record {
+                Record::Job {
+                    field: Some(field),
+                    field_id,
+                    ..
+                } => Some((field.clone(), *field_id)),
+                _ => None,
+            })
+            .unwrap_or_else(|| ("unassigned".to_string(), None));
+        TaskSource {
+            field,
+            field_id,
+            farm: None,
+            area_m2: 0,
+            varieties: varieties(entries),
+            start_ms: entries.first().map(|e| e.wall_ms).unwrap_or_default(),
+            stop_ms: entries.last().map(|e| e.wall_ms).unwrap_or_default(),
+            records: log_records(entries, row_width_in),
+        }
+    }
+
+    // by id, logs from before field ids only by a name no other field has
+    fn match_field<'a>(&self, fields: &'a [Field]) -> Option<&'a Field> {
+        match self.field_id {
+            Some(id) => fields.iter().find(|f| f.id == id),
+            None => {
+                let mut named = fields.iter().filter(|f| f.name == self.field);
+                named.next().filter(|_| named.next().is_none())
+            }
+        }
+    }
+}
+
+fn varieties(entries: &[Entry]) -> Vec<String> {
+    let mut out: Vec<String> = vec![];
+    for e in entries {
+        if let Record::Job {
+            variety: Some(v), ..
+        } = &e.record
+        {
+            if !out.contains(v) {
+                out.push(v.clone());
+            }
+        }
+    }
+    out
+}
+
+/// One binary time log record
+#[derive(Debug, Clone, PartialEq)]
+struct LogRecord {
+    wall_ms: u64,
+    lat: f64,
+    lon: f64,
+    values: [i32; LOGGED.len()],
+}
+
+fn seeds_per_m2_milli(spacing_in: f32, row_width_in: f32) -> i32 {
+    if spacing_in <= 0.0 {
+        return 0;
+    }
+    (spacing_to_population(spacing_in, row_width_in) / SQ_M_PER_ACRE * 1000.0) as i32
+}
+
+fn log_records(entries: &[Entry], row_width_in: f32) -> Vec<LogRecord> {
+    let mut lowered = starts_lowered(entries);
+    let mut spacing = 0.0;
+    let mut tickrate = None;
+    let width_mm = (ROWS as f32 * row_width_in * 25.4) as i32;
+    let planter = has_planter(entries);
+
+    let mut out = vec![];
+    for e in entries {
+        match &e.record {
+            Record::Event(Event::PlanterLowered) => lowered = true,
+            Record::Event(Event::PlanterRaised) => lowered = false,
+            Record::Job { spacing_in, .. } => spacing = *spacing_in,
+            Record::Control {
+                actual_tickrate, ..
+            } => tickrate = Some(*actual_tickrate),
+            Record::Fix(fix) | Record::Planter(fix)
+                if matches!(e.record, Record::Planter(_)) == planter =>
+            {
+                let actual = tickrate
+                    .and_then(|t| tickrate_to_spacing(t, fix.speed_mph))
+                    .filter(|_| lowered)
+                    .map_or(0, |s| seeds_per_m2_milli(s, row_width_in));
+                out.push(LogRecord {
+                    wall_ms: e.wall_ms,
+                    lat: fix.latitude,
+                    lon: fix.longitude,
+                    values: [
+                        seeds_per_m2_milli(spacing, row_width_in),
+                        actual,
+                        width_mm,
+                        lowered as i32,
+                    ],
+                });
+            }
+            _ => {}
+        }
+    }
+    out
+}
+
+// time of day and days since 1980-01-01, UTC
+fn tlg_time(wall_ms: u64) -> (u32, u16) {
+    let epoch = NaiveDate::from_ymd_opt(1980, 1, 1)
+        .unwrap()
+        .and_hms_opt(0, 0, 0)
+        .unwrap()
+        .and_utc()
+        .timestamp_millis() as u64;
+    let ms = wall_ms.saturating_sub(epoch);
+    ((ms % 86_400_000) as u32, (ms / 86_400_000) as u16)
+}
+
+fn write_tlg_bin(records: &[LogRecord], out: &mut impl Write) -> io::Result<()> {
+    for r in records {
+        let (ms, days) = tlg_time(r.wall_ms);
+        out.write_all(&ms.to_le_bytes())?;
+        out.write_all(&days.to_le_bytes())?;
+        out.write_all(&((r.lat * 1e7).round() as i32).to_le_bytes())?;
+        out.write_all(&((r.lon * 1e7).round() as i32).to_le_bytes())?;
+        // GNSS fix
+        out.write_all(&[1])?;
+        out.write_all(&[r.values.len() as u8])?;
+        for (i, v) in r.values.iter().enumerate() {
+            out.write_all(&[i as u8])?;
+            out.write_all(&v.to_le_bytes())?;
+        }
+    }
+    Ok(())
+}
+
+// empty attributes are filled from the binary file
+fn tlg_header() -> String {
+    let mut s = String::new();
+    s.push_str("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n");
+    s.push_str("<TIM A=\"\" D=\"4\">\n<PTN A=\"\" B=\"\" D=\"\"/>\n");
+    for (ddi, _) in LOGGED {
+        writeln!(s, "<DLV A=\"{ddi:04X}\" B=\"\" C=\"DET1\"/>").unwrap();
+    }
+    s.push_str("</TIM>\n");
+    s
+}
+
+fn iso_time(wall_ms: u64) -> String {
+    Utc.timestamp_millis_opt(wall_ms as i64)
+        .single()
+        .map(|t| t.format("%Y-%m-%dT%H:%M:%S").to_string())
+        .unwrap_or_default()
+}
+
+/// How the planter names itself in exported task data
+pub struct DeviceCfg {
+    // ISO 11783-5 NAME, the identity number unique to this planter
+    pub manufacturer_code: u16,
+    pub identity_number: u32,
+    pub serial: String,
+    // ISO 639 language of the designators
+    pub language: [u8; 2],
+}
+
+impl Default for DeviceCfg {
+    fn default() -> Self {
+        DeviceCfg {
+            manufacturer_code: 0,
+            identity_number: 1,
+            serial: "1".to_string(),
+            language: *b"en",
+        }
+    }
+}
+
+impl DeviceCfg {
+    // the Pi's serial number tells one planter from another
+    pub fn local() -> DeviceCfg {
+        let serial = fs::read_to_string("/proc/cpuinfo")
+            .unwrap_or_default()
+            .lines()
+            .filter(|l| l.starts_with("Serial"))
+            .find_map(|l| l.split(':').nth(1))
+            .map(|s| s.trim().to_string())
+            .filter(|s| !s.is_empty());
+        let default = DeviceCfg::default();
+        match serial {
+            Some(serial) => DeviceCfg {
+                identity_number: u64::from_str_radix(&serial, 16).unwrap_or(1) as u32 & 0x1F_FFFF,
+                serial,
+                ..default
+            },
+            None => default,
+        }
+    }
+
+    // self-configurable, agricultural industry group, planters/seeders device class,
+    // rate control function
+    fn client_name(&self) -> String {
+        let name: u64 = 1 << 63
+            | 2 << 60
+            | 4 << 49
+            | 128 << 40
+            | (self.manufacturer_code as u64 & 0x7FF) << 21
+            | (self.identity_number as u64 & 0x1F_FFFF);
+        format!("{name:016X}")
+    }
+
+    // metric units throughout, the last byte reserved
+    fn localization_label(&self) -> String {
+        let [a, b] = self.language;
+        format!("{a:02X}{b:02X}50000000FF")
+    }
+}
+
+// FNV-1a, stable between builds unlike the std hasher
+fn fnv(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xcbf2_9ce4_8422_2325, |h, b| {
+        (h ^ *b as u64).wrapping_mul(0x0100_0000_01b3)
+    })
+}
+
+// a single device element carrying the logged process data
+fn device(cfg: &DeviceCfg) -> String {
+    let mut body = String::new();
+    body.push_str("<DET A=\"DET1\" B=\"1\" C=\"1\" D=\"Planter\" E=\"0\" F=\"0\">\n");
+    for i in 0..LOGGED.len() {
+        writeln!(body, "<DOR A=\"{}\"/>", 10 + i).unwrap();
+    }
+    body.push_str("</DET>\n");
+    for (i, (ddi, name)) in LOGGED.iter().enumerate() {
+        writeln!(
+            body,
+            "<DPD A=\"{}\" B=\"{ddi:04X}\" C=\"1\" D=\"9\" E=\"{name}\"/>",
+            10 + i
+        )
+        .unwrap();
+    }
+    // the structure label changes with the description, seven bytes
+    let structure = fnv(body.as_bytes()) & 0xFF_FFFF_FFFF_FFFF;
+
+    let mut s = String::new();
+    writeln!(
+        s,
+        "<DVC A=\"DVC1\" B=\"popl planter\" C=\"{}\" D=\"{}\" E=\"{}\" F=\"{structure:014X}\" G=\"{}\">",
+        env!("CARGO_PKG_VERSION"),
+        cfg.client_name(),
+        xml_escape(&cfg.serial),
+        cfg.localization_label(),
+    )
+    .unwrap();
+    s.push_str(&body);
+    s.push_str("</DVC>\n");
+    s
+}
+
+/// Writes TASKDATA/TASKDATA.XML and a TLGnnnnn.XML/.BIN pair per task under dir
+pub fn write_taskdata(dir: &Path, tasks: &[TaskSource], cfg: &DeviceCfg) -> io::Result<PathBuf> {
+    let dir = dir.join("TASKDATA");
+    fs::create_dir_all(&dir)?;
+
+    let mut farms: Vec<String> = vec![];
+    let mut products: Vec<String> = vec![];
+    for t in tasks {
+        if let Some(f) = &t.farm {
+            if !farms.contains(f) {
+                farms.push(f.clone());
+            }
+        }
+        for v in &t.varieties {
+            if !products.contains(v) {
+                products.push(v.clone());
+            }
+        }
+    }
+
+    let mut xml = String::new();
+    xml.push_str("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n");
+    xml.push_str(
+        "<ISO11783_TaskData VersionMajor=\"4\" VersionMinor=\"2\" ManagementSoftwareManufacturer=\"\" ManagementSoftwareVersion=\"\" TaskControllerManufacturer=\"popl\" TaskControllerVersion=\"0.1.0\" DataTransferOrigin=\"2\">\n",
+    );
+    for (i, f) in farms.iter().enumerate() {
+        writeln!(xml, "<FRM A=\"FRM{}\" B=\"{}\"/>", i + 1, xml_escape(f)).unwrap();
+    }
+    for (i, p) in products.iter().enumerate() {
+        writeln!(xml, "<PDT A=\"PDT{}\" B=\"{}\"/>", i + 1, xml_escape(p)).unwrap();
+    }
+    xml.push_str(&device(cfg));
+
+    for (i, t) in tasks.iter().enumerate() {
+        let n = i + 1;
+        let farm = t
+            .farm
+            .as_ref()
+            .and_then(|f| farms.iter().position(|x| x == f))
+            .map(|i| format!(" F=\"FRM{}\"", i + 1))
+            .unwrap_or_default();
+        writeln!(
+            xml,
+            "<PFD A=\"PFD{n}\" C=\"{}\" D=\"{}\"{farm}/>",
+            xml_escape(&t.field),
+            t.area_m2
+        )
+        .unwrap();
+
+        let started = iso_time(t.start_ms);
+        let date = started.split('T').next().unwrap_or_default();
+        // status 4, completed
+        writeln!(
+            xml,
+            "<TSK A=\"TSK{n}\" B=\"{} {date}\" E=\"PFD{n}\" G=\"4\">",
+            xml_escape(&t.field),
+        )
+        .unwrap();
+        writeln!(
+            xml,
+            "<TIM A=\"{started}\" B=\"{}\" D=\"4\"/>",
+            iso_time(t.stop_ms)
+        )
+        .unwrap();
+        for v in &t.varieties {
+            let p = products.iter().position(|x| x == v).unwrap_or_default();
+            writeln!(xml, "<PAN A=\"PDT{}\"/>", p + 1).unwrap();
+        }
+        writeln!(xml, "<DAN A=\"{}\" C=\"DVC1\"/>", cfg.client_name()).unwrap();
+        if !t.records.is_empty() {
+            let name = format!("TLG{n:05}");
+            writeln!(xml, "<TLG A=\"{name}\"/>").unwrap();
+            fs::write(dir.join(format!("{name}.XML")), tlg_header())?;
+            let mut bin = BufWriter::new(File::create(dir.join(format!("{name}.BIN")))?);
+            write_tlg_bin(&t.records, &mut bin)?;
+            bin.flush()?;
+        }
+        xml.push_str("</TSK>\n");
+    }
+    xml.push_str("</ISO11783_TaskData>\n");
+
+    fs::write(dir.join("TASKDATA.XML"), xml)?;
+    Ok(dir)
+}
+
+// a session is read again only once its log has changed
+struct Cached {
+    modified: Option<SystemTime>,
+    len: u64,
+    row_width_in: f32,
+    task: TaskSource,
+}
+
+/// Sessions read by earlier exports
+#[derive(Default)]
+pub struct SessionCache {
+    tasks: HashMap<PathBuf, Cached>,
+}
+
+impl SessionCache {
+    fn task(&mut self, path: &Path, row_width_in: f32) -> io::Result<TaskSource> {
+        let meta = fs::metadata(path)?;
+        let (modified, len) = (meta.modified().ok(), meta.len());
+        if let Some(c) = self.tasks.get(path) {
+            if modified.is_some()
+                && c.modified == modified
+                && c.len == len
+                && c.row_width_in == row_width_in
+            {
+                return Ok(c.task.clone());
+            }
+        }
+        let task = TaskSource::from_session(&read_session(path)?, row_width_in);
+        self.tasks.insert(
+            path.to_path_buf(),
+            Cached {
+                modified,
+                len,
+                row_width_in,
+                task: task.clone(),
+            },
+        );
+        Ok(task)
+    }
+}
+
+/// Exports every session log in session_dir as a task, farm and area come from
+/// the matching field
+pub fn export_sessions(
+    cache: &mut SessionCache,
+    session_dir: &Path,
+    out_dir: &Path,
+    row_width_in: f32,
+    fields: &[Field],
+    device: &DeviceCfg,
+) -> io::Result<PathBuf> {
+    let mut sessions: Vec<PathBuf> = fs::read_dir(session_dir)?
+        .filter_map(|e| e.ok().map(|e| e.path()))
+        .filter(|p| p.extension().map_or(false, |e| e == "jsonl"))
+        .collect();
+    sessions.sort();
+    // logs since deleted are dropped
+    cache.tasks.retain(|p, _| sessions.contains(p));
+
+    let mut tasks = vec![];
+    for path in sessions {
+        let mut task = cache.task(&path, row_width_in)?;
+        if let Some(f) = task.match_field(fields) {
+            task.farm = Some(f.farm.clone());
+            task.area_m2 = (f.acres.unwrap_or_default() * SQ_M_PER_ACRE) as u64;
+        }
+        tasks.push(task);
+    }
+    write_taskdata(out_dir, &tasks, device)
+}
+
+/// Mounted removable drives
+pub fn usb_mounts() -> Vec<PathBuf> {
+    fs::read_to_string("/proc/mounts")
+        .unwrap_or_default()
+        .lines()
+        .filter_map(|l| l.split_whitespace().nth(1))
+        .filter(|m| m.starts_with("/media/") || m.starts_with("/run/media/"))
+        // spaces in mount points are escaped as \040
+        .map(|m| PathBuf::from(m.replace("\\040", " ")))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::gps::Fix;
+    use crate::util::TempDir;
+
+    fn entry(wall_ms: u64, record: Record) -> Entry {
+        Entry {
+            mono_ms: wall_ms,
+            wall_ms,
+            record,
+        }
+    }
+
+    fn fix() -> Record {
+        Record::Fix(Fix {
+            latitude: 46.8,
+            longitude: -100.7,
+            speed_mph: 3.0,
+            heading: None,
+        })
+    }
+
+    #[test]
+    fn time_log() {
+        // 1980-01-02 00:00:01 UTC
+        assert_eq!(tlg_time(315_532_800_000 + 86_400_000 + 1000), (1000, 1));
+
+        let task = TaskSource::from_session(
+            &[
+                entry(
+                    0,
+                    Record::Job {
+                        field: Some("North 40".into()),
+                        field_id: Some(3),
+                        variety: Some("Russet".into()),
+                        seed_lot: None,
+                        spacing_in: 10.0,
+                    },
+                ),
+                entry(1, fix()),
+                entry(2, Record::Event(Event::PlanterLowered)),
+                entry(
+                    3,
+                    Record::Control {
+                        drive: 0,
+                        target_tickrate: 75,
+                        actual_tickrate: 75,
+                        throttle: None,
+                    },
+                ),
+                entry(4, fix()),
+            ],
+            34.0,
+        );
+        assert_eq!(task.field, "North 40");
+        assert_eq!(task.field_id, Some(3));
+        assert_eq!(task.varieties, vec!["Russet".to_string()]);
+
+        let records = &task.records;
+        assert_eq!(records.len(), 2);
+        // 18449 seeds/ac is 4.559 per square metre
+        assert_eq!(records[0].values[0], 4558);
+        assert_eq!(records[0].values[1], 0);
+        assert_eq!(records[0].values[2], 1727);
+        assert_eq!(records[1].values[3], 1);
+        assert!(records[1].values[1] > 0);
+
+        let mut bin = vec![];
+        write_tlg_bin(records, &mut bin).unwrap();
+        // time, position, status, count and 4 values
+        assert_eq!(bin.len(), 2 * (6 + 8 + 1 + 1 + 4 * 5));
+    }
+
+    #[test]
+    fn taskdata_files() {
+        let dir = TempDir::new("isoxml");
+        let task = TaskSource::from_session(&[entry(1, fix()), entry(2, fix())], 34.0);
+        let out = write_taskdata(dir.path(), &[task], &DeviceCfg::default()).unwrap();
+        let xml = fs::read_to_string(out.join("TASKDATA.XML")).unwrap();
+        assert!(xml.contains("<PFD A=\"PFD1\" C=\"unassigned\""));
+        assert!(xml.contains("D=\"A008800000000001\""));
+        assert!(xml.contains("<DAN A=\"A008800000000001\" C=\"DVC1\"/>"));
+        assert!(xml.contains("<TLG A=\"TLG00001\"/>"));
+        assert!(out.join("TLG00001.BIN").exists());
+    }
+
+    fn field(id: i64, farm: &str, name: &str) -> Field {
+        Field {
+            id,
+            farm: farm.into(),
+            name: name.into(),
+            acres: None,
+        }
+    }
+
+    #[test]
+    fn fields_match_by_id() {
+        let fields = [field(1, "Home", "North 40"), field(2, "River", "North 40")];
+        let job = |field_id| {
+            entry(
+                0,
+                Record::Job {
+                    field: Some("North 40".into()),
+                    field_id,
+                    variety: None,
+                    seed_lot: None,
+                    spacing_in: 10.0,
+                },
+            )
+        };
+        let task = TaskSource::from_session(&[job(Some(2))], 34.0);
+        assert_eq!(task.match_field(&fields).map(|f| f.id), Some(2));
+        // an older log, the name is shared
+        let task = TaskSource::from_session(&[job(None)], 34.0);
+        assert!(task.match_field(&fields).is_none());
+        assert_eq!(task.match_field(&fields[..1]).map(|f| f.id), Some(1));
+    }
+}
@@ -0,0 +1,336 @@
+use crate::util;
+use serde::{Deserialize, Serialize};
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Read, Write};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+pub const TALLY_REGISTERS: usize = 3;
+const LOG_TAIL_LINES: usize = 20;
+
+/// Counters and state that must survive the tractor key being turned off
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct Counters {
+    // lifetime row feet
+    pub feet_planted: f32,
+    // resettable row feet registers
+    pub tally_feet: [f32; TALLY_REGISTERS],
+    // the active job in the field database
+    #[serde(default)]
+    pub job_id: Option<i64>,
+    // kept here so a pass cut short by the power going off is not lost
+    #[serde(default)]
+    pub pass: Option<OpenPass>,
+    // lifetime estimate of seed used, and what is left on the planter and truck
+    #[serde(default)]
+    pub seed_cwt: f32,
+    #[serde(default)]
+    pub onboard_cwt: f32,
+    #[serde(default)]
+    pub truck_cwt: f32,
+    pub log_tail: Vec<String>,
+}
+
+/// Pass in progress, from lowering the planter until it is raised
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct OpenPass {
+    pub started_at: String,
+    // when it was last added to, its end if the power goes off
+    pub updated_at: String,
+    pub row_feet: f32,
+    // ground travelled, whatever the sections
+    #[serde(default)]
+    pub feet: f32,
+    pub speed_sum: f32,
+    pub samples: u32,
+    pub seed_cwt: f32,
+    // spacing ratio over the first feet, while the seed wheel comes up to speed
+    pub start_ratio_sum: f32,
+    pub start_samples: u32,
+}
+
+impl Counters {
+    pub fn add_feet(&mut self, ft: f32) {
+        self.feet_planted += ft;
+        self.tally_feet.iter_mut().for_each(|t| *t += ft);
+    }
+
+    pub fn add_seed(&mut self, cwt: f32) {
+        self.seed_cwt += cwt;
+        self.onboard_cwt = (self.onboard_cwt - cwt).max(0.0);
+    }
+
+    pub fn reset_tally(&mut self, id: usize) {
+        self.tally_feet[id] = 0.0;
+    }
+
+    pub fn push_log(&mut self, line: String) {
+        self.log_tail.push(line);
+        if self.log_tail.len() > LOG_TAIL_LINES {
+            self.log_tail.remove(0);
+        }
+    }
+}
+
+pub struct JournalCfg {
+    // changes are batched to limit SD card wear
+    pub write_interval: Duration,
+    // the journal is rewritten as a single record past this size
+    pub compact_bytes: u64,
+}
+
+impl Default for JournalCfg {
+    fn default() -> Self {
+        JournalCfg {
+            write_interval: Duration::from_secs(10),
+            compact_bytes: 64 * 1024,
+        }
+    }
+}
+
+pub fn default_path() -> PathBuf {
+    util::data_dir().join("journal.bin")
+}
+
+/// Append-only journal of counter snapshots.
+/// Each record is [len: u32][crc32: u32][json], all little endian,
+/// a torn or corrupt tail is dropped on recovery.
+pub struct Journal {
+    cfg: JournalCfg,
+    path: PathBuf,
+    file: File,
+    len: u64,
+    written: Counters,
+    last_write: Option<Instant>,
+}
+
+impl Journal {
+    // recovers the last consistent state and truncates anything after it
+    pub fn open(path: &Path, cfg: JournalCfg) -> io::Result<(Journal, Counters)> {
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        let mut file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .open(path)?;
+
+        let mut buf = vec![];
+        file.read_to_end(&mut buf)?;
+        let (counters, len) = recover(&buf);
+        if len != buf.len() as u64 {
+            eprintln!(
+                "journal: dropped {} bytes of torn tail",
+                buf.len() as u64 - len
+            );
+            file.set_len(len)?;
+            file.sync_all()?;
+        }
+
+        let mut journal = Journal {
+            cfg,
+            path: path.to_path_buf(),
+            file: OpenOptions::new().append(true).open(path)?,
+            len,
+            written: counters.clone(),
+            last_write: None,
+        };
+        if journal.len > journal.cfg.compact_bytes {
+            journal.compact(&counters)?;
+        }
+        Ok((journal, counters))
+    }
+
+    // writes at most once per interval, and only when something changed
+    pub fn store(&mut self, counters: &Counters, now: Instant) -> io::Result<()> {
+        if *counters == self.written {
+            return Ok(());
+        }
+        match self.last_write {
+            Some(t) if now.saturating_duration_since(t) < self.cfg.write_interval => Ok(()),
+            _ => {
+                self.last_write = Some(now);
+                self.flush(counters)
+            }
+        }
+    }
+
+    pub fn flush(&mut self, counters: &Counters) -> io::Result<()> {
+        if *counters == self.written {
+            return Ok(());
+        }
+        if self.len > self.cfg.compact_bytes {
+            return self.compact(counters);
+        }
+        let record = encode(counters)?;
+        self.file.write_all(&record)?;
+        self.file.sync_data()?;
+        self.len += record.len() as u64;
+        self.written = counters.clone();
+        Ok(())
+    }
+
+    // the new journal is complete on disk before it replaces the old one
+    fn compact(&mut self, counters: &Counters) -> io::Result<()> {
+        let record = encode(counters)?;
+        util::atomic_write(&self.path, &record)?;
+        self.file = OpenOptions::new().append(true).open(&self.path)?;
+        self.len = record.len() as u64;
+        self.written = counters.clone();
+        Ok(())
+    }
+}
+
+fn encode(counters: &Counters) -> io::Result<Vec<u8>> {
+    let payload = serde_json::to_vec(counters)?;
+    let mut record = Vec::with_capacity(payload.len() + 8);
+    record.extend_from_slice(&(payload.len() as u32).to_le_bytes());
+    record.extend_from_slice(&crc32(&payload).to_le_bytes());
+    record.extend_from_slice(&payload);
+    Ok(record)
+}
+
+// last valid record and the length of the valid prefix
+fn recover(buf: &[u8]) -> (Counters, u64) {
+    let mut counters = Counters::default();
+    let mut pos = 0;
+    while buf.len() - pos >= 8 {
+        let len = u32::from_le_bytes(buf[pos..pos + 4].try_into().unwrap()) as usize;
+        let crc = u32::from_le_bytes(buf[pos + 4..pos + 8].try_into().unwrap());
+        // a length past the end of memory, as on a 32 bit Pi, is a torn record too
+        let Some(end) = (pos + 8).checked_add(len) else {
+            break;
+        };
+        let Some(payload) = buf.get(pos + 8..end) else {
+            break;
+        };
+        if crc32(payload) != crc {
+            break;
+        }
+        match serde_json::from_slice(payload) {
+            Ok(c) => counters = c,
+            Err(_) => break,
+        }
+        pos = end;
+    }
+    (counters, pos as u64)
+}
+
+// IEEE 802.3
+fn crc32(data: &[u8]) -> u32 {
+    let mut crc = !0u32;
+    for b in data {
+        crc ^= *b as u32;
+        for _ in 0..8 {
+            crc = if crc & 1 != 0 {
+                (crc >> 1) ^ 0xEDB8_8320
+            } else {
+                crc >> 1
+            };
+        }
+    }
+    !crc
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::util::TempDir;
+
+    fn counters(i: usize) -> Counters {
+        let mut c = Counters::default();
+        c.add_feet(i as f32 * 100.0);
+        c.job_id = Some(i as i64 % 3);
+        c.push_log(format!("line {i}"));
+        c
+    }
+
+    #[test]
+    fn crc() {
+        assert_eq!(crc32(b"123456789"), 0xCBF4_3926);
+    }
+
+    #[test]
+    fn recovers_from_truncation() {
+        let mut buf = vec![];
+        let mut ends = vec![];
+        for i in 1..=20 {
+            buf.extend(encode(&counters(i)).unwrap());
+            ends.push(buf.len());
+        }
+
+        // truncate at pseudo random offsets, the recovered state is the last
+        // record that was completely written
+        let mut seed: u64 = 0x2545_F491_4F6C_DD1D;
+        for _ in 0..500 {
+            seed = seed.wrapping_mul(6364136223846793005).wrapping_add(1);
+            let cut = (seed >> 33) as usize % (buf.len() + 1);
+
+            let (c, len) = recover(&buf[..cut]);
+            let complete = ends.iter().filter(|e| **e <= cut).count();
+            let expected = if complete == 0 {
+                Counters::default()
+            } else {
+                counters(complete)
+            };
+            assert_eq!(c, expected, "cut at {cut}");
+            assert_eq!(
+                len as usize,
+                if complete == 0 { 0 } else { ends[complete - 1] }
+            );
+        }
+    }
+
+    #[test]
+    fn corrupt_record_is_dropped() {
+        let mut buf = encode(&counters(1)).unwrap();
+        let first = buf.len();
+        buf.extend(encode(&counters(2)).unwrap());
+        let last = buf.len() - 2;
+        buf[last] ^= 0xff;
+
+        let (c, len) = recover(&buf);
+        assert_eq!(c, counters(1));
+        assert_eq!(len as usize, first);
+    }
+
+    #[test]
+    fn huge_length_is_truncation() {
+        let mut buf = encode(&counters(1)).unwrap();
+        let first = buf.len();
+        buf.extend(u32::MAX.to_le_bytes());
+        buf.extend(0u32.to_le_bytes());
+        buf.extend(b"{}");
+
+        let (c, len) = recover(&buf);
+        assert_eq!(c, counters(1));
+        assert_eq!(len as usize, first);
+    }
+
+    #[test]
+    fn reopen_and_compact() {
+        let dir = TempDir::new("journal");
+        let path = dir.path().join("journal.bin");
+        let cfg = || JournalCfg {
+            write_interval: Duration::ZERO,
+            compact_bytes: 512,
+        };
+
+        let (mut j, c) = Journal::open(&path, cfg()).unwrap();
+        assert_eq!(c, Counters::default());
+        for i in 1..=30 {
+            j.flush(&counters(i)).unwrap();
+        }
+        assert!(fs::metadata(&path).unwrap().len() <= 512 + 200);
+        drop(j);
+
+        // a half written record from a power cut
+        let mut f = OpenOptions::new().append(true).open(&path).unwrap();
+        f.write_all(&encode(&counters(31)).unwrap()[..10]).unwrap();
+        drop(f);
+
+        let (_, c) = Journal::open(&path, cfg()).unwrap();
+        assert_eq!(c, counters(30));
+    }
+}
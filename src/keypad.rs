@@ -0,0 +1,131 @@
+use crate::gui::BODY_HEIGHT;
+use crate::msg::Message;
+use crate::msg::Message::KeypadPress;
+use iced::widget::{Button, Column, Container, Row, Text};
+use iced::{alignment, Length};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum KeypadTarget {
+    Spacing,
+    Population,
+    TruckLoad,
+    BulkFill,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Key {
+    Digit(char),
+    Point,
+    Back,
+    Enter,
+    Cancel,
+}
+
+/// Numeric entry on the touchscreen
+pub struct Keypad {
+    pub target: KeypadTarget,
+    pub entry: String,
+}
+
+impl Keypad {
+    pub fn new(target: KeypadTarget) -> Self {
+        Keypad {
+            target,
+            entry: String::new(),
+        }
+    }
+
+    pub fn press(&mut self, key: Key) {
+        match key {
+            Key::Digit(c) if self.entry.len() < 8 => self.entry.push(c),
+            Key::Point if !self.entry.contains('.') => self.entry.push('.'),
+            Key::Back => {
+                self.entry.pop();
+            }
+            _ => {}
+        }
+    }
+
+    pub fn value(&self) -> Option<f32> {
+        self.entry.parse().ok().filter(|v: &f32| *v > 0.0)
+    }
+}
+
+const KEY_HEIGHT: u16 = BODY_HEIGHT / 5;
+
+fn key<'a>(label: &str, k: Key) -> Button<'a, Message> {
+    Button::new(
+        Text::new(label.to_string())
+            .size(28)
+            .horizontal_alignment(alignment::Horizontal::Center),
+    )
+    .width(Length::Fill)
+    .height(KEY_HEIGHT)
+    .on_press(KeypadPress(k))
+}
+
+pub fn make_keypad(keypad: &Keypad) -> Container<Message> {
+    let label = match keypad.target {
+        KeypadTarget::Spacing => "Spacing (in)",
+        KeypadTarget::Population => "Population (seeds/ac)",
+        KeypadTarget::TruckLoad => "Truck load (cwt)",
+        KeypadTarget::BulkFill => "Bulk fill (cwt)",
+    };
+
+    let digits = |keys: [char; 3]| {
+        keys.into_iter().fold(Row::new().spacing(5), |row, c| {
+            row.push(key(&c.to_string(), Key::Digit(c)))
+        })
+    };
+
+    let col = Column::new()
+        .spacing(5)
+        .push(
+            Row::new()
+                .height(KEY_HEIGHT)
+                .push(Text::new(label).size(24).width(Length::Fill))
+                .push(Text::new(keypad.entry.clone()).size(32).width(Length::Fill)),
+        )
+        .push(digits(['7', '8', '9']).push(key("<", Key::Back)))
+        .push(digits(['4', '5', '6']).push(key("Cancel", Key::Cancel)))
+        .push(digits(['1', '2', '3']).push(key("Enter", Key::Enter)))
+        .push(
+            Row::new()
+                .spacing(5)
+                .push(key("0", Key::Digit('0')))
+                .push(key(".", Key::Point)),
+        );
+
+    Container::new(col).height(BODY_HEIGHT).width(Length::Fill)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn entry() {
+        let mut k = Keypad::new(KeypadTarget::Spacing);
+        assert_eq!(k.value(), None);
+        for key in [Key::Digit('1'), Key::Point, Key::Point, Key::Digit('5')] {
+            k.press(key);
+        }
+        assert_eq!(k.entry, "1.5");
+        assert_eq!(k.value(), Some(1.5));
+        k.press(Key::Back);
+        k.press(Key::Back);
+        assert_eq!(k.value(), Some(1.0));
+
+        // zero is no value, and the entry is capped
+        let mut k = Keypad::new(KeypadTarget::Population);
+        k.press(Key::Digit('0'));
+        assert_eq!(k.value(), None);
+        for _ in 0..12 {
+            k.press(Key::Digit('9'));
+        }
+        assert_eq!(k.entry.len(), 8);
+        // enter and cancel are handled by the dash
+        k.press(Key::Enter);
+        assert_eq!(k.entry.len(), 8);
+    }
+}
@@ -1,11 +1,36 @@
+pub mod actuator;
+pub mod alarm;
 pub mod app;
+pub mod asplanted;
+pub mod boundary;
+pub mod control;
+pub mod coverage;
+pub mod curve;
+pub mod db;
+pub mod diagnostics;
+pub mod flowmap;
+pub mod geo;
+pub mod geometry;
 pub mod gps;
 mod gui;
+pub mod hopper;
 pub mod io;
+pub mod isoxml;
+pub mod journal;
+mod keypad;
+pub mod log;
+mod map;
+pub mod mode;
 pub mod monitor;
 mod msg;
+pub mod prescription;
 mod row_ui;
+pub mod section;
+pub mod seed;
+pub mod settings;
 pub mod util;
+pub mod valve;
+pub mod watchdog;
 
 pub fn add(left: usize, right: usize) -> usize {
     left + right
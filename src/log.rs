@@ -0,0 +1,376 @@
+use crate::gps::Fix;
+use crate::io::{Cmd, Event};
+use crate::util::{self, Speed, TickRate};
+use chrono::{Local, TimeZone};
+use crossbeam_channel::{Receiver, Sender};
+use serde::{Deserialize, Serialize};
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufRead, BufReader, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::thread;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "kind", content = "data", rename_all = "snake_case")]
+pub enum Record {
+    Event(Event),
+    Cmd(Cmd),
+    Control {
+        // older logs have a single drive
+        #[serde(default)]
+        drive: usize,
+        target_tickrate: TickRate,
+        actual_tickrate: TickRate,
+        // throttle of the correction pulse, if one was sent
+        throttle: Option<f32>,
+    },
+    Fix(Fix),
+    // middle of the row units, worked out from the antenna fix
+    Planter(Fix),
+    // each row through curves, logged with each fix while planting
+    Row {
+        row: usize,
+        speed_mph: Speed,
+        target_tickrate: TickRate,
+        // in the ground at the shared seed wheel speed
+        spacing_in: Option<f32>,
+    },
+    // what is being planted, logged whenever it changes
+    Job {
+        field: Option<String>,
+        // older logs name the field only
+        #[serde(default)]
+        field_id: Option<i64>,
+        variety: Option<String>,
+        seed_lot: Option<String>,
+        spacing_in: f32,
+    },
+    // prescription zone under the planter, logged with each fix while planting
+    Rate {
+        zone: Option<String>,
+        prescribed_in: Option<f32>,
+        target_in: f32,
+        actual_in: Option<f32>,
+    },
+}
+
+/// One line of a session log
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Entry {
+    // milliseconds since the app started, never jumps
+    pub mono_ms: u64,
+    // milliseconds since the unix epoch
+    pub wall_ms: u64,
+    pub record: Record,
+}
+
+enum LogMsg {
+    Entry(Entry),
+    Field(Option<String>),
+    Flush(Sender<()>),
+}
+
+pub fn default_dir() -> PathBuf {
+    util::data_dir().join("sessions")
+}
+
+// buffered lines are written out at least this often
+const FLUSH_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Session data logger, writes JSON Lines from a background thread.
+/// The default logger discards everything.
+#[derive(Default)]
+pub struct Logger {
+    tx: Option<Sender<LogMsg>>,
+    start: Option<Instant>,
+}
+
+impl Logger {
+    pub fn open(dir: &Path) -> io::Result<Logger> {
+        fs::create_dir_all(dir)?;
+        let (tx, rx) = crossbeam_channel::unbounded();
+        let dir = dir.to_path_buf();
+        thread::Builder::new()
+            .name("log".into())
+            .spawn(move || run_logger(dir, rx))?;
+        Ok(Logger {
+            tx: Some(tx),
+            start: Some(Instant::now()),
+        })
+    }
+
+    pub fn record(&self, record: Record) {
+        if let (Some(tx), Some(start)) = (&self.tx, self.start) {
+            let wall_ms = SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_millis() as u64;
+            tx.send(LogMsg::Entry(Entry {
+                mono_ms: start.elapsed().as_millis() as u64,
+                wall_ms,
+                record,
+            }));
+        }
+    }
+
+    // starts a new session file when the field changes
+    pub fn set_field(&self, field: Option<String>) {
+        if let Some(tx) = &self.tx {
+            tx.send(LogMsg::Field(field));
+        }
+    }
+
+    // blocks until everything logged so far is on disk
+    pub fn flush(&self, timeout: Duration) {
+        if let Some(tx) = &self.tx {
+            let (done_tx, done) = crossbeam_channel::bounded(1);
+            tx.send(LogMsg::Flush(done_tx));
+            done.recv_timeout(timeout);
+        }
+    }
+}
+
+// sessions are named by local date and field, eg. 2023-05-14_north-40.jsonl
+pub fn session_name(wall_ms: u64, field: Option<&str>) -> String {
+    let date = Local
+        .timestamp_millis_opt(wall_ms as i64)
+        .single()
+        .map(|t| t.format("%Y-%m-%d").to_string())
+        .unwrap_or_else(|| "unknown".to_string());
+    let field: String = field
+        .unwrap_or("unassigned")
+        .chars()
+        .map(|c| if c.is_alphanumeric() { c } else { '-' })
+        .collect();
+    format!("{date}_{field}.jsonl")
+}
+
+// a full or failing card is reported once, not for every line, until writes work again
+fn report(result: io::Result<()>, what: &str, failing: &mut bool) {
+    match result {
+        Ok(()) => *failing = false,
+        Err(e) => {
+            if !*failing {
+                eprintln!("session log {what} failed: {e}");
+            }
+            *failing = true;
+        }
+    }
+}
+
+fn run_logger(dir: PathBuf, rx: Receiver<LogMsg>) {
+    let mut field: Option<String> = None;
+    let mut session: Option<(String, BufWriter<File>)> = None;
+    let mut last_flush = Instant::now();
+    let mut failing = false;
+
+    loop {
+        let msg = match rx.recv_timeout(FLUSH_INTERVAL) {
+            Ok(msg) => Some(msg),
+            Err(crossbeam_channel::RecvTimeoutError::Timeout) => None,
+            Err(crossbeam_channel::RecvTimeoutError::Disconnected) => break,
+        };
+
+        match msg {
+            Some(LogMsg::Entry(entry)) => {
+                let name = session_name(entry.wall_ms, field.as_deref());
+                if session.as_ref().map(|(n, _)| n != &name).unwrap_or(true) {
+                    if let Some((_, mut w)) = session.take() {
+                        report(w.flush(), "flush", &mut failing);
+                    }
+                    match OpenOptions::new()
+                        .create(true)
+                        .append(true)
+                        .open(dir.join(&name))
+                    {
+                        Ok(f) => session = Some((name, BufWriter::new(f))),
+                        Err(e) => eprintln!("failed to open session {name}: {e}"),
+                    }
+                }
+                if let Some((_, w)) = session.as_mut() {
+                    if let Ok(line) = serde_json::to_string(&entry) {
+                        report(writeln!(w, "{line}"), "write", &mut failing);
+                    }
+                }
+            }
+            Some(LogMsg::Field(f)) => field = f,
+            Some(LogMsg::Flush(done)) => {
+                if let Some((_, w)) = session.as_mut() {
+                    report(w.flush(), "flush", &mut failing);
+                    report(w.get_ref().sync_data(), "sync", &mut failing);
+                }
+                // nobody is waiting once the caller has timed out
+                let _ = done.send(());
+                last_flush = Instant::now();
+            }
+            None => {}
+        }
+
+        if last_flush.elapsed() >= FLUSH_INTERVAL {
+            if let Some((_, w)) = session.as_mut() {
+                report(w.flush(), "flush", &mut failing);
+            }
+            last_flush = Instant::now();
+        }
+    }
+
+    if let Some((_, mut w)) = session {
+        report(w.flush(), "flush", &mut failing);
+    }
+}
+
+pub fn read_session(path: &Path) -> io::Result<Vec<Entry>> {
+    let f = BufReader::new(File::open(path)?);
+    // a torn last line after a power cut is skipped
+    Ok(f.lines()
+        .map_while(Result::ok)
+        .filter_map(|l| serde_json::from_str(&l).ok())
+        .collect())
+}
+
+pub fn export_jsonl(entries: &[Entry], out: &mut impl Write) -> io::Result<()> {
+    for e in entries {
+        writeln!(out, "{}", serde_json::to_string(e)?)?;
+    }
+    Ok(())
+}
+
+const CSV_HEADER: &str = "mono_ms,wall_ms,kind,name,value,target_tickrate,actual_tickrate,throttle,latitude,longitude,speed_mph,heading,variety,seed_lot,prescribed_in,actual_in";
+
+pub fn export_csv(entries: &[Entry], out: &mut impl Write) -> io::Result<()> {
+    writeln!(out, "{CSV_HEADER}")?;
+    for e in entries {
+        let mut cols: [String; 16] = Default::default();
+        cols[0] = e.mono_ms.to_string();
+        cols[1] = e.wall_ms.to_string();
+        match &e.record {
+            Record::Event(ev) => {
+                cols[2] = "event".into();
+                (cols[3], cols[4]) = split_debug(ev);
+            }
+            Record::Cmd(cmd) => {
+                cols[2] = "cmd".into();
+                (cols[3], cols[4]) = split_debug(cmd);
+            }
+            Record::Control {
+                drive,
+                target_tickrate,
+                actual_tickrate,
+                throttle,
+            } => {
+                cols[2] = "control".into();
+                cols[3] = drive.to_string();
+                cols[5] = target_tickrate.to_string();
+                cols[6] = actual_tickrate.to_string();
+                cols[7] = throttle.map(|t| t.to_string()).unwrap_or_default();
+            }
+            Record::Fix(fix) | Record::Planter(fix) => {
+                cols[2] = match e.record {
+                    Record::Planter(_) => "planter",
+                    _ => "fix",
+                }
+                .into();
+                cols[8] = fix.latitude.to_string();
+                cols[9] = fix.longitude.to_string();
+                cols[10] = fix.speed_mph.to_string();
+                cols[11] = fix.heading.map(|h| h.to_string()).unwrap_or_default();
+            }
+            Record::Row {
+                row,
+                speed_mph,
+                target_tickrate,
+                spacing_in,
+            } => {
+                cols[2] = "row".into();
+                cols[3] = row.to_string();
+                cols[5] = target_tickrate.to_string();
+                cols[10] = speed_mph.to_string();
+                cols[15] = spacing_in.map(|s| s.to_string()).unwrap_or_default();
+            }
+            Record::Job {
+                field,
+                variety,
+                seed_lot,
+                spacing_in,
+                ..
+            } => {
+                cols[2] = "job".into();
+                cols[3] = field.clone().unwrap_or_default();
+                cols[4] = spacing_in.to_string();
+                cols[12] = variety.clone().unwrap_or_default();
+                cols[13] = seed_lot.clone().unwrap_or_default();
+            }
+            Record::Rate {
+                zone,
+                prescribed_in,
+                target_in,
+                actual_in,
+            } => {
+                cols[2] = "rate".into();
+                cols[3] = zone.clone().unwrap_or_default();
+                cols[4] = target_in.to_string();
+                cols[14] = prescribed_in.map(|s| s.to_string()).unwrap_or_default();
+                cols[15] = actual_in.map(|s| s.to_string()).unwrap_or_default();
+            }
+        }
+        let line: Vec<String> = cols.iter().map(|c| csv_field(c)).collect();
+        writeln!(out, "{}", line.join(","))?;
+    }
+    Ok(())
+}
+
+// GroundSpeed(3.3) => ("GroundSpeed", "3.3")
+fn split_debug<T: std::fmt::Debug>(v: &T) -> (String, String) {
+    let s = format!("{v:?}");
+    match s.split_once('(') {
+        Some((name, rest)) => (name.to_string(), rest.trim_end_matches(')').to_string()),
+        None => (s, String::new()),
+    }
+}
+
+fn csv_field(s: &str) -> String {
+    if s.contains([',', '"', '\n']) {
+        format!("\"{}\"", s.replace('"', "\"\""))
+    } else {
+        s.to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn csv_export() {
+        let entries = vec![
+            Entry {
+                mono_ms: 10,
+                wall_ms: 1000,
+                record: Record::Event(Event::GroundSpeed(3.5)),
+            },
+            Entry {
+                mono_ms: 20,
+                wall_ms: 1010,
+                record: Record::Cmd(Cmd::SeedBeltControl(1, true)),
+            },
+        ];
+
+        let mut jsonl = vec![];
+        export_jsonl(&entries, &mut jsonl).unwrap();
+        let first = String::from_utf8(jsonl).unwrap();
+        let first = first.lines().next().unwrap();
+        let e: Entry = serde_json::from_str(first).unwrap();
+        assert!(matches!(e.record, Record::Event(Event::GroundSpeed(s)) if s == 3.5));
+
+        let mut csv = vec![];
+        export_csv(&entries, &mut csv).unwrap();
+        let csv = String::from_utf8(csv).unwrap();
+        let lines: Vec<&str> = csv.lines().collect();
+        assert_eq!(lines[0], CSV_HEADER);
+        assert_eq!(lines[1], "10,1000,event,GroundSpeed,3.5,,,,,,,,,,,");
+        assert_eq!(
+            lines[2],
+            "20,1010,cmd,SeedBeltControl,\"1, true\",,,,,,,,,,,"
+        );
+    }
+}
@@ -1,19 +1,71 @@
 use iced::window::Position;
 use iced::{window, Application, Settings};
 use popl::app::Dash;
-use popl::io::{IoCfg, IO};
+use popl::control::Drive;
+use popl::coverage;
+use popl::db::{self, Db};
+use popl::flowmap;
+use popl::io::{is_io_thread, IoCfg, IO, SHUTDOWN_TIMEOUT};
+use popl::journal::{self, Journal, JournalCfg};
+use popl::log::{self, Logger};
 use popl::monitor::Monitor;
+use popl::valve;
+use std::panic;
 
 fn main() -> iced::Result {
-    #[cfg(all(target_arch = "arm"))]
-    let io = IO::new(IoCfg::default()).expect("io init error");
-    //#[cfg(all(target_arch = "x86_64"))]
-    let io = IO::fake(IoCfg::default()).expect("io init error");
+    let io_cfg = IoCfg::default();
+    let mut flow_maps = flowmap::load(&flowmap::default_path()).into_iter();
+    let mut valves = valve::load(&valve::default_path()).into_iter();
+    let drives = io_cfg
+        .flow_drives
+        .iter()
+        .map(|d| {
+            let mut drive = Drive::new(d.rows.clone());
+            drive.flow_map = flow_maps.next().unwrap_or_default();
+            if let Some(cal) = valves.next().flatten() {
+                drive.valve.calibrate(cal);
+            }
+            drive
+        })
+        .collect();
+    // the real pins on the Pi, anywhere else a stand in
+    #[cfg(target_arch = "arm")]
+    let io = IO::new(io_cfg).expect("io init error");
+    #[cfg(not(target_arch = "arm"))]
+    let io = IO::fake(io_cfg).expect("io init error");
+
+    // a panic anywhere else leaves the outputs safe before the process goes down,
+    // the io and flow drive threads handle their own panics
+    let shutdown = io.shutdown_handle();
+    let default_hook = panic::take_hook();
+    panic::set_hook(Box::new(move |info| {
+        default_hook(info);
+        if !is_io_thread() {
+            shutdown.shutdown(SHUTDOWN_TIMEOUT);
+        }
+    }));
+
+    let log = Logger::open(&log::default_dir()).unwrap_or_else(|e| {
+        eprintln!("session logging disabled: {e}");
+        Logger::default()
+    });
+
+    let (journal, counters) = match Journal::open(&journal::default_path(), JournalCfg::default()) {
+        Ok((journal, counters)) => (Some(journal), counters),
+        Err(e) => {
+            eprintln!("counters will not be saved: {e}");
+            (None, Default::default())
+        }
+    };
+
+    let db = Db::open(&db::default_path())
+        .map_err(|e| eprintln!("field database unavailable: {e}"))
+        .ok();
 
     Dash::run(Settings {
         id: None,
         antialiasing: true,
-        exit_on_close_request: true,
+        exit_on_close_request: false,
         window: window::Settings {
             size: (800, 480),
             position: Position::Centered,
@@ -21,12 +73,35 @@ fn main() -> iced::Result {
             ..window::Settings::default()
         },
         flags: Monitor {
-            feet_planted: 19166.4,
+            counters,
+            journal,
+            last_tick: None,
+            db,
+            job: None,
+            locator: Default::default(),
+            in_field: None,
+            planter: Default::default(),
+            curve: Default::default(),
+            coverage: coverage::load(&coverage::default_path()),
+            sections: Default::default(),
+            variable_rate: Default::default(),
             ground_speed_mph: 3.3,
-            seed_wheel_speed_rpm: 100.0,
             auto_prime: [true, true],
             priming: [false, false],
             planter_raised: false,
+            alarms: Default::default(),
+            watchdog: Default::default(),
+            drives,
+            flow_map_cfg: Default::default(),
+            start_cfg: Default::default(),
+            flow_map_path: Some(flowmap::default_path()),
+            valve_path: Some(valve::default_path()),
+            coverage_path: Some(coverage::default_path()),
+            seed_meter: Default::default(),
+            hoppers: Default::default(),
+            mode: Default::default(),
+            mode_cfg: Default::default(),
+            log,
             io,
         },
         ..Settings::default()
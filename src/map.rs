@@ -0,0 +1,415 @@
+use crate::app::Dash;
+use crate::coverage::{Accuracy, Coverage, Sample};
+use crate::geo::{Boundary, LocalProjection, Xy};
+use crate::gui::make_tabs;
+use crate::msg::Message;
+use iced::widget::canvas::{
+    self, event, Canvas, Cursor, Event, Frame, Geometry, LineCap, LineJoin, Path, Stroke,
+};
+use iced::widget::{Button, Column, Container, Row, Space, Text};
+use iced::{mouse, touch, Alignment, Color, Length, Point, Rectangle, Theme};
+use std::cell::Cell;
+use std::collections::HashMap;
+
+// pixels per metre
+const DEFAULT_ZOOM: f32 = 1.0;
+const MIN_ZOOM: f32 = 0.05;
+const MAX_ZOOM: f32 = 20.0;
+const ZOOM_STEP: f32 = 1.5;
+// wheel notches are gentler than the buttons
+const WHEEL_ZOOM: f32 = 1.2;
+// pixels of scroll counted as one wheel notch
+const WHEEL_PIXELS: f32 = 50.0;
+
+const SOIL: Color = Color::from_rgb(0.25, 0.19, 0.13);
+const BOUNDARY: Color = Color::WHITE;
+const PASS_LINE: Color = Color::from_rgb(0.1, 0.1, 0.1);
+const TRACTOR: Color = Color::from_rgb(0.1, 0.5, 0.9);
+const GOOD: Color = Color::from_rgb(0.2, 0.7, 0.2);
+const FAIR: Color = Color::from_rgb(0.9, 0.8, 0.1);
+const POOR: Color = Color::from_rgb(0.85, 0.2, 0.2);
+const UNKNOWN: Color = Color::from_rgb(0.5, 0.5, 0.5);
+
+fn accuracy_color(a: Accuracy) -> Color {
+    match a {
+        Accuracy::Good => GOOD,
+        Accuracy::Fair => FAIR,
+        Accuracy::Poor => POOR,
+        Accuracy::Unknown => UNKNOWN,
+    }
+}
+
+/// Zoom and centre of the map, the centre follows the tractor until the map is dragged
+pub struct MapView {
+    pub zoom: f32,
+    pub centre: Option<Xy>,
+    // while following, the view moves on only once the tractor nears the edge
+    follow: Cell<Option<Xy>>,
+    // soil, finished passes and boundaries, drawn again when they or the view change
+    layers: canvas::Cache,
+    drawn: Cell<Option<Drawn>>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Drawn {
+    zoom: f32,
+    centre: Xy,
+    revision: u64,
+}
+
+impl Default for MapView {
+    fn default() -> Self {
+        MapView {
+            zoom: DEFAULT_ZOOM,
+            centre: None,
+            follow: Cell::new(None),
+            layers: canvas::Cache::new(),
+            drawn: Cell::new(None),
+        }
+    }
+}
+
+impl MapView {
+    pub fn zoom_by(&mut self, factor: f32) {
+        self.zoom = (self.zoom * factor).clamp(MIN_ZOOM, MAX_ZOOM);
+    }
+
+    pub fn follow(&mut self) {
+        self.centre = None;
+        self.follow.set(None);
+    }
+
+    // the boundaries have changed
+    pub fn redraw(&self) {
+        self.layers.clear();
+    }
+}
+
+/// Boundaries, coverage and the tractor drawn on a plane, north up
+struct Map<'a> {
+    view: &'a MapView,
+    coverage: &'a Coverage,
+    boundaries: Vec<&'a Boundary>,
+    swath_m: f32,
+}
+
+/// Fingers and mouse drag in progress
+#[derive(Default)]
+struct Gesture {
+    fingers: HashMap<touch::Finger, Point>,
+    drag: Option<Point>,
+}
+
+impl<'a> Map<'a> {
+    // plane and centre of the view, once there is a fix or a boundary to put it on
+    fn placement(&self) -> Option<(LocalProjection, Xy)> {
+        let first = self
+            .boundaries
+            .first()
+            .and_then(|b| b.polygons.first())
+            .map(|p| &p.outer);
+        let proj = match (self.coverage.projection(), first.and_then(|r| r.first())) {
+            (Some(proj), _) => *proj,
+            (None, Some(p)) => LocalProjection::new(*p),
+            (None, None) => return None,
+        };
+        let boundary_centre = first.map(|ring| {
+            let n = ring.len().max(1) as f64;
+            let sum = ring
+                .iter()
+                .map(|p| proj.to_local(*p))
+                .fold(Xy::default(), |a, p| Xy {
+                    x: a.x + p.x,
+                    y: a.y + p.y,
+                });
+            Xy {
+                x: sum.x / n,
+                y: sum.y / n,
+            }
+        });
+        let centre = self
+            .view
+            .centre
+            .or(self.view.follow.get())
+            .or(self.coverage.position)
+            .or(boundary_centre)
+            .unwrap_or_default();
+        Some((proj, centre))
+    }
+
+    // the view jumps to the tractor once it is a quarter of the way out from the middle
+    fn keep_up(&self, bounds: Rectangle) {
+        let (None, Some(at)) = (self.view.centre, self.coverage.position) else {
+            return;
+        };
+        let zoom = self.view.zoom as f64;
+        let (rx, ry) = (
+            bounds.width as f64 / 4.0 / zoom,
+            bounds.height as f64 / 4.0 / zoom,
+        );
+        let near = self.view.follow.get().map_or(false, |c| {
+            (at.x - c.x).abs() < rx && (at.y - c.y).abs() < ry
+        });
+        if !near {
+            self.view.follow.set(Some(at));
+        }
+    }
+
+    fn pan(&self, dx: f32, dy: f32) -> Option<Message> {
+        let (_, c) = self.placement()?;
+        let zoom = self.view.zoom as f64;
+        Some(Message::MapPan(Xy {
+            x: c.x - dx as f64 / zoom,
+            y: c.y + dy as f64 / zoom,
+        }))
+    }
+
+    fn gesture(
+        &self,
+        g: &mut Gesture,
+        event: Event,
+        bounds: Rectangle,
+        cursor: Cursor,
+    ) -> Option<Message> {
+        match event {
+            Event::Touch(touch::Event::FingerPressed { id, position }) => {
+                if bounds.contains(position) {
+                    g.fingers.insert(id, position);
+                }
+                None
+            }
+            Event::Touch(touch::Event::FingerMoved { id, position }) => {
+                let prev = *g.fingers.get(&id)?;
+                g.fingers.insert(id, position);
+                match g.fingers.len() {
+                    1 => self.pan(position.x - prev.x, position.y - prev.y),
+                    // pinch, the other finger holds still for this event
+                    2 => {
+                        let (_, other) = g.fingers.iter().find(|(f, _)| **f != id)?;
+                        let before = other.distance(prev);
+                        let after = other.distance(position);
+                        (before > 1.0).then(|| Message::MapZoom(after / before))
+                    }
+                    _ => None,
+                }
+            }
+            Event::Touch(
+                touch::Event::FingerLifted { id, .. } | touch::Event::FingerLost { id, .. },
+            ) => {
+                g.fingers.remove(&id);
+                None
+            }
+            // touchscreens also send emulated mouse events, fingers take precedence
+            Event::Mouse(mouse::Event::ButtonPressed(mouse::Button::Left)) => {
+                if g.fingers.is_empty() && cursor.is_over(&bounds) {
+                    g.drag = cursor.position();
+                }
+                None
+            }
+            Event::Mouse(mouse::Event::ButtonReleased(mouse::Button::Left)) => {
+                g.drag = None;
+                None
+            }
+            Event::Mouse(mouse::Event::CursorMoved { position }) => {
+                let prev = g.drag?;
+                g.drag = Some(position);
+                if !g.fingers.is_empty() {
+                    return None;
+                }
+                self.pan(position.x - prev.x, position.y - prev.y)
+            }
+            Event::Mouse(mouse::Event::WheelScrolled { delta }) if cursor.is_over(&bounds) => {
+                let notches = match delta {
+                    mouse::ScrollDelta::Lines { y, .. } => y,
+                    mouse::ScrollDelta::Pixels { y, .. } => y / WHEEL_PIXELS,
+                };
+                Some(Message::MapZoom(WHEEL_ZOOM.powf(notches)))
+            }
+            _ => None,
+        }
+    }
+}
+
+impl<'a> canvas::Program<Message> for Map<'a> {
+    type State = Gesture;
+
+    fn update(
+        &self,
+        state: &mut Gesture,
+        event: Event,
+        bounds: Rectangle,
+        cursor: Cursor,
+    ) -> (event::Status, Option<Message>) {
+        match self.gesture(state, event, bounds, cursor) {
+            Some(msg) => (event::Status::Captured, Some(msg)),
+            None => (event::Status::Ignored, None),
+        }
+    }
+
+    fn draw(
+        &self,
+        _state: &Gesture,
+        _theme: &Theme,
+        bounds: Rectangle,
+        _cursor: Cursor,
+    ) -> Vec<Geometry> {
+        self.keep_up(bounds);
+        let Some((proj, centre)) = self.placement() else {
+            let mut frame = Frame::new(bounds.size());
+            frame.fill_rectangle(Point::ORIGIN, bounds.size(), SOIL);
+            frame.fill_text(canvas::Text {
+                content: "Waiting for GPS".to_string(),
+                position: Point::new(10.0, 10.0),
+                color: Color::WHITE,
+                ..canvas::Text::default()
+            });
+            return vec![frame.into_geometry()];
+        };
+        let zoom = self.view.zoom;
+        let mid = Point::new(bounds.width / 2.0, bounds.height / 2.0);
+        let to_screen = |p: Xy| {
+            Point::new(
+                mid.x + (p.x - centre.x) as f32 * zoom,
+                mid.y - (p.y - centre.y) as f32 * zoom,
+            )
+        };
+
+        let drawn = Drawn {
+            zoom,
+            centre,
+            revision: self.coverage.revision(),
+        };
+        if self.view.drawn.replace(Some(drawn)) != Some(drawn) {
+            self.view.layers.clear();
+        }
+        let layers = self.view.layers.draw(bounds.size(), |frame| {
+            frame.fill_rectangle(Point::ORIGIN, bounds.size(), SOIL);
+            for pass in self.coverage.finished() {
+                self.draw_pass(frame, pass, to_screen);
+            }
+            let edge = Stroke::default().with_color(BOUNDARY).with_width(2.0);
+            for polygon in self.boundaries.iter().flat_map(|b| &b.polygons) {
+                for ring in std::iter::once(&polygon.outer).chain(&polygon.holes) {
+                    let mut points: Vec<Point> =
+                        ring.iter().map(|p| to_screen(proj.to_local(*p))).collect();
+                    if let Some(first) = points.first().copied() {
+                        points.push(first);
+                    }
+                    frame.stroke(&polyline(&points), edge.clone());
+                }
+            }
+        });
+
+        let mut frame = Frame::new(bounds.size());
+        if let Some(pass) = self.coverage.current() {
+            self.draw_pass(&mut frame, pass, to_screen);
+        }
+
+        // pointing along the heading, north up
+        if let Some(at) = self.coverage.position {
+            let heading = self.coverage.heading.unwrap_or(0.0);
+            frame.with_save(|f| {
+                f.translate(to_screen(at) - Point::ORIGIN);
+                f.rotate(heading.to_radians());
+                let arrow = Path::new(|b| {
+                    b.move_to(Point::new(0.0, -14.0));
+                    b.line_to(Point::new(8.0, 8.0));
+                    b.line_to(Point::new(-8.0, 8.0));
+                    b.close();
+                });
+                f.fill(&arrow, TRACTOR);
+            });
+        }
+
+        // 100 px scale bar
+        let bar = Point::new(10.0, bounds.height - 10.0);
+        frame.stroke(
+            &Path::line(bar, Point::new(bar.x + 100.0, bar.y)),
+            Stroke::default().with_color(Color::WHITE).with_width(2.0),
+        );
+        frame.fill_text(canvas::Text {
+            content: format!("{:.0} m", 100.0 / zoom),
+            position: Point::new(bar.x, bar.y - 20.0),
+            color: Color::WHITE,
+            ..canvas::Text::default()
+        });
+        vec![layers, frame.into_geometry()]
+    }
+}
+
+fn polyline(points: &[Point]) -> Path {
+    Path::new(|b| {
+        for (i, p) in points.iter().enumerate() {
+            if i == 0 {
+                b.move_to(*p)
+            } else {
+                b.line_to(*p)
+            }
+        }
+    })
+}
+
+impl<'a> Map<'a> {
+    // coverage in runs of one colour, each coloured by the spacing at its end
+    fn draw_pass(&self, frame: &mut Frame, pass: &[Sample], to_screen: impl Fn(Xy) -> Point) {
+        let swath = |a: Accuracy| {
+            Stroke::default()
+                .with_color(accuracy_color(a))
+                .with_width((self.swath_m * self.view.zoom).max(1.0))
+                .with_line_cap(LineCap::Butt)
+                .with_line_join(LineJoin::Round)
+        };
+        let mut run: Vec<Point> = vec![];
+        let mut color = None;
+        for w in pass.windows(2) {
+            if color != Some(w[1].accuracy) {
+                if let Some(a) = color {
+                    frame.stroke(&polyline(&run), swath(a));
+                }
+                run = vec![to_screen(w[0].at)];
+                color = Some(w[1].accuracy);
+            }
+            run.push(to_screen(w[1].at));
+        }
+        if let Some(a) = color {
+            frame.stroke(&polyline(&run), swath(a));
+        }
+        let line: Vec<Point> = pass.iter().map(|s| to_screen(s.at)).collect();
+        frame.stroke(
+            &polyline(&line),
+            Stroke::default().with_color(PASS_LINE).with_width(1.0),
+        );
+    }
+}
+
+pub fn make_map_page(dash: &Dash) -> Container<Message> {
+    let view = dash.map_view();
+    let legend = |label: &str, color: Color| Text::new(label.to_string()).style(color);
+    let controls = Row::new()
+        .spacing(10)
+        .align_items(Alignment::Center)
+        .push(Button::new("+").on_press(Message::MapZoom(ZOOM_STEP)))
+        .push(Button::new("-").on_press(Message::MapZoom(1.0 / ZOOM_STEP)))
+        .push(if view.centre.is_some() {
+            Button::new("Follow").on_press(Message::MapFollow)
+        } else {
+            Button::new("Follow")
+        })
+        .push(Space::new(Length::Fill, Length::Shrink))
+        .push(legend("within 5%", GOOD))
+        .push(legend("within 15%", FAIR))
+        .push(legend("off", POOR));
+    let map = Canvas::new(Map {
+        view,
+        coverage: dash.coverage(),
+        boundaries: dash.boundaries().collect(),
+        swath_m: dash.swath_m(),
+    })
+    .width(Length::Fill)
+    .height(Length::Fill);
+
+    let body = Column::new().push(make_tabs(dash)).push(controls).push(map);
+    Container::new(body)
+        .width(Length::Fill)
+        .height(Length::Fill)
+}
@@ -0,0 +1,121 @@
+use std::fmt;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Mode {
+    #[default]
+    Idle,
+    Prime,
+    Planting,
+    Headland,
+    Transport,
+    Calibrate,
+    // flow stopped after a critical input failed, only selecting Idle clears it
+    Fault,
+}
+
+/// Inputs that drive mode transitions
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ModeInput {
+    Lowered,
+    Raised,
+    GroundSpeed(f32),
+    Select(Mode),
+    Fault,
+}
+
+pub struct ModeCfg {
+    // above this speed the planter is on the road
+    pub transport_speed_mph: f32,
+}
+
+impl Default for ModeCfg {
+    fn default() -> Self {
+        ModeCfg {
+            transport_speed_mph: 8.0,
+        }
+    }
+}
+
+impl Mode {
+    // modes the operator can pick, the rest follow from the sensors
+    pub const SELECTABLE: [Mode; 4] = [Mode::Idle, Mode::Prime, Mode::Transport, Mode::Calibrate];
+
+    pub fn next(self, input: ModeInput, cfg: &ModeCfg) -> Mode {
+        use Mode::*;
+        use ModeInput as I;
+        let road_speed = |mph: f32| mph > cfg.transport_speed_mph;
+
+        match (self, input) {
+            (_, I::Fault) => Fault,
+            // only the operator clears a fault
+            (Fault, I::Select(Idle)) => Idle,
+            (Fault, _) => Fault,
+
+            (_, I::Select(m)) if Mode::SELECTABLE.contains(&m) => m,
+            (Calibrate, _) => Calibrate,
+
+            (Idle | Prime | Headland, I::Lowered) => Planting,
+            (Transport, I::Lowered) => Planting,
+            (Planting, I::Raised) => Headland,
+            (Idle | Prime | Headland, I::GroundSpeed(mph)) if road_speed(mph) => Transport,
+            (m, _) => m,
+        }
+    }
+
+    // seed belts stay off while on the road
+    pub fn belts_allowed(&self, mph: f32, cfg: &ModeCfg) -> bool {
+        !(*self == Mode::Transport && mph > cfg.transport_speed_mph)
+    }
+
+    // flow valve motion, either by the controller or the operator
+    pub fn flow_allowed(&self, mph: f32, cfg: &ModeCfg) -> bool {
+        match self {
+            Mode::Transport => mph <= cfg.transport_speed_mph,
+            Mode::Headland | Mode::Fault => false,
+            _ => true,
+        }
+    }
+
+    // the controller only runs in the ground, it is frozen on the headland
+    pub fn controller_active(&self) -> bool {
+        *self == Mode::Planting
+    }
+}
+
+impl fmt::Display for Mode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Debug::fmt(self, f)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Mode::*;
+    use super::*;
+
+    #[test]
+    fn transitions() {
+        let cfg = ModeCfg::default();
+
+        let m = Idle.next(ModeInput::Lowered, &cfg);
+        assert_eq!(m, Planting);
+        let m = m.next(ModeInput::Raised, &cfg);
+        assert_eq!(m, Headland);
+        assert!(!m.flow_allowed(4.0, &cfg));
+        let m = m.next(ModeInput::GroundSpeed(12.0), &cfg);
+        assert_eq!(m, Transport);
+        assert!(!m.belts_allowed(12.0, &cfg));
+        assert!(m.belts_allowed(3.0, &cfg));
+
+        // operator cannot select planting directly
+        assert_eq!(Idle.next(ModeInput::Select(Planting), &cfg), Idle);
+
+        let m = Planting.next(ModeInput::Fault, &cfg);
+        assert_eq!(m.next(ModeInput::Lowered, &cfg), Fault);
+        assert_eq!(m.next(ModeInput::Select(Prime), &cfg), Fault);
+        assert_eq!(m.next(ModeInput::GroundSpeed(12.0), &cfg), Fault);
+        assert_eq!(m.next(ModeInput::Select(Idle), &cfg), Idle);
+
+        assert_eq!(Calibrate.next(ModeInput::Lowered, &cfg), Calibrate);
+    }
+}
@@ -1,38 +1,1388 @@
-use crate::io::{Cmd, Event, IO};
+use crate::alarm::{Alarm, Alarms};
+use crate::boundary::FieldLocator;
+use crate::control::{Drive, FlowController};
+use crate::coverage::{self, Accuracy, Coverage};
+use crate::curve::Curve;
+use crate::db::{self, BulkFill, Db, Job, PassSummary, SeedLot, Variety};
+use crate::diagnostics::WheelFault;
+use crate::flowmap::{self, FlowMapCfg, StartCfg};
+use crate::geo::{LatLon, Xy, M_PER_FT};
+use crate::geometry::Planter;
+use crate::gps::Fix;
+use crate::hopper::{Hoppers, Prediction};
+use crate::io::{Cmd, Event, IO, SHUTDOWN_TIMEOUT};
+use crate::journal::{Counters, Journal, OpenPass};
+use crate::log::{Logger, Record};
+use crate::mode::{Mode, ModeCfg, ModeInput};
+use crate::prescription::VariableRate;
+use crate::section::Sections;
+use crate::seed::SeedMeter;
+use crate::util::{
+    mph_to_fps, rpm_to_tickrate, tickrate_to_rpm, tickrate_to_spacing, Speed, TickRate,
+};
+use crate::valve::{self, Homing, HomingStep};
+use crate::watchdog::{Source, Watchdog};
+use chrono::Local;
 use embedded_hal::digital::OutputPin;
+use std::path::PathBuf;
 use std::thread;
+use std::time::{Duration, Instant};
+
+pub const ROWS: usize = 2;
 
 #[derive(Default)]
 pub struct Monitor {
     pub io: IO,
 
     pub ground_speed_mph: f32,
-    pub seed_wheel_speed_rpm: f32,
     pub planter_raised: bool,
-    pub auto_prime: [bool; 2],
-    pub priming: [bool; 2],
+    pub auto_prime: [bool; ROWS],
+    pub priming: [bool; ROWS],
+
+    pub counters: Counters,
+    pub journal: Option<Journal>,
+    pub last_tick: Option<Instant>,
+
+    pub db: Option<Db>,
+    pub job: Option<Job>,
+    pub locator: FieldLocator,
+    // field the last gps fixes were in
+    pub in_field: Option<i64>,
+    pub planter: Planter,
+    pub curve: Curve,
+    pub coverage: Coverage,
+    pub sections: Sections,
+    pub variable_rate: VariableRate,
+
+    pub alarms: Alarms,
+    pub watchdog: Watchdog,
+    // seed wheel drives, each with its own encoder and controller
+    pub drives: Vec<Drive>,
+    pub flow_map_cfg: FlowMapCfg,
+    pub start_cfg: StartCfg,
+    // where the learned flow maps, valve calibrations and coverage are kept, None keeps
+    // them in memory
+    pub flow_map_path: Option<PathBuf>,
+    pub valve_path: Option<PathBuf>,
+    pub coverage_path: Option<PathBuf>,
+    pub seed_meter: SeedMeter,
+    pub hoppers: Hoppers,
 
-    pub feet_planted: f32,
+    pub mode: Mode,
+    pub mode_cfg: ModeCfg,
+
+    pub log: Logger,
 }
 
 impl Monitor {
+    pub fn send(&self, cmd: Cmd) {
+        self.log.record(Record::Cmd(cmd.clone()));
+        self.io.tx.send(cmd);
+    }
+
     pub fn enable_seed_belt(&self, id: usize, en: bool) {
-        self.io.tx.send(Cmd::SeedBeltControl(id, en));
+        if en && !self.belts_allowed() {
+            return;
+        }
+        self.send(Cmd::SeedBeltControl(id, en));
+    }
+
+    pub fn flow_allowed(&self) -> bool {
+        self.mode
+            .flow_allowed(self.ground_speed_mph, &self.mode_cfg)
+    }
+
+    pub fn belts_allowed(&self) -> bool {
+        self.mode
+            .belts_allowed(self.ground_speed_mph, &self.mode_cfg)
+    }
+
+    // commands from the soft io page obey the mode like everything else
+    pub fn simulate(&mut self, cmd: Cmd) {
+        match cmd {
+            Cmd::SeedBeltControl(id, en) => self.enable_seed_belt(id, en),
+            Cmd::FlowThrottle(..) | Cmd::FlowPulse(..) if !self.flow_allowed() => {
+                self.note(format!("no flow in mode {}", self.mode))
+            }
+            cmd => self.send(cmd),
+        }
+    }
+
+    // a ground speed input also sets the speed, so the limits are judged before and after
+    pub fn transition(&mut self, input: ModeInput) {
+        let (flow_was, belts_were) = (self.flow_allowed(), self.belts_allowed());
+        if let ModeInput::GroundSpeed(mph) = input {
+            self.ground_speed_mph = mph;
+        }
+        let next = self.mode.next(input, &self.mode_cfg);
+        let changed = next != self.mode;
+        if changed {
+            let prev = self.mode;
+            self.mode = next;
+            self.note(format!("mode {next}"));
+
+            if next == Mode::Planting {
+                self.start_pass();
+            } else if prev == Mode::Planting {
+                self.end_pass();
+            }
+        }
+
+        // the limits hold on every input, eg. speeding up past the transport limit
+        if !self.flow_allowed() && (changed || flow_was) {
+            self.halt();
+        }
+        if !self.belts_allowed() && (changed || belts_were) {
+            for id in 0..self.priming.len() {
+                self.priming[id] = false;
+                self.send(Cmd::SeedBeltControl(id, false));
+            }
+        }
+    }
+
+    pub fn halt(&mut self) {
+        let now = Instant::now();
+        for id in 0..self.drives.len() {
+            self.drives[id].cut_short(now);
+            self.send(Cmd::FlowHold(id));
+        }
+    }
+
+    // stops flow and belts, and waits for the io thread to confirm
+    pub fn shutdown(&mut self) {
+        self.halt();
+        self.end_pass();
+        self.save_flow_maps();
+        if !self.io.shutdown(SHUTDOWN_TIMEOUT) {
+            eprintln!("io did not confirm shutdown");
+        }
+        self.log.flush(SHUTDOWN_TIMEOUT);
+        if let Some(journal) = self.journal.as_mut() {
+            if let Err(e) = journal.flush(&self.counters) {
+                eprintln!("failed to flush counters: {e}");
+            }
+        }
+    }
+
+    pub fn set_spacing(&mut self, in_between: f32) {
+        for drive in self.drives.iter_mut() {
+            drive.controller.set_spacing(in_between);
+        }
+        self.log_job();
+    }
+
+    // every drive runs to the same spacing
+    pub fn spacing(&self) -> f32 {
+        self.drives
+            .first()
+            .map_or(FlowController::default().spacing(), |d| {
+                d.controller.spacing()
+            })
+    }
+
+    // mean over the drives
+    pub fn seed_wheel_speed_rpm(&self) -> f32 {
+        let n = self.drives.len().max(1) as f32;
+        self.drives
+            .iter()
+            .map(|d| d.seed_wheel_speed_rpm)
+            .sum::<f32>()
+            / n
+    }
+
+    fn row_drive(&self, row: usize) -> Option<&Drive> {
+        self.drives.iter().find(|d| d.rows.contains(&row))
+    }
+
+    // lets the as-planted map attribute coverage
+    fn log_job(&self) {
+        let job = self.job.as_ref();
+        self.log.record(Record::Job {
+            field: job.map(|j| j.field.clone()),
+            field_id: job.map(|j| j.field_id),
+            variety: job.and_then(|j| j.variety.clone()),
+            seed_lot: job.and_then(|j| j.seed_lot.clone()),
+            spacing_in: self.spacing(),
+        });
+    }
+
+    // counters from here on are attributed to the job
+    pub fn select_job(&mut self, job: Job) {
+        if self.job.as_ref().map(|j| j.id) == Some(job.id) {
+            return;
+        }
+        let planting = self.counters.pass.is_some();
+        self.end_pass();
+        self.cover_field(job.field_id);
+        self.note(format!("job {}", job.field));
+        self.counters.job_id = Some(job.id);
+        self.log.set_field(Some(job.field.clone()));
+        self.job = Some(job);
+        self.log_job();
+        self.load_prescription();
+        self.clear_alarm(&Alarm::NoJob);
+        if planting {
+            self.start_pass();
+        }
+    }
+
+    // records what is going in the ground on the active job
+    pub fn set_job_variety(&mut self, variety: &Variety, lot: Option<&SeedLot>) {
+        let Some(job) = self.job.as_mut() else {
+            return;
+        };
+        let lot = lot.map(|l| l.lot.clone());
+        if let Some(db) = &self.db {
+            if let Err(e) =
+                db.set_job_variety(job.id, &variety.name, lot.as_deref(), variety.spacing_in)
+            {
+                eprintln!("failed to record variety: {e}");
+            }
+        }
+        job.variety = Some(variety.name.clone());
+        job.seed_lot = lot.clone();
+        job.spacing_in = variety.spacing_in;
+        self.log_job();
+        self.note(format!(
+            "variety {} {}",
+            variety.name,
+            lot.unwrap_or_default()
+        ));
+    }
+
+    // picks the job back up after a restart
+    pub fn resume_job(&mut self) {
+        let (Some(db), Some(id)) = (&self.db, self.counters.job_id) else {
+            return;
+        };
+        match db.job(id) {
+            Ok(job) if job.ended_at.is_none() => {
+                self.cover_field(job.field_id);
+                self.log.set_field(Some(job.field.clone()));
+                self.job = Some(job);
+                self.log_job();
+                self.load_prescription();
+                if let Some(pass) = &self.counters.pass {
+                    let ended_at = pass.updated_at.clone();
+                    self.record_pass(ended_at);
+                }
+            }
+            Ok(_) => {
+                self.counters.job_id = None;
+                self.counters.pass = None;
+            }
+            Err(e) => eprintln!("failed to resume job {id}: {e}"),
+        }
+    }
+
+    // coverage from a restart is kept when it is on the same field
+    fn cover_field(&mut self, field_id: i64) {
+        if self.coverage.field_id != Some(field_id) {
+            self.coverage.clear();
+            self.coverage.field_id = Some(field_id);
+        }
+    }
+
+    pub fn finish_job(&mut self) {
+        self.end_pass();
+        let Some(job) = self.job.take() else {
+            return;
+        };
+        if let Some(db) = &self.db {
+            if let Err(e) = db.end_job(job.id, &db::timestamp()) {
+                eprintln!("failed to end job: {e}");
+            }
+        }
+        self.note(format!("finished {}", job.field));
+        self.counters.job_id = None;
+        self.log.set_field(None);
+        self.log_job();
+        self.variable_rate.load(None);
+    }
+
+    // the job's field prescription, if it has one
+    fn load_prescription(&mut self) {
+        let (Some(db), Some(job)) = (&self.db, &self.job) else {
+            return;
+        };
+        let rx = db.prescription(job.field_id).unwrap_or_else(|e| {
+            eprintln!("failed to load prescription: {e}");
+            None
+        });
+        if rx.is_some() {
+            self.note(format!("prescription for {}", job.field));
+        }
+        self.variable_rate.load(rx);
+    }
+
+    // the target follows the zone the planter reaches after the look ahead
+    fn follow_prescription(&mut self, planting: bool) {
+        if !self.variable_rate.is_active() {
+            return;
+        }
+        let (Some(proj), Some(at)) = (self.coverage.projection(), self.coverage.position) else {
+            return;
+        };
+        let heading = self.coverage.heading.unwrap_or(0.0) as f64;
+        let secs = self.variable_rate.cfg.look_ahead.as_secs_f32();
+        let ahead = (mph_to_fps(self.ground_speed_mph) * secs) as f64 * M_PER_FT;
+        let (sin, cos) = heading.to_radians().sin_cos();
+        let ahead = proj.to_geo(Xy {
+            x: at.x + sin * ahead,
+            y: at.y + cos * ahead,
+        });
+        if let Some(spacing) = self.variable_rate.update(ahead) {
+            self.set_spacing(spacing);
+        }
+        if planting {
+            self.log.record(Record::Rate {
+                zone: self.variable_rate.zone.clone(),
+                prescribed_in: self.variable_rate.prescribed_in,
+                target_in: self.spacing(),
+                actual_in: self.spacing_ratio().map(|r| r * self.spacing()),
+            });
+        }
+    }
+
+    fn start_pass(&mut self) {
+        let Some(job) = &self.job else {
+            self.raise_alarm(Alarm::NoJob);
+            return;
+        };
+        let started_at = db::timestamp();
+        if let Some(db) = &self.db {
+            if let Err(e) = db.start_job(job.id, &started_at) {
+                eprintln!("failed to start job: {e}");
+            }
+        }
+        self.counters.pass = Some(OpenPass {
+            updated_at: started_at.clone(),
+            started_at,
+            ..OpenPass::default()
+        });
+    }
+
+    fn end_pass(&mut self) {
+        self.record_pass(db::timestamp());
+    }
+
+    fn record_pass(&mut self, ended_at: String) {
+        let spacing_in = self.spacing();
+        let (Some(pass), Some(job)) = (self.counters.pass.take(), self.job.as_mut()) else {
+            return;
+        };
+        if pass.row_feet <= 0.0 {
+            return;
+        }
+        let summary = PassSummary {
+            started_at: pass.started_at,
+            ended_at,
+            row_feet: pass.row_feet,
+            avg_speed_mph: pass.speed_sum / pass.samples.max(1) as f32,
+            spacing_in,
+            seed_cwt: pass.seed_cwt,
+            start_spacing_in: (pass.start_samples > 0)
+                .then(|| pass.start_ratio_sum / pass.start_samples as f32 * spacing_in),
+        };
+        job.row_feet += summary.row_feet;
+        job.seed_cwt += summary.seed_cwt;
+        if let Some(db) = &self.db {
+            if let Err(e) = db.record_pass(job.id, &summary) {
+                eprintln!("failed to record pass: {e}");
+            }
+        }
+    }
+
+    // seed moved from the truck into the planter
+    pub fn bulk_fill(&mut self, cwt: f32) {
+        let fill = BulkFill {
+            job_id: self.job.as_ref().map(|j| j.id),
+            at: db::timestamp(),
+            cwt,
+            estimated_used_cwt: self.counters.seed_cwt,
+        };
+        if let Some(db) = &self.db {
+            if let Err(e) = db.record_bulk_fill(&fill) {
+                eprintln!("failed to record bulk fill: {e}");
+            }
+        }
+        self.counters.onboard_cwt += cwt;
+        self.counters.truck_cwt = (self.counters.truck_cwt - cwt).max(0.0);
+        self.note(format!("bulk fill {cwt:.1} cwt"));
+        self.clear_alarm(&Alarm::CallTruck);
+    }
+
+    pub fn truck_load(&mut self, cwt: f32) {
+        self.counters.truck_cwt = cwt;
+        self.note(format!("truck load {cwt:.1} cwt"));
+    }
+
+    // recent history kept with the counters, shown after a restart
+    pub fn note(&mut self, text: String) {
+        let time = Local::now().format("%m-%d %H:%M:%S");
+        self.counters.push_log(format!("{time} {text}"));
+    }
+
+    pub fn raise_alarm(&mut self, alarm: Alarm) {
+        let text = alarm.to_string();
+        if self.alarms.raise(alarm) {
+            self.note(text);
+            self.update_alarm_outputs();
+        }
+    }
+
+    pub fn clear_alarm(&mut self, alarm: &Alarm) {
+        if self.alarms.clear(alarm) {
+            self.update_alarm_outputs();
+        }
+    }
+
+    pub fn acknowledge_alarms(&mut self) {
+        self.alarms.acknowledge();
+        self.update_alarm_outputs();
+    }
+
+    fn update_alarm_outputs(&self) {
+        let out = self.alarms.outputs();
+        self.send(Cmd::Buzzer(out.buzzer));
+        self.send(Cmd::Beacon(out.beacon));
+    }
+
+    pub fn tick(&mut self, now: Instant) {
+        let dt = self
+            .last_tick
+            .map(|t| now.saturating_duration_since(t))
+            .unwrap_or_default();
+        self.last_tick = Some(now);
+        let on: [bool; ROWS] = std::array::from_fn(|r| self.sections.is_on(r));
+        if self.mode == Mode::Planting && self.planting() {
+            let ft = mph_to_fps(self.ground_speed_mph) * dt.as_secs_f32();
+            // rows shut off by section control plant nothing
+            let row_feet = ft * on.iter().filter(|&&on| on).count() as f32;
+            self.counters.add_feet(row_feet);
+            let ratio = self.spacing_ratio();
+            let measure_ft = self.start_cfg.measure_ft;
+            if let Some(pass) = self.counters.pass.as_mut() {
+                pass.updated_at = db::timestamp();
+                if let (true, Some(ratio)) = (pass.feet < measure_ft, ratio) {
+                    pass.start_ratio_sum += ratio;
+                    pass.start_samples += 1;
+                }
+                pass.feet += ft;
+                pass.row_feet += row_feet;
+                pass.speed_sum += self.ground_speed_mph;
+                pass.samples += 1;
+            }
+        }
+        let cwt = self.seed_meter.take_cwt();
+        self.counters.add_seed(cwt);
+        if let Some(pass) = self.counters.pass.as_mut() {
+            pass.seed_cwt += cwt;
+            self.hoppers.record_usage(cwt, dt, on);
+        }
+        // the estimate only counts down once a bulk fill has been entered,
+        // a starving seed belt is noticed either way
+        let prediction = self
+            .bin_prediction(now)
+            .filter(|_| self.counters.onboard_cwt > 0.0 || self.hoppers.starving(now));
+        if self.hoppers.call_truck(prediction) {
+            self.raise_alarm(Alarm::CallTruck);
+        }
+        if let Some(journal) = self.journal.as_mut() {
+            if let Err(e) = journal.store(&self.counters, now) {
+                eprintln!("failed to store counters: {e}");
+            }
+        }
+
+        for id in 0..self.drives.len() {
+            if let Some(rate) = self.drives[id].tick_meter.update(now) {
+                self.drives[id].seed_wheel_speed_rpm = tickrate_to_rpm(rate);
+                self.learn_flow(id, now);
+            }
+        }
+        self.check_watchdog(now);
+        self.update_homing(now);
+        self.check_drives(now);
+
+        if !(self.mode.controller_active() && self.planting() && self.inputs_ok()) {
+            return;
+        }
+        for id in 0..self.drives.len() {
+            let drive = &self.drives[id];
+            // a drive whose rows are all released is held by section control
+            if !drive.rows.iter().any(|r| self.sections.is_on(*r)) || drive.homing.is_some() {
+                continue;
+            }
+            let mph = self.drive_speed(id);
+            let tickrate = rpm_to_tickrate(drive.seed_wheel_speed_rpm);
+            let target_tickrate = drive.controller.target_tickrate(mph);
+            let settling = drive.settle_until.map_or(false, |at| now < at);
+            let cmd = match self.feed_forward(id, tickrate_to_rpm(target_tickrate), now) {
+                Some(cmd) => Some(cmd),
+                None if settling => continue,
+                None => match self.drives[id].controller.update(id, mph, tickrate) {
+                    Some(Cmd::FlowPulse(id, rate, d)) => self.limit_pulse(id, rate, d, now),
+                    cmd => cmd,
+                },
+            };
+            self.log.record(Record::Control {
+                drive: id,
+                target_tickrate,
+                actual_tickrate: tickrate,
+                throttle: match cmd {
+                    Some(Cmd::FlowPulse(_, throttle, _)) => Some(throttle),
+                    _ => None,
+                },
+            });
+            if let Some(cmd) = cmd {
+                self.send(cmd);
+            }
+        }
+    }
+
+    // the pulse is cut short at the soft limits, and moves the valve model
+    fn limit_pulse(&mut self, id: usize, rate: f32, d: Duration, now: Instant) -> Option<Cmd> {
+        let drive = &mut self.drives[id];
+        drive.cut_short(now);
+        let d = drive.valve.limit(rate, d);
+        if d.is_zero() {
+            return None;
+        }
+        drive.valve.moved(rate, d);
+        drive.moved_at = Some(now + d);
+        drive.pulse = Some((rate, now + d));
+        Some(Cmd::FlowPulse(id, rate, d))
+    }
+
+    // a big change in target speed sends the valve straight to the learned position
+    fn feed_forward(&mut self, id: usize, rpm: f32, now: Instant) -> Option<Cmd> {
+        let cfg = &self.flow_map_cfg;
+        let drive = &mut self.drives[id];
+        let from = drive.valve.position()?;
+        if let Some(last) = drive.feed_forward_rpm {
+            if (rpm - last).abs() <= last.abs() * cfg.jump_threshold {
+                return None;
+            }
+        }
+        drive.feed_forward_rpm = Some(rpm);
+        let to = drive.flow_map.position_for(rpm)?;
+        let rate = if to > from { 1.0 } else { -1.0 };
+        let d = drive.valve.cfg.full_travel.mul_f32((to - from).abs());
+        drive.settle_until = Some(now + d + cfg.settle);
+        self.limit_pulse(id, rate, d, now)
+    }
+
+    // the drive's rows on average, slower on the inside of a curve
+    fn drive_speed(&self, id: usize) -> Speed {
+        let rows = &self.drives[id].rows;
+        let n = rows.len().max(1) as f32;
+        rows.iter().map(|r| self.row_speed(*r)).sum::<f32>() / n
+    }
+
+    // a seed wheel that does not follow its valve, checked while the controller runs
+    fn check_drives(&mut self, now: Instant) {
+        let active = self.mode.controller_active() && self.planting();
+        for id in 0..self.drives.len() {
+            let drive = &self.drives[id];
+            let checked = active
+                && drive.homing.is_none()
+                && drive.rows.iter().any(|r| self.sections.is_on(*r));
+            let faults = if checked {
+                let position = drive.valve.position();
+                // without a learned map, a valve opened to its limit should at least keep up
+                let at_limit = position.map_or(false, |p| p >= 1.0 - drive.valve.cfg.soft_limit);
+                let target =
+                    tickrate_to_rpm(drive.controller.target_tickrate(self.drive_speed(id)));
+                let expected = position
+                    .and_then(|p| drive.flow_map.rpm_at(p))
+                    .or(at_limit.then_some(target));
+                let drive = &mut self.drives[id];
+                let rpm = drive.seed_wheel_speed_rpm;
+                drive.diagnostics.update(now, position, expected, rpm)
+            } else {
+                self.drives[id].diagnostics.reset();
+                vec![]
+            };
+            for fault in WheelFault::ALL {
+                let alarm = Alarm::SeedWheel(id, fault);
+                if faults.contains(&fault) {
+                    // flow is stopped once, as for a stale input
+                    if fault == WheelFault::EncoderLost && !self.alarms.is_active(&alarm) {
+                        self.halt();
+                        self.raise_alarm(alarm);
+                        self.transition(ModeInput::Fault);
+                        continue;
+                    }
+                    self.raise_alarm(alarm);
+                } else {
+                    self.clear_alarm(&alarm);
+                }
+            }
+        }
+    }
+
+    // on lowering the seed wheel is started at once, and left to come up to speed
+    // while the openers go into the ground
+    fn prestart(&mut self, now: Instant) {
+        if !self.flow_allowed() || self.ground_speed_mph <= 0.0 {
+            return;
+        }
+        let lead = self.start_cfg.lead(self.ground_speed_mph);
+        for id in 0..self.drives.len() {
+            let mph = self.ground_speed_mph;
+            let drive = &self.drives[id];
+            let rpm = tickrate_to_rpm(drive.controller.target_tickrate(mph));
+            // the wheel then starts with the controller, once in the ground
+            if drive.valve.position().is_none() {
+                self.note(format!("valve {} not homed, no prestart", id + 1));
+            } else if drive.flow_map.position_for(rpm).is_none() {
+                self.note(format!("flow map {} not learned, no prestart", id + 1));
+            }
+            if let Some(cmd) = self.feed_forward(id, rpm, now) {
+                self.send(cmd);
+            }
+            let drive = &mut self.drives[id];
+            drive.settle_until = drive.settle_until.max(Some(now + lead));
+        }
+    }
+
+    // closed on raising so the wheel does not run on over the headland
+    fn close_valves(&mut self, now: Instant) {
+        for id in 0..self.drives.len() {
+            self.close_valve(id, now);
+        }
+    }
+
+    fn close_valve(&mut self, id: usize, now: Instant) {
+        if self.drives[id].homing.is_some() {
+            return;
+        }
+        let full_travel = self.drives[id].valve.cfg.full_travel;
+        if let Some(cmd) = self.limit_pulse(id, -1.0, full_travel, now) {
+            self.send(cmd);
+        }
+    }
+
+    // a drive with all its rows just released is stopped, and closed as on raising
+    fn stop_released(&mut self, changes: &[(usize, bool)], now: Instant) {
+        for id in 0..self.drives.len() {
+            let rows = &self.drives[id].rows;
+            let released = rows.iter().any(|r| changes.contains(&(*r, false)))
+                && !rows.iter().any(|r| self.sections.is_on(*r));
+            if !released {
+                continue;
+            }
+            self.drives[id].cut_short(now);
+            self.send(Cmd::FlowHold(id));
+            // coming back on is a jump like lowering
+            self.drives[id].feed_forward_rpm = None;
+            if self.start_cfg.close_on_raise {
+                self.close_valve(id, now);
+            }
+        }
+    }
+
+    // the wheel speed at a known valve position, once it has held since the last move,
+    // and only while the wheel is following its valve
+    fn learn_flow(&mut self, id: usize, now: Instant) {
+        let faulty = WheelFault::ALL
+            .into_iter()
+            .any(|f| self.alarms.is_active(&Alarm::SeedWheel(id, f)));
+        let cfg = &self.flow_map_cfg;
+        let drive = &mut self.drives[id];
+        if faulty || drive.diagnostics.pending() || drive.valve.at_limit() {
+            return;
+        }
+        let steady = drive
+            .moved_at
+            .map_or(true, |at| now.saturating_duration_since(at) >= cfg.steady);
+        let rpm = drive.seed_wheel_speed_rpm;
+        if let (true, Some(position)) = (steady && rpm > 0.0, drive.valve.position()) {
+            drive.flow_map.learn(cfg, position, rpm);
+        }
+    }
+
+    pub fn save_flow_maps(&self) {
+        let Some(path) = &self.flow_map_path else {
+            return;
+        };
+        let maps: Vec<_> = self.drives.iter().map(|d| &d.flow_map).collect();
+        if let Err(e) = flowmap::save(path, &maps) {
+            eprintln!("failed to save flow map: {e}");
+        }
     }
 
-    pub fn halt(&self) {
-        self.io.tx.send(Cmd::FlowHold);
+    pub fn save_coverage(&self) {
+        let Some(path) = &self.coverage_path else {
+            return;
+        };
+        if let Err(e) = coverage::save(path, &self.coverage) {
+            eprintln!("failed to save coverage: {e}");
+        }
+    }
+
+    pub fn save_valves(&self) {
+        let Some(path) = &self.valve_path else {
+            return;
+        };
+        let cals: Vec<_> = self.drives.iter().map(|d| d.valve.calibration()).collect();
+        if let Err(e) = valve::save(path, &cals) {
+            eprintln!("failed to save valve calibration: {e}");
+        }
+    }
+
+    pub fn home_valve(&mut self, id: usize) {
+        if self.planting() {
+            self.note("raise the planter to home the valve".to_string());
+            return;
+        }
+        if !self.flow_allowed() {
+            self.note(format!("no valve homing in mode {}", self.mode));
+            return;
+        }
+        if let Some(drive) = self.drives.get_mut(id) {
+            drive.homing = Some(Homing::default());
+            self.note(format!("homing valve {}", id + 1));
+        }
+    }
+
+    pub fn is_homing(&self, id: usize) -> bool {
+        self.drives[id].homing.is_some()
+    }
+
+    // homing runs out of the ground and gives way once planting starts, or when the
+    // mode stops flow motion. A valve with no known position, after a restart, is zeroed
+    // against its closed endstop as soon as the planter is out of the ground.
+    fn update_homing(&mut self, now: Instant) {
+        let planting = self.planting();
+        let allowed = self.flow_allowed();
+        let out_of_ground = !planting && self.mode != Mode::Planting && allowed;
+        for id in 0..self.drives.len() {
+            let drive = &mut self.drives[id];
+            let (rpm, pot) = (drive.seed_wheel_speed_rpm, drive.pot);
+            if drive.homing.is_none()
+                && out_of_ground
+                && !drive.valve.is_homed()
+                && !drive.valve.has_pot()
+            {
+                drive.homing = Some(Homing::zero());
+                self.note(format!("zeroing valve {}", id + 1));
+            }
+            let drive = &mut self.drives[id];
+            let Some(homing) = drive.homing.as_mut() else {
+                continue;
+            };
+            if planting || !allowed {
+                let what = if homing.is_zero() {
+                    "zeroing"
+                } else {
+                    "homing"
+                };
+                drive.homing = None;
+                self.send(Cmd::FlowHold(id));
+                self.note(format!("valve {} {what} stopped", id + 1));
+                continue;
+            }
+            match homing.update(now, rpm, pot) {
+                HomingStep::Wait => {}
+                HomingStep::Drive(rate, d) => {
+                    drive.moved_at = Some(now + d);
+                    drive.pulse = Some((rate, now + d));
+                    self.send(Cmd::FlowPulse(id, rate, d))
+                }
+                HomingStep::Done {
+                    full_travel,
+                    pot_range,
+                } => {
+                    drive.homing = None;
+                    drive.valve.homed(full_travel, pot_range);
+                    self.note(format!(
+                        "valve {} travel {:.1}s",
+                        id + 1,
+                        full_travel.as_secs_f32()
+                    ));
+                    self.save_valves();
+                    // back to closed, short of the endstop
+                    if let Some(cmd) = self.limit_pulse(id, -1.0, full_travel, now) {
+                        self.send(cmd);
+                    }
+                }
+                HomingStep::Closed => {
+                    drive.homing = None;
+                    drive.pulse = None;
+                    drive.valve.zeroed();
+                }
+                HomingStep::Failed => {
+                    drive.homing = None;
+                    self.note(format!("valve {} homing failed, no change in flow", id + 1));
+                }
+            }
+        }
+    }
+
+    pub fn load_boundaries(&mut self) {
+        let Some(db) = self.db.as_ref() else {
+            return;
+        };
+        match db.boundaries() {
+            Ok(b) => self.locator = FieldLocator::new(b),
+            Err(e) => eprintln!("failed to load boundaries: {e}"),
+        }
+    }
+
+    // planting with no boundary around the planter is worth a warning
+    fn locate(&mut self, p: LatLon) {
+        if self.locator.is_empty() {
+            return;
+        }
+        self.in_field = self.locator.locate(p);
+        if self.in_field.is_none() && self.mode == Mode::Planting && self.planting() {
+            self.raise_alarm(Alarm::OutsideBoundary);
+        } else {
+            self.clear_alarm(&Alarm::OutsideBoundary);
+        }
+    }
+
+    // where the row units are, so maps and exports show seed where it went
+    fn log_planter(&mut self, fix: &Fix) {
+        let (Some(proj), Some(at)) = (self.coverage.projection(), self.planter.centre()) else {
+            return;
+        };
+        let p = proj.to_geo(at);
+        self.log.record(Record::Planter(Fix {
+            latitude: p.lat,
+            longitude: p.lon,
+            speed_mph: fix.speed_mph,
+            heading: self.planter.heading(),
+        }));
+    }
+
+    // rows over planted ground or outside the field are released until they leave it
+    fn update_sections(&mut self, planting: bool, now: Instant) {
+        let rows = self.planter.rows().zip(self.planter.heading());
+        let changes = if let (true, Some((rows, heading))) = (planting, rows) {
+            let proj = self.coverage.projection().copied();
+            let locator = &self.locator;
+            let inside = |p: Xy| match proj {
+                Some(proj) if !locator.is_empty() => {
+                    locator.boundaries().any(|b| b.contains(proj.to_geo(p)))
+                }
+                _ => true,
+            };
+            self.sections
+                .update(&self.coverage, rows, heading, inside, self.ground_speed_mph)
+        } else {
+            self.sections.reset()
+        };
+        if changes.is_empty() {
+            return;
+        }
+        for (row, on) in &changes {
+            self.send(Cmd::RowClutch(*row, *on));
+        }
+        self.stop_released(&changes, now);
+    }
+
+    // spacing in the ground over the target
+    pub fn spacing_ratio(&self) -> Option<f32> {
+        let tickrate = rpm_to_tickrate(self.seed_wheel_speed_rpm());
+        tickrate_to_spacing(tickrate, self.ground_speed_mph).map(|s| s / self.spacing())
+    }
+
+    // rows on the inside of a curve travel slower than the planter centre
+    pub fn row_speed(&self, row: usize) -> Speed {
+        self.curve
+            .row_speeds(self.ground_speed_mph, self.planter.cfg.row_offsets_m)[row]
+    }
+
+    // what a drive on this row alone would run at
+    pub fn row_target_tickrate(&self, row: usize) -> TickRate {
+        let Some(drive) = self.row_drive(row) else {
+            return 0;
+        };
+        drive.controller.target_tickrate(self.row_speed(row))
+    }
+
+    // spacing in the ground on one row over the target, at its drive's wheel speed
+    pub fn row_spacing_ratio(&self, row: usize) -> Option<f32> {
+        let tickrate = rpm_to_tickrate(self.row_drive(row)?.seed_wheel_speed_rpm);
+        tickrate_to_spacing(tickrate, self.row_speed(row)).map(|s| s / self.spacing())
+    }
+
+    pub fn row_in_tolerance(&self, row: usize) -> bool {
+        self.row_spacing_ratio(row)
+            .map_or(true, |r| (r - 1.0).abs() <= self.curve.cfg.tolerance)
+    }
+
+    // lets the log show which curves were out of tolerance
+    fn log_rows(&self) {
+        for row in 0..ROWS {
+            self.log.record(Record::Row {
+                row,
+                speed_mph: self.row_speed(row),
+                target_tickrate: self.row_target_tickrate(row),
+                spacing_in: self.row_spacing_ratio(row).map(|r| r * self.spacing()),
+            });
+        }
+    }
+
+    pub fn bin_prediction(&self, now: Instant) -> Option<Prediction> {
+        self.hoppers
+            .predict(self.counters.onboard_cwt, self.ground_speed_mph, now)
+    }
+
+    pub fn inputs_ok(&self) -> bool {
+        !Source::ALL
+            .into_iter()
+            .any(|s| self.alarms.is_active(&Alarm::StaleInput(s)))
+    }
+
+    pub fn planting(&self) -> bool {
+        !self.planter_raised && self.ground_speed_mph > 0.0
+    }
+
+    // flow is stopped once when a critical input goes stale while planting
+    pub fn check_watchdog(&mut self, now: Instant) {
+        let planting = self.planting();
+        for source in Source::ALL {
+            let alarm = Alarm::StaleInput(source);
+            if planting && self.watchdog.is_stale(source, now) {
+                if !self.alarms.is_active(&alarm) {
+                    self.halt();
+                    self.raise_alarm(alarm);
+                    self.transition(ModeInput::Fault);
+                }
+            } else {
+                self.clear_alarm(&alarm);
+            }
+        }
     }
 
     pub fn handle_event(&mut self, e: Event) {
+        self.handle_event_at(e, Instant::now())
+    }
+
+    pub fn handle_event_at(&mut self, e: Event, now: Instant) {
+        match &e {
+            // ticks are summarized by the control records
+            Event::SeedWheelTick(_) | Event::SeedWheelReversed(_) | Event::ValvePosition(..) => {}
+            Event::GpsFix(fix) => self.log.record(Record::Fix(*fix)),
+            e => self.log.record(Record::Event(e.clone())),
+        }
+
+        match &e {
+            Event::GroundSpeed(_) => self.watchdog.feed(Source::GroundSpeed, now),
+            Event::PlanterRaised | Event::PlanterLowered => self.watchdog.feed(Source::Lift, now),
+            _ => {}
+        }
+
         match e {
-            Event::SeedWheelTick => {}
-            Event::PlanterRaised => self.planter_raised = true,
-            Event::PlanterLowered => self.planter_raised = false,
-            Event::GroundSpeed(mph) => self.ground_speed_mph = mph,
-            Event::HopperEmpty(n) => self.priming[n] = true,
-            Event::HopperFull(n) => self.priming[n] = false,
-            Event::SeedWheelSpeed(rpm) => self.seed_wheel_speed_rpm = rpm,
+            Event::SeedWheelTick(id) => {
+                // seed only reaches the ground in it, from the rows that are engaged
+                let planting = self.mode == Mode::Planting && self.planting();
+                if let Some(drive) = self.drives.get_mut(id) {
+                    drive.tick_meter.tick();
+                    drive.diagnostics.tick(false, now);
+                    if planting {
+                        let rows = drive.rows.iter().filter(|r| self.sections.is_on(**r));
+                        self.seed_meter.tick(rows.count());
+                    }
+                }
+            }
+            // turning, but no seed is picked going backwards
+            Event::SeedWheelReversed(id) => {
+                if let Some(drive) = self.drives.get_mut(id) {
+                    drive.tick_meter.tick();
+                    drive.diagnostics.tick(true, now);
+                }
+            }
+            Event::PlanterRaised => {
+                self.planter_raised = true;
+                let was_planting = self.mode == Mode::Planting;
+                self.transition(ModeInput::Raised);
+                // after the hold, which would otherwise cut the close short
+                if self.start_cfg.close_on_raise && was_planting {
+                    self.close_valves(now);
+                }
+                self.save_flow_maps();
+            }
+            Event::PlanterLowered => {
+                self.planter_raised = false;
+                // nothing is expected while out of the ground, and the wheels are given
+                // the prestart lead to come up to speed
+                for source in Source::ALL {
+                    self.watchdog.feed(source, now);
+                }
+                let lead = self.start_cfg.lead(self.ground_speed_mph);
+                // the first target in the ground is always a jump
+                for drive in self.drives.iter_mut() {
+                    drive.feed_forward_rpm = None;
+                    drive.diagnostics.rearm(now + lead);
+                }
+                self.transition(ModeInput::Lowered);
+                self.prestart(now);
+            }
+            Event::GroundSpeed(mph) => self.transition(ModeInput::GroundSpeed(mph)),
+            Event::HopperEmpty(n) => {
+                self.priming[n] = true;
+                self.hoppers.empty(n, now);
+            }
+            Event::HopperFull(n) => {
+                self.priming[n] = false;
+                self.hoppers.full(n, now);
+            }
+            Event::ValvePosition(id, reading) => {
+                if let Some(drive) = self.drives.get_mut(id) {
+                    drive.pot = Some(reading);
+                    drive.valve.feedback(reading);
+                }
+            }
+            Event::SeedWheelSpeed(id, rpm) => {
+                if let Some(drive) = self.drives.get_mut(id) {
+                    drive.seed_wheel_speed_rpm = rpm;
+                    drive.diagnostics.signal(now);
+                    self.learn_flow(id, now);
+                }
+            }
+            Event::FlowError(id, _) => self.raise_alarm(Alarm::FlowDrive(id)),
+            Event::AlarmAck => self.acknowledge_alarms(),
+            Event::GpsFix(fix) => {
+                self.locate(LatLon {
+                    lat: fix.latitude,
+                    lon: fix.longitude,
+                });
+                let planting = self.mode == Mode::Planting && self.planting();
+                let accuracy = Accuracy::from_ratio(self.spacing_ratio());
+                let antenna = self.coverage.to_local(&fix);
+                self.planter.update(antenna, fix.heading);
+                let at = self.planter.centre().unwrap_or(antenna);
+                let heading = self.planter.heading().or(fix.heading);
+                if self.coverage.update(at, heading, accuracy, planting) {
+                    self.save_coverage();
+                }
+                self.curve.update(self.planter.heading(), now);
+                self.log_planter(&fix);
+                if planting {
+                    self.log_rows();
+                }
+                self.update_sections(planting, now);
+                self.follow_prescription(planting);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crossbeam_channel::Receiver;
+
+    fn monitor(drives: Vec<Drive>) -> (Monitor, Receiver<Cmd>) {
+        let (io, _, cmds) = IO::detached();
+        let monitor = Monitor {
+            io,
+            drives,
+            ..Default::default()
+        };
+        (monitor, cmds)
+    }
+
+    fn held(cmds: &Receiver<Cmd>, id: usize) -> bool {
+        cmds.try_iter()
+            .any(|c| matches!(c, Cmd::FlowHold(d) if d == id))
+    }
+
+    #[test]
+    fn headland_turn_does_not_fault() {
+        let (mut m, cmds) = monitor(vec![Drive::new(vec![0, 1])]);
+        let t = Instant::now();
+        let ms = Duration::from_millis;
+
+        m.handle_event_at(Event::GroundSpeed(3.0), t);
+        m.handle_event_at(Event::PlanterLowered, t);
+        m.handle_event_at(Event::SeedWheelTick(0), t + ms(1000));
+        m.tick(t + ms(1000));
+        m.handle_event_at(Event::PlanterRaised, t + ms(2000));
+        m.tick(t + ms(3000));
+
+        // a long turn, nothing from the encoder out of the ground
+        m.handle_event_at(Event::GroundSpeed(3.0), t + ms(10000));
+        m.tick(t + ms(10000));
+        m.handle_event_at(Event::PlanterLowered, t + ms(10000));
+        m.tick(t + ms(11000));
+        m.tick(t + ms(12500));
+        assert_eq!(m.mode, Mode::Planting);
+        cmds.try_iter().count();
+
+        // but once the valve is open the wheel has to turn
+        for i in 26..80 {
+            let now = t + ms(500 * i);
+            m.handle_event_at(Event::GroundSpeed(3.0), now);
+            m.tick(now);
+            if m.mode == Mode::Fault {
+                break;
+            }
+        }
+        assert!(m
+            .alarms
+            .is_active(&Alarm::SeedWheel(0, WheelFault::EncoderLost)));
+        assert_eq!(m.mode, Mode::Fault);
+        assert!(held(&cmds, 0));
+    }
+
+    #[test]
+    fn encoder_is_watched_per_drive() {
+        let (mut m, _cmds) = monitor(vec![Drive::new(vec![0]), Drive::new(vec![1])]);
+        let t = Instant::now();
+        let ms = Duration::from_millis;
+
+        m.handle_event_at(Event::GroundSpeed(3.0), t);
+        m.handle_event_at(Event::PlanterLowered, t);
+        for i in 1..8 {
+            let now = t + ms(500 * i);
+            m.handle_event_at(Event::GroundSpeed(3.0), now);
+            m.handle_event_at(Event::SeedWheelTick(1), now);
+            m.tick(now);
+        }
+        assert!(m
+            .alarms
+            .is_active(&Alarm::SeedWheel(0, WheelFault::EncoderLost)));
+        assert!(!m
+            .alarms
+            .is_active(&Alarm::SeedWheel(1, WheelFault::EncoderLost)));
+        assert_eq!(m.mode, Mode::Fault);
+    }
+
+    #[test]
+    fn prestart_says_why_not() {
+        let (mut m, _cmds) = monitor(vec![Drive::new(vec![0, 1])]);
+        let t = Instant::now();
+        let noted = |m: &Monitor, text: &str| m.counters.log_tail.iter().any(|l| l.ends_with(text));
+
+        m.handle_event_at(Event::GroundSpeed(3.0), t);
+        m.handle_event_at(Event::PlanterLowered, t);
+        assert!(noted(&m, "valve 1 not homed, no prestart"));
+
+        m.handle_event_at(Event::PlanterRaised, t);
+        m.drives[0].valve.zeroed();
+        m.handle_event_at(Event::PlanterLowered, t);
+        assert!(noted(&m, "flow map 1 not learned, no prestart"));
+    }
+
+    #[test]
+    fn learns_only_from_a_healthy_wheel() {
+        let (mut m, _cmds) = monitor(vec![Drive::new(vec![0, 1])]);
+        let t = Instant::now();
+        let full_travel = m.drives[0].valve.cfg.full_travel;
+        let learned = |m: &Monitor| m.drives[0].flow_map.rpm_at(0.6).is_some();
+
+        // against the closed stop, then a good point well open
+        m.drives[0].valve.zeroed();
+        m.handle_event_at(Event::SeedWheelSpeed(0, 20.0), t);
+        m.drives[0].valve.moved(1.0, full_travel.mul_f32(0.7));
+        m.handle_event_at(Event::SeedWheelSpeed(0, 40.0), t);
+        assert_eq!(m.drives[0].flow_map.rpm_at(0.5), None);
+
+        // a stall has been seen but not yet raised
+        m.drives[0].valve.moved(-1.0, full_travel.mul_f32(0.2));
+        m.drives[0]
+            .diagnostics
+            .update(t, Some(0.5), Some(30.0), 5.0);
+        m.handle_event_at(Event::SeedWheelSpeed(0, 5.0), t);
+        assert!(!learned(&m));
+
+        // raised
+        m.drives[0].diagnostics.reset();
+        m.raise_alarm(Alarm::SeedWheel(0, WheelFault::Stalled));
+        m.handle_event_at(Event::SeedWheelSpeed(0, 5.0), t);
+        assert!(!learned(&m));
+
+        m.clear_alarm(&Alarm::SeedWheel(0, WheelFault::Stalled));
+        m.handle_event_at(Event::SeedWheelSpeed(0, 30.0), t);
+        assert!(learned(&m));
+    }
+
+    #[test]
+    fn released_drive_is_stopped_and_closed() {
+        let (mut m, cmds) = monitor(vec![Drive::new(vec![0]), Drive::new(vec![1])]);
+        let t = Instant::now();
+        for drive in m.drives.iter_mut() {
+            drive.valve.homed(Duration::from_secs(2), None);
+        }
+        m.sections.cfg.enabled = true;
+
+        // row 1 runs out of the field
+        let rows = [Xy { x: 0.0, y: 0.0 }, Xy { x: 1.0, y: 0.0 }];
+        let changes = m
+            .sections
+            .update(&m.coverage, rows, 0.0, |p| p.x < 0.5, 4.0);
+        m.stop_released(&changes, t);
+        let sent: Vec<_> = cmds.try_iter().collect();
+        assert!(matches!(sent[..], [Cmd::FlowHold(1), Cmd::FlowPulse(1, rate, _)] if rate < 0.0));
+        assert!(m.drives[1].valve.position().unwrap() < 0.05);
+        assert_eq!(m.drives[0].valve.position(), Some(1.0));
+    }
+
+    #[test]
+    fn transport_limit_holds_without_a_mode_change() {
+        let (mut m, cmds) = monitor(vec![Drive::new(vec![0, 1])]);
+        let t = Instant::now();
+        m.transition(ModeInput::Select(Mode::Transport));
+        m.handle_event_at(Event::GroundSpeed(5.0), t);
+        m.simulate(Cmd::SeedBeltControl(0, true));
+        m.priming[0] = true;
+        m.simulate(Cmd::FlowThrottle(0, 0.5));
+        cmds.try_iter().count();
+
+        // on the road, the mode stays transport
+        m.handle_event_at(Event::GroundSpeed(12.0), t);
+        assert_eq!(m.mode, Mode::Transport);
+        let sent: Vec<_> = cmds.try_iter().collect();
+        assert!(sent.iter().any(|c| matches!(c, Cmd::FlowHold(0))));
+        assert!(sent
+            .iter()
+            .any(|c| matches!(c, Cmd::SeedBeltControl(0, false))));
+        assert!(!m.priming[0]);
+
+        // the soft io page cannot get round it
+        m.simulate(Cmd::FlowThrottle(0, 0.5));
+        m.simulate(Cmd::SeedBeltControl(0, true));
+        assert_eq!(cmds.try_iter().count(), 0);
+        // nor is anything sent again for the next fix
+        m.handle_event_at(Event::GroundSpeed(12.5), t);
+        assert_eq!(cmds.try_iter().count(), 0);
+    }
+
+    #[test]
+    fn no_homing_on_the_road() {
+        let (mut m, cmds) = monitor(vec![Drive::new(vec![0, 1])]);
+        let t = Instant::now();
+        let pulsed =
+            |cmds: &Receiver<Cmd>| cmds.try_iter().any(|c| matches!(c, Cmd::FlowPulse(..)));
+        m.transition(ModeInput::Select(Mode::Transport));
+        m.handle_event_at(Event::PlanterRaised, t);
+        m.handle_event_at(Event::GroundSpeed(12.0), t);
+        m.tick(t);
+        assert!(!m.is_homing(0));
+        m.home_valve(0);
+        assert!(!m.is_homing(0));
+        assert!(!pulsed(&cmds));
+
+        // slowed down, the unknown valve is zeroed
+        m.handle_event_at(Event::GroundSpeed(3.0), t);
+        m.tick(t);
+        assert!(m.is_homing(0));
+        assert!(pulsed(&cmds));
+
+        // and back up to road speed it stops
+        m.handle_event_at(Event::GroundSpeed(12.0), t);
+        m.tick(t);
+        assert!(!m.is_homing(0));
+        assert!(held(&cmds, 0));
+    }
+
+    #[test]
+    fn fault_clears_only_on_idle() {
+        let (mut m, _cmds) = monitor(vec![Drive::new(vec![0, 1])]);
+        let t = Instant::now();
+        m.transition(ModeInput::Fault);
+        for e in [
+            Event::PlanterLowered,
+            Event::PlanterRaised,
+            Event::GroundSpeed(3.0),
+        ] {
+            m.handle_event_at(e, t);
         }
+        m.transition(ModeInput::Select(Mode::Prime));
+        m.transition(ModeInput::Select(Mode::Transport));
+        assert_eq!(m.mode, Mode::Fault);
+        m.transition(ModeInput::Select(Mode::Idle));
+        assert_eq!(m.mode, Mode::Idle);
+    }
+
+    #[test]
+    fn seed_is_counted_only_while_planting() {
+        let (mut m, _cmds) = monitor(vec![Drive::new(vec![0, 1])]);
+        let t = Instant::now();
+        let ticks = |m: &mut Monitor, n| {
+            for _ in 0..n {
+                m.handle_event_at(Event::SeedWheelTick(0), t);
+            }
+        };
+
+        // priming or turning the wheel by hand on the headland
+        m.handle_event_at(Event::GroundSpeed(3.0), t);
+        ticks(&mut m, 1000);
+        assert_eq!(m.seed_meter.take_cwt(), 0.0);
+
+        m.handle_event_at(Event::PlanterLowered, t);
+        ticks(&mut m, 1000);
+        let both = m.seed_meter.take_cwt();
+        assert!(both > 0.0);
+
+        // one row released by section control
+        m.sections.cfg.enabled = true;
+        let rows = [Xy { x: 0.0, y: 0.0 }, Xy { x: 1.0, y: 0.0 }];
+        m.sections
+            .update(&m.coverage, rows, 0.0, |p| p.x < 0.5, 4.0);
+        ticks(&mut m, 1000);
+        assert!((m.seed_meter.take_cwt() - both / 2.0).abs() < 1e-6);
+
+        m.handle_event_at(Event::PlanterRaised, t);
+        ticks(&mut m, 1000);
+        assert_eq!(m.seed_meter.take_cwt(), 0.0);
+    }
+
+    #[test]
+    fn row_feet_only_for_rows_planting() {
+        let (mut m, _cmds) = monitor(vec![Drive::new(vec![0, 1])]);
+        let t = Instant::now();
+        let secs = Duration::from_secs;
+        m.handle_event_at(Event::GroundSpeed(3.0), t);
+        m.handle_event_at(Event::PlanterLowered, t);
+        m.mode = Mode::Planting;
+        m.counters.pass = Some(OpenPass::default());
+        m.tick(t);
+        m.tick(t + secs(1));
+        let fps = mph_to_fps(3.0);
+        assert!((m.counters.feet_planted - 2.0 * fps).abs() < 1e-3);
+
+        // one row released by section control
+        m.sections.cfg.enabled = true;
+        let rows = [Xy { x: 0.0, y: 0.0 }, Xy { x: 1.0, y: 0.0 }];
+        m.sections
+            .update(&m.coverage, rows, 0.0, |p| p.x < 0.5, 4.0);
+        m.tick(t + secs(2));
+        assert!((m.counters.feet_planted - 3.0 * fps).abs() < 1e-3);
+        let pass = m.counters.pass.as_ref().unwrap();
+        assert!((pass.row_feet - 3.0 * fps).abs() < 1e-3);
+        assert!((pass.feet - 2.0 * fps).abs() < 1e-3);
+    }
+
+    #[test]
+    fn pass_survives_a_power_cut() {
+        let db = Db::open_in_memory().unwrap();
+        let field = db.add_field("Home", "North 40", None).unwrap();
+        let job = db.add_job(field, None, 10.0, 34.0).unwrap();
+        let (mut m, _cmds) = monitor(vec![]);
+        m.db = Some(db);
+        // as read back from the journal
+        m.counters.job_id = Some(job);
+        m.counters.pass = Some(OpenPass {
+            started_at: "2023-05-14T08:00:00".into(),
+            updated_at: "2023-05-14T08:05:00".into(),
+            row_feet: 2000.0,
+            speed_sum: 3.0,
+            samples: 1,
+            ..OpenPass::default()
+        });
+
+        m.resume_job();
+        assert!(m.counters.pass.is_none());
+        let db = m.db.as_ref().unwrap();
+        let passes = db.passes(job).unwrap();
+        assert_eq!(passes.len(), 1);
+        assert_eq!(passes[0].ended_at, "2023-05-14T08:05:00");
+        assert_eq!(db.job(job).unwrap().row_feet, 2000.0);
     }
 }
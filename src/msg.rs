@@ -1,13 +1,35 @@
+use crate::alarm::Alarm;
+use crate::geo::Xy;
 use crate::io::{Cmd, Event};
+use crate::keypad::{Key, KeypadTarget};
+use crate::mode::Mode;
+use std::time::Instant;
 
 #[derive(Debug, Clone)]
 pub enum Message {
     Halt,
+    Shutdown,
+    SelectMode(Mode),
     IncreaseSpacing,
     DecreaseSpacing,
+    OpenKeypad(KeypadTarget),
+    KeypadPress(Key),
     ToggleAutoPrime(usize, bool),
     FillHopper(usize),
     TabSelected(usize),
+    ResetTally(usize),
     SimulateCmd(Cmd),
     IOEvent(Event),
+    Tick(Instant),
+    AcknowledgeAlarms,
+    RaiseAlarm(Alarm),
+    SelectField(i64),
+    SelectVariety(i64, Option<i64>),
+    FinishJob,
+    ExportTaskData,
+    Exported(Result<String, String>),
+    MapPan(Xy),
+    MapZoom(f32),
+    MapFollow,
+    HomeValve(usize),
 }
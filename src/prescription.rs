@@ -0,0 +1,398 @@
+use crate::boundary::{from_geojson, read_dbf, shp_records};
+use crate::geo::{Boundary, LatLon};
+use crate::util::population_to_spacing;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::time::Duration;
+
+// attribute names read as a spacing in inches or seeds per acre, any case
+const SPACING_KEYS: [&str; 2] = ["spacing", "spacing_in"];
+const POPULATION_KEYS: [&str; 4] = ["population", "pop", "seeds_ac", "rate"];
+const NAME_KEYS: [&str; 2] = ["name", "zone"];
+
+// grid values above this are seeds per acre, below it inches
+const POPULATION_THRESHOLD: f32 = 100.0;
+
+fn invalid(msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg.to_string())
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum Rate {
+    SpacingIn(f32),
+    Population(f32),
+}
+
+impl Rate {
+    fn from_value(v: f32) -> Self {
+        if v > POPULATION_THRESHOLD {
+            Rate::Population(v)
+        } else {
+            Rate::SpacingIn(v)
+        }
+    }
+
+    pub fn spacing_in(&self, row_width_in: f32) -> f32 {
+        match *self {
+            Rate::SpacingIn(s) => s,
+            Rate::Population(p) => population_to_spacing(p, row_width_in),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Zone {
+    pub name: String,
+    pub rate: Rate,
+    pub boundary: Boundary,
+}
+
+/// ESRI ASCII grid in degrees, row 0 to the north
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Grid {
+    ncols: usize,
+    nrows: usize,
+    west: f64,
+    south: f64,
+    cell: f64,
+    values: Vec<Option<f32>>,
+}
+
+impl Grid {
+    pub fn parse(s: &str) -> io::Result<Grid> {
+        let mut header = HashMap::new();
+        let mut tokens = s.split_whitespace().peekable();
+        // keywords until the first number
+        while let Some(key) = tokens.next_if(|t| t.parse::<f64>().is_err()) {
+            let value = tokens
+                .next()
+                .and_then(|v| v.parse::<f64>().ok())
+                .ok_or_else(|| invalid("bad grid header"))?;
+            header.insert(key.to_lowercase(), value);
+        }
+        let get = |k: &str| header.get(k).copied().ok_or_else(|| invalid(k));
+        let count = |k: &str| {
+            let n = get(k)?;
+            if !(n.is_finite() && n >= 1.0 && n.fract() == 0.0) {
+                return Err(invalid(&format!("bad grid {k}")));
+            }
+            Ok(n as usize)
+        };
+        let (ncols, nrows) = (count("ncols")?, count("nrows")?);
+        let size = ncols
+            .checked_mul(nrows)
+            .ok_or_else(|| invalid("grid too large"))?;
+        let cell = get("cellsize")?;
+        if !(cell.is_finite() && cell > 0.0) {
+            return Err(invalid("bad grid cellsize"));
+        }
+        // corners are the outside edge, centres are half a cell in
+        let west = get("xllcorner").or_else(|_| get("xllcenter").map(|x| x - cell / 2.0))?;
+        let south = get("yllcorner").or_else(|_| get("yllcenter").map(|y| y - cell / 2.0))?;
+        let nodata = header.get("nodata_value").copied();
+        let values: Vec<Option<f32>> = tokens
+            .map(|t| t.parse::<f64>().ok().filter(|v| Some(*v) != nodata))
+            .map(|v| v.map(|v| v as f32))
+            .collect();
+        if values.len() != size {
+            return Err(invalid("grid size does not match its header"));
+        }
+        Ok(Grid {
+            ncols,
+            nrows,
+            west,
+            south,
+            cell,
+            values,
+        })
+    }
+
+    pub fn rate_at(&self, p: LatLon) -> Option<Rate> {
+        let col = ((p.lon - self.west) / self.cell).floor();
+        let row_from_south = ((p.lat - self.south) / self.cell).floor();
+        if col < 0.0 || row_from_south < 0.0 {
+            return None;
+        }
+        let (col, row_from_south) = (col as usize, row_from_south as usize);
+        if col >= self.ncols || row_from_south >= self.nrows {
+            return None;
+        }
+        let row = self.nrows - 1 - row_from_south;
+        self.values[row * self.ncols + col].map(Rate::from_value)
+    }
+}
+
+/// Zones and an optional grid of target rates, zones take precedence
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct Prescription {
+    pub zones: Vec<Zone>,
+    pub grid: Option<Grid>,
+}
+
+impl Prescription {
+    pub fn rate_at(&self, p: LatLon) -> Option<(&str, Rate)> {
+        if let Some(z) = self.zones.iter().find(|z| z.boundary.contains(p)) {
+            return Some((&z.name, z.rate));
+        }
+        Some(("grid", self.grid.as_ref()?.rate_at(p)?))
+    }
+}
+
+// the rate and name of a zone from its attributes
+fn zone_attrs(attrs: &HashMap<String, String>, n: usize) -> Option<(String, Rate)> {
+    let find = |keys: &[&str]| {
+        attrs
+            .iter()
+            .find(|(k, v)| keys.contains(&k.to_lowercase().as_str()) && !v.is_empty())
+            .map(|(_, v)| v.clone())
+    };
+    let number = |keys: &[&str]| find(keys).and_then(|v| v.parse::<f32>().ok());
+    let rate = match (number(&SPACING_KEYS), number(&POPULATION_KEYS)) {
+        (Some(s), _) => Rate::SpacingIn(s),
+        (None, Some(p)) => Rate::Population(p),
+        (None, None) => return None,
+    };
+    let name = find(&NAME_KEYS).unwrap_or_else(|| format!("Zone {n}"));
+    Some((name, rate))
+}
+
+fn zones_from_geojson(v: &Value) -> Vec<Zone> {
+    let features = match v["type"].as_str() {
+        Some("FeatureCollection") => v["features"].as_array().cloned().unwrap_or_default(),
+        Some("Feature") => vec![v.clone()],
+        _ => vec![],
+    };
+    features
+        .iter()
+        .enumerate()
+        .filter_map(|(i, f)| {
+            let attrs: HashMap<String, String> = f["properties"]
+                .as_object()?
+                .iter()
+                .map(|(k, v)| match v {
+                    Value::String(s) => (k.clone(), s.clone()),
+                    v => (k.clone(), v.to_string()),
+                })
+                .collect();
+            let (name, rate) = zone_attrs(&attrs, i + 1)?;
+            Some(Zone {
+                name,
+                rate,
+                boundary: from_geojson(&f["geometry"]),
+            })
+        })
+        .collect()
+}
+
+fn zones_from_shp(shp: &[u8], dbf: &[u8]) -> Vec<Zone> {
+    shp_records(shp)
+        .into_iter()
+        .zip(read_dbf(dbf))
+        .enumerate()
+        .filter_map(|(i, (polygons, attrs))| {
+            let (name, rate) = zone_attrs(&attrs, i + 1)?;
+            Some(Zone {
+                name,
+                rate,
+                boundary: Boundary { polygons },
+            })
+        })
+        .collect()
+}
+
+/// Reads zones from GeoJSON or a Shapefile with its .dbf, or a grid from an ESRI ASCII .asc
+pub fn read_prescription(path: &Path) -> io::Result<Prescription> {
+    let ext = path
+        .extension()
+        .and_then(|e| e.to_str())
+        .unwrap_or_default()
+        .to_lowercase();
+    let rx = match ext.as_str() {
+        "geojson" | "json" => Prescription {
+            zones: zones_from_geojson(&serde_json::from_slice(&fs::read(path)?)?),
+            grid: None,
+        },
+        "shp" => Prescription {
+            zones: zones_from_shp(&fs::read(path)?, &fs::read(path.with_extension("dbf"))?),
+            grid: None,
+        },
+        "asc" => Prescription {
+            zones: vec![],
+            grid: Some(Grid::parse(&fs::read_to_string(path)?)?),
+        },
+        _ => return Err(invalid("expected .geojson, .shp or .asc")),
+    };
+    if rx.zones.is_empty() && rx.grid.is_none() {
+        return Err(invalid("no zones with a spacing or population"));
+    }
+    Ok(rx)
+}
+
+pub struct PrescriptionCfg {
+    // how far ahead of the planter to read the map, covers the controller response
+    pub look_ahead: Duration,
+    pub row_width_in: f32,
+    pub min_spacing_in: f32,
+    pub max_spacing_in: f32,
+}
+
+impl Default for PrescriptionCfg {
+    fn default() -> Self {
+        PrescriptionCfg {
+            look_ahead: Duration::from_secs(2),
+            row_width_in: 34.0,
+            min_spacing_in: 6.0,
+            max_spacing_in: 18.0,
+        }
+    }
+}
+
+/// Target spacing following the prescription of the job's field
+#[derive(Default)]
+pub struct VariableRate {
+    pub cfg: PrescriptionCfg,
+    map: Option<Prescription>,
+    pub zone: Option<String>,
+    // spacing of the zone ahead, None outside every zone
+    pub prescribed_in: Option<f32>,
+}
+
+impl VariableRate {
+    pub fn load(&mut self, map: Option<Prescription>) {
+        self.map = map;
+        self.zone = None;
+        self.prescribed_in = None;
+    }
+
+    pub fn is_active(&self) -> bool {
+        self.map.is_some()
+    }
+
+    // the spacing to set when the prescription ahead changes, a manual change
+    // holds until the next zone
+    pub fn update(&mut self, ahead: LatLon) -> Option<f32> {
+        let (zone, rate) = self.map.as_ref()?.rate_at(ahead).unzip();
+        // unlike f32::clamp this does not panic on a bad range
+        let spacing = rate
+            .map(|r| r.spacing_in(self.cfg.row_width_in))
+            .filter(|s| s.is_finite() && *s > 0.0)
+            .map(|s| s.max(self.cfg.min_spacing_in).min(self.cfg.max_spacing_in));
+        self.zone = zone.map(str::to_string);
+        let changed = spacing != self.prescribed_in;
+        self.prescribed_in = spacing;
+        spacing.filter(|_| changed)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    const GRID: &str = "ncols 2\nnrows 2\nxllcorner -100.0\nyllcorner 46.0\ncellsize 0.01\nNODATA_value -9999\n40000 -9999\n10 12\n";
+
+    #[test]
+    fn zones_and_grid() {
+        let square = |lon: f64| {
+            json!([[
+                [lon, 46.0],
+                [lon + 0.01, 46.0],
+                [lon + 0.01, 46.01],
+                [lon, 46.01]
+            ]])
+        };
+        let v = json!({"type": "FeatureCollection", "features": [
+            {"type": "Feature", "properties": {"Zone": "good", "SPACING": 9.5},
+             "geometry": {"type": "Polygon", "coordinates": square(-100.0)}},
+            {"type": "Feature", "properties": {"population": "20000"},
+             "geometry": {"type": "Polygon", "coordinates": square(-99.99)}},
+            {"type": "Feature", "properties": {"yield": 400},
+             "geometry": {"type": "Polygon", "coordinates": square(-99.98)}},
+        ]});
+        let rx = Prescription {
+            zones: zones_from_geojson(&v),
+            grid: Some(Grid::parse(GRID).unwrap()),
+        };
+        assert_eq!(rx.zones.len(), 2);
+        let at = |lat, lon| rx.rate_at(LatLon { lat, lon });
+        assert_eq!(at(46.005, -99.995), Some(("good", Rate::SpacingIn(9.5))));
+        assert_eq!(
+            at(46.005, -99.985),
+            Some(("Zone 2", Rate::Population(20000.0)))
+        );
+        // north west cell of the grid, outside the zones
+        assert_eq!(
+            at(46.015, -99.995),
+            Some(("grid", Rate::Population(40000.0)))
+        );
+        assert_eq!(at(46.015, -99.985), None);
+        assert_eq!(at(46.005, -100.005), None);
+
+        // stored as json in the database
+        let stored: Prescription =
+            serde_json::from_str(&serde_json::to_string(&rx).unwrap()).unwrap();
+        assert_eq!(stored.grid, rx.grid);
+        assert_eq!(
+            stored.rate_at(LatLon {
+                lat: 46.005,
+                lon: -99.985
+            }),
+            Some(("Zone 2", Rate::Population(20000.0)))
+        );
+    }
+
+    #[test]
+    fn rejects_bad_grid_headers() {
+        let header = |ncols: &str, nrows: &str, cell: &str| {
+            format!("ncols {ncols}\nnrows {nrows}\nxllcorner -100.0\nyllcorner 46.0\ncellsize {cell}\n1 2\n3 4\n")
+        };
+        assert!(Grid::parse(&header("2", "2", "0.01")).is_ok());
+        assert!(Grid::parse(&header("0", "2", "0.01")).is_err());
+        assert!(Grid::parse(&header("-2", "2", "0.01")).is_err());
+        assert!(Grid::parse(&header("2.5", "2", "0.01")).is_err());
+        assert!(Grid::parse(&header("2", "inf", "0.01")).is_err());
+        assert!(Grid::parse(&header("2", "NaN", "0.01")).is_err());
+        assert!(Grid::parse(&header("1e19", "1e19", "0.01")).is_err());
+        assert!(Grid::parse(&header("2", "2", "0")).is_err());
+        assert!(Grid::parse(&header("2", "2", "-0.01")).is_err());
+        assert!(Grid::parse(&header("2", "2", "inf")).is_err());
+    }
+
+    #[test]
+    fn follows_zone_changes() {
+        let mut vr = VariableRate::default();
+        assert_eq!(
+            vr.update(LatLon {
+                lat: 46.0,
+                lon: -100.0
+            }),
+            None
+        );
+        vr.load(Some(Prescription {
+            zones: vec![],
+            grid: Some(Grid::parse(GRID).unwrap()),
+        }));
+        let south_west = LatLon {
+            lat: 46.005,
+            lon: -99.995,
+        };
+        assert_eq!(vr.update(south_west), Some(10.0));
+        assert_eq!(vr.update(south_west), None);
+        assert_eq!(vr.prescribed_in, Some(10.0));
+        // 40000 seeds/ac on 34" rows is under the minimum spacing
+        let north_west = LatLon {
+            lat: 46.015,
+            lon: -99.995,
+        };
+        assert_eq!(vr.update(north_west), Some(6.0));
+        assert_eq!(vr.zone.as_deref(), Some("grid"));
+
+        // a range the wrong way round gives the maximum rather than a panic
+        vr.cfg.min_spacing_in = 20.0;
+        assert_eq!(vr.update(south_west), Some(vr.cfg.max_spacing_in));
+    }
+}
@@ -3,17 +3,34 @@ use crate::gui::BODY_HEIGHT;
 use crate::msg::Message;
 use crate::msg::Message::FillHopper;
 use iced::widget::{container, image, row, Button, Column, Container, Row, Text, Toggler};
-use iced::Length;
+use iced::{Color, Length};
 
 use crate::row_ui::Message::ToggleAutoPrime;
 
+const OUT_OF_TOLERANCE: Color = Color::from_rgb(0.85, 0.2, 0.2);
+
 pub fn make_row(dash: &Dash, id: usize) -> Container<Message> {
     let col = Column::new()
-        .push(row![Text::new(format!("Row {}", id + 1))])
+        .push(row![Text::new(if dash.row_on(id) {
+            format!("Row {}", id + 1)
+        } else {
+            format!("Row {} off", id + 1)
+        })])
         .push(row![
             gear_icon(dash.priming(id)),
             Button::new("Prime").on_press(FillHopper(id))
         ])
+        .push(match dash.row_spacing_error(id) {
+            Some((error, true)) => Text::new(format!("Spacing {:+.0}%", error * 100.0)),
+            Some((error, false)) => {
+                Text::new(format!("Spacing {:+.0}%", error * 100.0)).style(OUT_OF_TOLERANCE)
+            }
+            None => Text::new("Spacing -"),
+        })
+        .push(Text::new(match dash.hopper_duty(id) {
+            Some(duty) => format!("Belt duty {:.0}%", duty * 100.0),
+            None => "Belt duty -".to_string(),
+        }))
         .push(row![Toggler::new(
             "Auto: ".to_string(),
             dash.auto_prime_on(id),
@@ -0,0 +1,163 @@
+use crate::coverage::Coverage;
+use crate::geo::{Xy, M_PER_FT};
+use crate::monitor::ROWS;
+use crate::util::{mph_to_fps, Speed};
+use std::time::Duration;
+
+pub struct SectionCfg {
+    pub enabled: bool,
+    // each row on its own clutch, otherwise the whole planter stops
+    pub per_row: bool,
+    // how far ahead to look when a row is planting, covers actuator latency
+    pub look_ahead_off: Duration,
+    // how far ahead to look when a row is off, covers the seed drop once the wheel restarts
+    pub look_ahead_on: Duration,
+    pub row_width_m: f64,
+}
+
+impl Default for SectionCfg {
+    fn default() -> Self {
+        SectionCfg {
+            enabled: false,
+            per_row: true,
+            look_ahead_off: Duration::from_millis(500),
+            look_ahead_on: Duration::from_millis(1000),
+            row_width_m: 34.0 * 0.0254,
+        }
+    }
+}
+
+/// Shuts rows off over planted ground and outside the field
+pub struct Sections {
+    pub cfg: SectionCfg,
+    on: [bool; ROWS],
+}
+
+impl Default for Sections {
+    fn default() -> Self {
+        Sections {
+            cfg: Default::default(),
+            on: [true; ROWS],
+        }
+    }
+}
+
+impl Sections {
+    pub fn is_on(&self, row: usize) -> bool {
+        self.on[row]
+    }
+
+    pub fn any_on(&self) -> bool {
+        self.on.iter().any(|on| *on)
+    }
+
+    // where the row will be after the look ahead, on the local plane
+    fn row_ahead(&self, at: Xy, heading: f32, row: usize, mph: Speed) -> Xy {
+        let look = if self.on[row] {
+            self.cfg.look_ahead_off
+        } else {
+            self.cfg.look_ahead_on
+        };
+        let ahead = (mph_to_fps(mph) * look.as_secs_f32()) as f64 * M_PER_FT;
+        let (sin, cos) = (heading as f64).to_radians().sin_cos();
+        Xy {
+            x: at.x + sin * ahead,
+            y: at.y + cos * ahead,
+        }
+    }
+
+    /// Rows that change state given the row units and the planter heading,
+    /// inside tells whether a point is in the field
+    pub fn update(
+        &mut self,
+        coverage: &Coverage,
+        rows: [Xy; ROWS],
+        heading: f32,
+        inside: impl Fn(Xy) -> bool,
+        mph: Speed,
+    ) -> Vec<(usize, bool)> {
+        if !self.cfg.enabled {
+            return self.reset();
+        }
+        let swath = self.cfg.row_width_m * ROWS as f64;
+        let mut want = [true; ROWS];
+        for (row, want) in want.iter_mut().enumerate() {
+            let p = self.row_ahead(rows[row], heading, row, mph);
+            // planted once more than half the row is over an earlier swath
+            *want = inside(p) && !coverage.covered(p, swath);
+        }
+        // a single drive keeps planting while any row needs seed
+        if !self.cfg.per_row {
+            want = [want.iter().any(|w| *w); ROWS];
+        }
+        self.set(want)
+    }
+
+    // every row back on, as when the planter is raised
+    pub fn reset(&mut self) -> Vec<(usize, bool)> {
+        self.set([true; ROWS])
+    }
+
+    fn set(&mut self, want: [bool; ROWS]) -> Vec<(usize, bool)> {
+        let changes = (0..ROWS)
+            .filter(|r| self.on[*r] != want[*r])
+            .map(|r| (r, want[r]))
+            .collect();
+        self.on = want;
+        changes
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::coverage::Accuracy;
+
+    #[test]
+    fn shutoff_over_planted_ground() {
+        let mut c = Coverage::default();
+        let mut s = Sections::default();
+        s.cfg.enabled = true;
+        let all = |_: Xy| true;
+        let w = s.cfg.row_width_m;
+
+        // first pass north along x = 0, then raised
+        for y in 0..50 {
+            let at = Xy {
+                x: 0.0,
+                y: y as f64,
+            };
+            c.update(at, Some(0.0), Accuracy::Good, true);
+        }
+        c.update(Xy { x: 0.0, y: 50.0 }, Some(0.0), Accuracy::Good, false);
+
+        // back south one row width east, row 1 on the west side overlaps the first pass
+        let centre = Xy { x: w, y: 45.0 };
+        c.update(centre, Some(180.0), Accuracy::Good, true);
+        let rows = [
+            Xy {
+                x: w * 1.5,
+                y: 45.0,
+            },
+            Xy {
+                x: w * 0.5,
+                y: 45.0,
+            },
+        ];
+        assert_eq!(s.update(&c, rows, 180.0, all, 4.0), vec![(1, false)]);
+        assert!(s.is_on(0));
+        assert!(s.any_on());
+
+        // whole planter keeps going while one row needs seed
+        s.cfg.per_row = false;
+        assert_eq!(s.update(&c, rows, 180.0, all, 4.0), vec![(1, true)]);
+
+        // out of the field
+        assert_eq!(
+            s.update(&c, rows, 180.0, |_| false, 4.0),
+            vec![(0, false), (1, false)]
+        );
+        assert!(!s.any_on());
+        assert_eq!(s.reset().len(), 2);
+    }
+}
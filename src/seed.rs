@@ -0,0 +1,84 @@
+use crate::db::BulkFill;
+use crate::util::seed_per_ticks;
+
+pub struct SeedCfg {
+    // share of picks that carry a seed piece
+    pub pick_efficiency: f32,
+    // used when the variety has no piece weight
+    pub piece_weight_oz: f32,
+}
+
+impl Default for SeedCfg {
+    fn default() -> Self {
+        SeedCfg {
+            pick_efficiency: 0.9,
+            piece_weight_oz: 2.0,
+        }
+    }
+}
+
+/// Estimates seed used from the seed wheel encoder
+#[derive(Default)]
+pub struct SeedMeter {
+    pub cfg: SeedCfg,
+    // encoder ticks times the rows each one turns
+    ticks: usize,
+}
+
+impl SeedMeter {
+    pub fn tick(&mut self, rows: usize) {
+        self.ticks += rows;
+    }
+
+    // hundredweight used by all rows since the last call
+    pub fn take_cwt(&mut self) -> f32 {
+        let picks = seed_per_ticks(std::mem::take(&mut self.ticks));
+        picks * self.cfg.pick_efficiency * self.cfg.piece_weight_oz / 16.0 / 100.0
+    }
+}
+
+/// Ratio of seed actually loaded to the estimate, fill to fill.
+/// Assumes the bin is brought back to the same level at each fill,
+/// so what went in at a fill is what was used since the previous one.
+pub fn reconcile(fills: &[BulkFill]) -> Option<f32> {
+    let (first, rest) = fills.split_first()?;
+    let last = rest.last()?;
+    let loaded: f32 = rest.iter().map(|f| f.cwt).sum();
+    let estimated = last.estimated_used_cwt - first.estimated_used_cwt;
+    (estimated > 0.0).then(|| loaded / estimated)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::monitor::ROWS;
+
+    fn fill(cwt: f32, estimated_used_cwt: f32) -> BulkFill {
+        BulkFill {
+            job_id: None,
+            at: String::new(),
+            cwt,
+            estimated_used_cwt,
+        }
+    }
+
+    #[test]
+    fn seed_estimate() {
+        let mut meter = SeedMeter {
+            cfg: SeedCfg {
+                pick_efficiency: 1.0,
+                piece_weight_oz: 1.6,
+            },
+            ticks: 0,
+        };
+        // one revolution, 24 picks per row
+        (0..340).for_each(|_| meter.tick(ROWS));
+        let cwt = meter.take_cwt();
+        assert!((cwt - 24.0 * ROWS as f32 * 0.001).abs() < 1e-6);
+        assert_eq!(meter.take_cwt(), 0.0);
+
+        assert_eq!(reconcile(&[fill(50.0, 0.0)]), None);
+        let fills = [fill(50.0, 2.0), fill(40.0, 42.0), fill(48.0, 82.0)];
+        assert_eq!(reconcile(&fills), Some(88.0 / 80.0));
+    }
+}
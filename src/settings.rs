@@ -0,0 +1,262 @@
+use crate::util;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// Operator settings that survive a restart
+#[derive(Debug, Clone, PartialEq)]
+pub struct Settings {
+    pub seed_spacing_in: f32,
+    pub spacing_step_in: f32,
+    pub min_spacing_in: f32,
+    pub max_spacing_in: f32,
+    pub row_width_in: f32,
+    pub pick_efficiency: f32,
+    pub seed_piece_weight_oz: f32,
+    // switch jobs when the planter enters a field with a boundary
+    pub auto_select_job: bool,
+    // release rows over planted ground and outside the field
+    pub section_control: bool,
+    pub section_per_row: bool,
+    // seconds to look ahead before releasing and before engaging a row
+    pub look_ahead_off_s: f32,
+    pub look_ahead_on_s: f32,
+    // seconds ahead to read the prescription, the controller's response time
+    pub rx_look_ahead_s: f32,
+    // metres from the gps antenna back to the hitch pin, and right of the centreline
+    pub antenna_to_hitch_m: f32,
+    pub antenna_offset_m: f32,
+    // metres from the hitch pin back to the row units
+    pub hitch_to_rows_m: f32,
+    // metres right of the planter centreline, negative for left
+    pub row_offset_1_m: f32,
+    pub row_offset_2_m: f32,
+    // feet and seconds from the lift switch to the openers in the ground
+    pub start_lead_ft: f32,
+    pub start_lead_s: f32,
+    // close the flow valves on raising
+    pub close_on_raise: bool,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Settings {
+            seed_spacing_in: 10.0,
+            spacing_step_in: 0.5,
+            min_spacing_in: 6.0,
+            max_spacing_in: 18.0,
+            row_width_in: 34.0,
+            pick_efficiency: 0.9,
+            seed_piece_weight_oz: 2.0,
+            auto_select_job: false,
+            section_control: false,
+            section_per_row: true,
+            look_ahead_off_s: 0.5,
+            look_ahead_on_s: 1.0,
+            rx_look_ahead_s: 2.0,
+            antenna_to_hitch_m: 2.5,
+            antenna_offset_m: 0.0,
+            hitch_to_rows_m: 3.0,
+            row_offset_1_m: -0.43,
+            row_offset_2_m: 0.43,
+            start_lead_ft: 6.0,
+            start_lead_s: 1.0,
+            close_on_raise: true,
+        }
+    }
+}
+
+pub fn default_path() -> PathBuf {
+    util::config_dir().join("settings.conf")
+}
+
+impl Settings {
+    // missing or unreadable values fall back to the defaults
+    pub fn load(path: &Path) -> Settings {
+        Settings::parse(&fs::read_to_string(path).unwrap_or_default())
+    }
+
+    fn parse(contents: &str) -> Settings {
+        let mut s = Settings::default();
+        for (key, value) in contents.lines().filter_map(|l| l.split_once('=')) {
+            let Some(v) = value.trim().parse::<f32>().ok().filter(|v| v.is_finite()) else {
+                continue;
+            };
+            match key.trim() {
+                "seed_spacing_in" => s.seed_spacing_in = v,
+                "spacing_step_in" => s.spacing_step_in = v,
+                "min_spacing_in" => s.min_spacing_in = v,
+                "max_spacing_in" => s.max_spacing_in = v,
+                "row_width_in" => s.row_width_in = v,
+                "pick_efficiency" => s.pick_efficiency = v,
+                "seed_piece_weight_oz" => s.seed_piece_weight_oz = v,
+                "auto_select_job" => s.auto_select_job = v != 0.0,
+                "section_control" => s.section_control = v != 0.0,
+                "section_per_row" => s.section_per_row = v != 0.0,
+                "look_ahead_off_s" => s.look_ahead_off_s = v,
+                "look_ahead_on_s" => s.look_ahead_on_s = v,
+                "rx_look_ahead_s" => s.rx_look_ahead_s = v,
+                "antenna_to_hitch_m" => s.antenna_to_hitch_m = v,
+                "antenna_offset_m" => s.antenna_offset_m = v,
+                "hitch_to_rows_m" => s.hitch_to_rows_m = v,
+                "row_offset_1_m" => s.row_offset_1_m = v,
+                "row_offset_2_m" => s.row_offset_2_m = v,
+                "start_lead_ft" => s.start_lead_ft = v,
+                "start_lead_s" => s.start_lead_s = v,
+                "close_on_raise" => s.close_on_raise = v != 0.0,
+                _ => {}
+            }
+        }
+        s.validate()
+    }
+
+    // values that make no sense are put back to their defaults
+    fn validate(mut self) -> Settings {
+        let d = Settings::default();
+        let positive = |v: f32| v > 0.0;
+        let not_negative = |v: f32| v >= 0.0;
+        if !(positive(self.min_spacing_in) && self.min_spacing_in <= self.max_spacing_in) {
+            eprintln!(
+                "spacing range {}..{} is not valid, using the default",
+                self.min_spacing_in, self.max_spacing_in
+            );
+            self.min_spacing_in = d.min_spacing_in;
+            self.max_spacing_in = d.max_spacing_in;
+        }
+        self.seed_spacing_in = self.clamp_spacing(self.seed_spacing_in);
+        let fields: [(&str, &mut f32, f32, fn(f32) -> bool); 9] = [
+            (
+                "spacing_step_in",
+                &mut self.spacing_step_in,
+                d.spacing_step_in,
+                positive,
+            ),
+            (
+                "row_width_in",
+                &mut self.row_width_in,
+                d.row_width_in,
+                positive,
+            ),
+            (
+                "pick_efficiency",
+                &mut self.pick_efficiency,
+                d.pick_efficiency,
+                |v| v > 0.0 && v <= 1.0,
+            ),
+            (
+                "seed_piece_weight_oz",
+                &mut self.seed_piece_weight_oz,
+                d.seed_piece_weight_oz,
+                positive,
+            ),
+            (
+                "look_ahead_off_s",
+                &mut self.look_ahead_off_s,
+                d.look_ahead_off_s,
+                not_negative,
+            ),
+            (
+                "look_ahead_on_s",
+                &mut self.look_ahead_on_s,
+                d.look_ahead_on_s,
+                not_negative,
+            ),
+            (
+                "rx_look_ahead_s",
+                &mut self.rx_look_ahead_s,
+                d.rx_look_ahead_s,
+                not_negative,
+            ),
+            (
+                "start_lead_ft",
+                &mut self.start_lead_ft,
+                d.start_lead_ft,
+                not_negative,
+            ),
+            (
+                "start_lead_s",
+                &mut self.start_lead_s,
+                d.start_lead_s,
+                not_negative,
+            ),
+        ];
+        for (name, value, default, ok) in fields {
+            if !ok(*value) {
+                eprintln!("setting {name}={value} is out of range, using {default}");
+                *value = default;
+            }
+        }
+        self
+    }
+
+    pub fn save(&self, path: &Path) -> io::Result<()> {
+        util::atomic_write(path, self.contents())
+    }
+
+    fn contents(&self) -> String {
+        format!(
+            "seed_spacing_in={}\nspacing_step_in={}\nmin_spacing_in={}\nmax_spacing_in={}\nrow_width_in={}\npick_efficiency={}\nseed_piece_weight_oz={}\nauto_select_job={}\nsection_control={}\nsection_per_row={}\nlook_ahead_off_s={}\nlook_ahead_on_s={}\nrx_look_ahead_s={}\nantenna_to_hitch_m={}\nantenna_offset_m={}\nhitch_to_rows_m={}\nrow_offset_1_m={}\nrow_offset_2_m={}\nstart_lead_ft={}\nstart_lead_s={}\nclose_on_raise={}\n",
+            self.seed_spacing_in,
+            self.spacing_step_in,
+            self.min_spacing_in,
+            self.max_spacing_in,
+            self.row_width_in,
+            self.pick_efficiency,
+            self.seed_piece_weight_oz,
+            self.auto_select_job as u8,
+            self.section_control as u8,
+            self.section_per_row as u8,
+            self.look_ahead_off_s,
+            self.look_ahead_on_s,
+            self.rx_look_ahead_s,
+            self.antenna_to_hitch_m,
+            self.antenna_offset_m,
+            self.hitch_to_rows_m,
+            self.row_offset_1_m,
+            self.row_offset_2_m,
+            self.start_lead_ft,
+            self.start_lead_s,
+            self.close_on_raise as u8,
+        )
+    }
+
+    // unlike f32::clamp this never panics, a bad value ends up at the minimum
+    pub fn clamp_spacing(&self, in_between: f32) -> f32 {
+        in_between.max(self.min_spacing_in).min(self.max_spacing_in)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trip_and_validation() {
+        let s = Settings {
+            seed_spacing_in: 12.5,
+            section_control: true,
+            start_lead_s: 1.5,
+            ..Default::default()
+        };
+        assert_eq!(Settings::parse(&s.contents()), s);
+
+        // unknown keys and junk are skipped, bad values put back
+        let s = Settings::parse(
+            "foo=1\nseed_spacing_in=abc\nmin_spacing_in=20\nmax_spacing_in=8\n\
+             look_ahead_on_s=-1\nstart_lead_s=NaN\npick_efficiency=1.5\nrow_width_in=30\n",
+        );
+        let d = Settings::default();
+        assert_eq!(s.seed_spacing_in, d.seed_spacing_in);
+        assert_eq!((s.min_spacing_in, s.max_spacing_in), (6.0, 18.0));
+        assert_eq!(s.look_ahead_on_s, d.look_ahead_on_s);
+        assert_eq!(s.start_lead_s, d.start_lead_s);
+        assert_eq!(s.pick_efficiency, d.pick_efficiency);
+        assert_eq!(s.row_width_in, 30.0);
+
+        // the saved spacing is kept within the range
+        let s = Settings::parse("seed_spacing_in=40\n");
+        assert_eq!(s.seed_spacing_in, 18.0);
+        assert_eq!(s.clamp_spacing(f32::NAN), 6.0);
+        assert_eq!(s.clamp_spacing(2.0), 6.0);
+    }
+}
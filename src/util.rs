@@ -1,3 +1,8 @@
+use std::fs::{self, File};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
 // rate of encoder ticks per seconc
 pub type TickRate = usize;
 // rate of picks per second
@@ -12,14 +17,44 @@ const REVOLUTION_PICKS: f32 = 24.0;
 // 100 tick encoder steps per seed wheel revolution
 const REVOLUTION_TICKS: f32 = 340.0;
 
+// square inches per acre
+const ACRE_SQ_IN: f32 = 43560.0 * 144.0;
+
 pub fn row_feet_to_acres(ft: f32) -> f32 {
     ft / 14520.0
 }
 
+pub fn row_feet_to_acres_at(ft: f32, row_width_in: f32) -> f32 {
+    ft * (row_width_in / 12.0) / 43560.0
+}
+
+// seeds per acre at a given in-row spacing and row width (inches)
+pub fn spacing_to_population(in_between: f32, row_width: f32) -> f32 {
+    ACRE_SQ_IN / (in_between * row_width)
+}
+
+pub fn population_to_spacing(seeds_per_acre: f32, row_width: f32) -> f32 {
+    ACRE_SQ_IN / (seeds_per_acre * row_width)
+}
+
+// hundredweight of seed per acre at a given average seed piece weight
+pub fn seed_cwt_per_acre(seeds_per_acre: f32, piece_weight_oz: f32) -> f32 {
+    seeds_per_acre * piece_weight_oz / 16.0 / 100.0
+}
+
+// seconds from settings, which may be negative or not a number
+pub fn secs_or(secs: f32, default: Duration) -> Duration {
+    Duration::try_from_secs_f32(secs).unwrap_or(default)
+}
+
 pub fn mph_to_fps(mph: Speed) -> f32 {
     mph * 1.467
 }
 
+pub fn knots_to_mph(knots: f32) -> Speed {
+    knots * 1.15078
+}
+
 pub fn fps_to_sps(fps: Speed, in_between: f32) -> SeedRate {
     fps * 12.0 / in_between
 }
@@ -57,6 +92,79 @@ pub fn sps_from_tickrate(tickrate: TickRate) -> SeedRate {
     tickrate as f32 / ticks_per_pick() as f32
 }
 
+// spacing the seed wheel is actually producing at a ground speed
+pub fn tickrate_to_spacing(tickrate: TickRate, mph: Speed) -> Option<f32> {
+    let sps = sps_from_tickrate(tickrate);
+    (sps > 0.0).then(|| mph_to_fps(mph) * 12.0 / sps)
+}
+
+pub fn rpm_to_tickrate(rpm: f32) -> TickRate {
+    (rpm * REVOLUTION_TICKS / 60.0) as TickRate
+}
+
+pub fn tickrate_to_rpm(tickrate: TickRate) -> f32 {
+    tickrate as f32 * 60.0 / REVOLUTION_TICKS
+}
+
+fn home() -> PathBuf {
+    PathBuf::from(std::env::var("HOME").unwrap_or_else(|_| ".".to_string()))
+}
+
+// what the planter keeps between runs
+pub fn data_dir() -> PathBuf {
+    home().join(".local/share/popl")
+}
+
+pub fn config_dir() -> PathBuf {
+    home().join(".config/popl")
+}
+
+// write then rename so a power cut never leaves a partial file
+pub fn atomic_write(path: &Path, contents: impl AsRef<[u8]>) -> io::Result<()> {
+    let dir = path.parent().filter(|d| !d.as_os_str().is_empty());
+    if let Some(dir) = dir {
+        fs::create_dir_all(dir)?;
+    }
+    let tmp = path.with_extension("tmp");
+    let mut f = File::create(&tmp)?;
+    f.write_all(contents.as_ref())?;
+    f.sync_all()?;
+    fs::rename(&tmp, path)?;
+    if let Some(dir) = dir {
+        File::open(dir)?.sync_all()?;
+    }
+    Ok(())
+}
+
+/// A directory of its own for a test, removed when dropped
+#[cfg(test)]
+pub(crate) struct TempDir(std::path::PathBuf);
+
+#[cfg(test)]
+impl TempDir {
+    pub fn new(name: &str) -> TempDir {
+        use std::sync::atomic::{AtomicUsize, Ordering};
+        static NEXT: AtomicUsize = AtomicUsize::new(0);
+        let n = NEXT.fetch_add(1, Ordering::Relaxed);
+        let dir = std::env::temp_dir().join(format!("popl-{name}-{}-{n}", std::process::id()));
+        // left by an earlier run that had the same pid
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        TempDir(dir)
+    }
+
+    pub fn path(&self) -> &std::path::Path {
+        &self.0
+    }
+}
+
+#[cfg(test)]
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.0);
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -75,9 +183,37 @@ mod tests {
 
         assert!(sps_to_mph(10.0, 12.0) > sps_to_mph(10.0, 10.0));
 
+        let pop = spacing_to_population(10.0, 34.0);
+        assert_eq!(pop.round(), 18449.0);
+        assert!((population_to_spacing(pop, 34.0) - 10.0).abs() < 0.001);
+        assert_eq!(seed_cwt_per_acre(16000.0, 2.0), 20.0);
+
+        assert_eq!(
+            row_feet_to_acres_at(14520.0, 36.0),
+            row_feet_to_acres(14520.0)
+        );
+
+        // tick rates are whole ticks per second
+        let tickrate = sps_to_tickrate(fps_to_sps(mph_to_fps(3.0), 10.0));
+        assert!((tickrate_to_spacing(tickrate, 3.0).unwrap() - 10.0).abs() < 0.2);
+        assert_eq!(tickrate_to_spacing(0, 3.0), None);
+
+        assert_eq!(rpm_to_tickrate(60.0), 340);
+        assert_eq!(tickrate_to_rpm(340), 60.0);
+
         println!("{}", sps_to_mph(10.0, 8.0));
         println!("{}", sps_to_mph(10.0, 10.0));
         println!("{}", sps_to_mph(10.0, 12.0));
         println!("{}", sps_to_mph(10.0, 14.0));
     }
+
+    #[test]
+    fn atomic_write_replaces_whole() {
+        let dir = TempDir::new("atomic");
+        let path = dir.path().join("state/valves.json");
+        atomic_write(&path, "first").unwrap();
+        atomic_write(&path, "second").unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap(), "second");
+        assert!(!path.with_extension("tmp").exists());
+    }
 }
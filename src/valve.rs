@@ -0,0 +1,397 @@
+use crate::util;
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+pub struct ValveCfg {
+    // closed to open at full throttle, learned by homing
+    pub full_travel: Duration,
+    // share of the travel kept clear of each endstop
+    pub soft_limit: f32,
+}
+
+impl Default for ValveCfg {
+    fn default() -> Self {
+        ValveCfg {
+            full_travel: Duration::from_secs(2),
+            soft_limit: 0.02,
+        }
+    }
+}
+
+/// What homing found, kept across restarts
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct Calibration {
+    pub full_travel: Duration,
+    // pot readings at closed and open
+    pub pot_range: Option<(f32, f32)>,
+}
+
+/// Flow valve position from 0.0 closed to 1.0 open, integrated from throttle and time,
+/// or read from a potentiometer once homing has found its endstops
+#[derive(Default)]
+pub struct Valve {
+    pub cfg: ValveCfg,
+    // unknown until homed
+    position: Option<f32>,
+    // pot readings at closed and open
+    pot_range: Option<(f32, f32)>,
+    calibrated: bool,
+}
+
+impl Valve {
+    pub fn position(&self) -> Option<f32> {
+        self.position
+    }
+
+    pub fn is_homed(&self) -> bool {
+        self.position.is_some()
+    }
+
+    // the position follows the pot once its endstops are known
+    pub fn has_pot(&self) -> bool {
+        self.pot_range.is_some()
+    }
+
+    pub fn calibration(&self) -> Option<Calibration> {
+        self.calibrated.then_some(Calibration {
+            full_travel: self.cfg.full_travel,
+            pot_range: self.pot_range,
+        })
+    }
+
+    // from a previous homing, the position is still unknown until zeroed or read from the pot
+    pub fn calibrate(&mut self, cal: Calibration) {
+        self.cfg.full_travel = cal.full_travel;
+        self.pot_range = cal.pot_range;
+        self.calibrated = true;
+    }
+
+    fn travel(&self, rate: f32, duration: Duration) -> f32 {
+        rate * duration.as_secs_f32() / self.cfg.full_travel.as_secs_f32().max(f32::EPSILON)
+    }
+
+    pub fn moved(&mut self, rate: f32, duration: Duration) {
+        if let Some(p) = self.position {
+            self.position = Some((p + self.travel(rate, duration)).clamp(0.0, 1.0));
+        }
+    }
+
+    /// How long the throttle may be held before reaching a soft limit,
+    /// unlimited until homed
+    pub fn limit(&self, rate: f32, duration: Duration) -> Duration {
+        let Some(p) = self.position else {
+            return duration;
+        };
+        let room = if rate > 0.0 {
+            1.0 - self.cfg.soft_limit - p
+        } else {
+            p - self.cfg.soft_limit
+        };
+        let wanted = self.travel(rate, duration).abs();
+        if room >= wanted {
+            duration
+        } else if room <= 0.0 {
+            Duration::ZERO
+        } else {
+            duration.mul_f32(room / wanted)
+        }
+    }
+
+    // against either soft limit, where the opening no longer follows the model
+    pub fn at_limit(&self) -> bool {
+        let edge = self.cfg.soft_limit + 1e-3;
+        self.position
+            .map_or(false, |p| p <= edge || p >= 1.0 - edge)
+    }
+
+    // raw pot reading, the model is corrected once the endstops are known
+    pub fn feedback(&mut self, reading: f32) {
+        if let Some((closed, open)) = self.pot_range {
+            if (open - closed).abs() > f32::EPSILON {
+                self.position = Some(((reading - closed) / (open - closed)).clamp(0.0, 1.0));
+            }
+        }
+    }
+
+    // left fully open by homing
+    pub fn homed(&mut self, full_travel: Duration, pot_range: Option<(f32, f32)>) {
+        self.calibrate(Calibration {
+            full_travel,
+            pot_range,
+        });
+        self.position = Some(1.0);
+    }
+
+    // driven against the closed endstop
+    pub fn zeroed(&mut self) {
+        self.position = Some(0.0);
+    }
+}
+
+pub fn default_path() -> PathBuf {
+    util::data_dir().join("valves.json")
+}
+
+// one per drive, None where the valve has not been homed
+pub fn load(path: &Path) -> Vec<Option<Calibration>> {
+    fs::read_to_string(path)
+        .ok()
+        .and_then(|s| serde_json::from_str(&s).ok())
+        .unwrap_or_default()
+}
+
+pub fn save(path: &Path, cals: &[Option<Calibration>]) -> io::Result<()> {
+    util::atomic_write(path, serde_json::to_string(cals)?)
+}
+
+pub struct HomingCfg {
+    // long enough to reach the closed endstop from anywhere
+    pub close_time: Duration,
+    // opening is done in steps, each measured once it settles
+    pub step: Duration,
+    pub settle: Duration,
+    // steps with no gain before the valve is taken to be open
+    pub flat_steps: u32,
+    // gain counted as still opening, share of the reading
+    pub tolerance: f32,
+    pub max_open: Duration,
+}
+
+impl Default for HomingCfg {
+    fn default() -> Self {
+        HomingCfg {
+            close_time: Duration::from_secs(4),
+            step: Duration::from_millis(200),
+            // the seed wheel speed is measured once a second
+            settle: Duration::from_millis(1500),
+            flat_steps: 3,
+            tolerance: 0.02,
+            max_open: Duration::from_secs(8),
+        }
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub enum HomingStep {
+    Wait,
+    // throttle held for the duration
+    Drive(f32, Duration),
+    Done {
+        full_travel: Duration,
+        pot_range: Option<(f32, f32)>,
+    },
+    // at the closed endstop, for a zero only
+    Closed,
+    // nothing changed while opening, no hydraulic flow or no signal
+    Failed,
+}
+
+enum Phase {
+    Start,
+    Closing,
+    Opening,
+}
+
+/// Finds the full travel time by closing against the endstop, then opening in steps
+/// until the seed wheel speed, or the pot, stops rising
+pub struct Homing {
+    pub cfg: HomingCfg,
+    // only close against the endstop, the travel is already known
+    zero_only: bool,
+    phase: Phase,
+    next_at: Option<Instant>,
+    closed_pot: Option<f32>,
+    driven: Duration,
+    best: f32,
+    best_at: Duration,
+    flat: u32,
+}
+
+impl Default for Homing {
+    fn default() -> Self {
+        Homing {
+            cfg: Default::default(),
+            zero_only: false,
+            phase: Phase::Start,
+            next_at: None,
+            closed_pot: None,
+            driven: Duration::ZERO,
+            best: 0.0,
+            best_at: Duration::ZERO,
+            flat: 0,
+        }
+    }
+}
+
+impl Homing {
+    pub fn zero() -> Self {
+        Homing {
+            zero_only: true,
+            ..Default::default()
+        }
+    }
+
+    pub fn is_zero(&self) -> bool {
+        self.zero_only
+    }
+
+    // rpm is the seed wheel speed, pot the raw position reading if there is one
+    pub fn update(&mut self, now: Instant, rpm: f32, pot: Option<f32>) -> HomingStep {
+        if self.next_at.map_or(false, |at| now < at) {
+            return HomingStep::Wait;
+        }
+        match self.phase {
+            Phase::Start => {
+                self.phase = Phase::Closing;
+                // nothing is measured after a zero, so there is no settling
+                let settle = if self.zero_only {
+                    Duration::ZERO
+                } else {
+                    self.cfg.settle
+                };
+                self.next_at = Some(now + self.cfg.close_time + settle);
+                HomingStep::Drive(-1.0, self.cfg.close_time)
+            }
+            Phase::Closing if self.zero_only => HomingStep::Closed,
+            Phase::Closing => {
+                self.phase = Phase::Opening;
+                self.closed_pot = pot;
+                self.best = pot.unwrap_or(rpm);
+                self.open_step(now)
+            }
+            Phase::Opening => {
+                let reading = pot.unwrap_or(rpm);
+                let gain = reading - self.best;
+                if gain > self.best.abs() * self.cfg.tolerance && gain > f32::EPSILON {
+                    self.best = reading;
+                    self.best_at = self.driven;
+                    self.flat = 0;
+                } else {
+                    self.flat += 1;
+                }
+                if self.flat < self.cfg.flat_steps && self.driven < self.cfg.max_open {
+                    return self.open_step(now);
+                }
+                if self.best_at.is_zero() {
+                    return HomingStep::Failed;
+                }
+                HomingStep::Done {
+                    full_travel: self.best_at,
+                    pot_range: self.closed_pot.zip(pot),
+                }
+            }
+        }
+    }
+
+    fn open_step(&mut self, now: Instant) -> HomingStep {
+        self.driven += self.cfg.step;
+        self.next_at = Some(now + self.cfg.step + self.cfg.settle);
+        HomingStep::Drive(1.0, self.cfg.step)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn soft_limits() {
+        let mut v = Valve::default();
+        let pulse = Duration::from_millis(100);
+        // unknown position is not limited
+        assert_eq!(v.limit(-1.0, pulse), pulse);
+        v.moved(1.0, pulse);
+        assert_eq!(v.position(), None);
+
+        v.homed(Duration::from_secs(2), None);
+        assert_eq!(v.limit(1.0, pulse), Duration::ZERO);
+        assert!(v.at_limit());
+        v.moved(-1.0, Duration::from_secs(1));
+        assert!((v.position().unwrap() - 0.5).abs() < 1e-6);
+        assert!(!v.at_limit());
+        assert_eq!(v.limit(0.5, pulse), pulse);
+        // 0.03 above the closed soft limit, 60 ms at full throttle
+        v.moved(-1.0, Duration::from_millis(900));
+        let d = v.limit(-1.0, pulse);
+        assert!((d.as_secs_f32() - 0.06).abs() < 1e-3, "{d:?}");
+    }
+
+    #[test]
+    fn homing_finds_full_travel() {
+        let mut h = Homing::default();
+        let t = Instant::now();
+        assert_eq!(
+            h.update(t, 0.0, None),
+            HomingStep::Drive(-1.0, h.cfg.close_time)
+        );
+        assert_eq!(h.update(t, 0.0, None), HomingStep::Wait);
+
+        // the wheel speeds up until 1.2 s of opening, then holds
+        let mut now = t + Duration::from_secs(6);
+        let mut driven = Duration::ZERO;
+        let mut rpm = 0.0;
+        let done = loop {
+            match h.update(now, rpm, None) {
+                HomingStep::Drive(rate, d) => {
+                    assert_eq!(rate, 1.0);
+                    driven += d;
+                    rpm = 40.0 * driven.min(Duration::from_millis(1200)).as_secs_f32();
+                }
+                HomingStep::Wait => {}
+                step => break step,
+            }
+            now += Duration::from_millis(100);
+        };
+        assert_eq!(
+            done,
+            HomingStep::Done {
+                full_travel: Duration::from_millis(1200),
+                pot_range: None
+            }
+        );
+
+        // no flow, nothing to learn from
+        let mut h = Homing::default();
+        let mut now = t;
+        let failed = loop {
+            match h.update(now, 0.0, None) {
+                HomingStep::Drive(..) | HomingStep::Wait => now += Duration::from_millis(100),
+                step => break step,
+            }
+        };
+        assert_eq!(failed, HomingStep::Failed);
+
+        // a zero closes and is done, the valve keeps the travel it had
+        let mut h = Homing::zero();
+        assert_eq!(
+            h.update(t, 0.0, None),
+            HomingStep::Drive(-1.0, h.cfg.close_time)
+        );
+        assert_eq!(
+            h.update(t + Duration::from_secs(1), 0.0, None),
+            HomingStep::Wait
+        );
+        assert_eq!(
+            h.update(t + h.cfg.close_time, 0.0, None),
+            HomingStep::Closed
+        );
+    }
+
+    #[test]
+    fn calibration_survives_a_restart() {
+        let mut v = Valve::default();
+        assert_eq!(v.calibration(), None);
+        v.homed(Duration::from_millis(1200), Some((0.1, 0.9)));
+        let cal = v.calibration().unwrap();
+
+        let mut v = Valve::default();
+        v.calibrate(cal);
+        assert!(!v.is_homed());
+        v.feedback(0.5);
+        assert!((v.position().unwrap() - 0.5).abs() < 1e-6);
+        assert_eq!(v.cfg.full_travel, Duration::from_millis(1200));
+    }
+}
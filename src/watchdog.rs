@@ -0,0 +1,98 @@
+use std::time::{Duration, Instant};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Source {
+    GroundSpeed,
+    Lift,
+}
+
+impl Source {
+    pub const ALL: [Source; 2] = [Source::GroundSpeed, Source::Lift];
+}
+
+/// Maximum age of each input before it is considered stale, None disables the check,
+/// the seed wheel encoders are watched per drive by the diagnostics
+pub struct WatchdogCfg {
+    pub ground_speed: Option<Duration>,
+    // the lift switch only reports changes, so by default it is never stale
+    pub lift: Option<Duration>,
+}
+
+impl Default for WatchdogCfg {
+    fn default() -> Self {
+        WatchdogCfg {
+            ground_speed: Some(Duration::from_secs(3)),
+            lift: None,
+        }
+    }
+}
+
+/// Tracks when each input source last reported
+pub struct Watchdog {
+    pub cfg: WatchdogCfg,
+    last_seen: [Instant; 2],
+}
+
+impl Default for Watchdog {
+    fn default() -> Self {
+        Watchdog::new(WatchdogCfg::default())
+    }
+}
+
+impl Watchdog {
+    pub fn new(cfg: WatchdogCfg) -> Self {
+        Watchdog {
+            cfg,
+            last_seen: [Instant::now(); 2],
+        }
+    }
+
+    pub fn feed(&mut self, source: Source, now: Instant) {
+        self.last_seen[source as usize] = now;
+    }
+
+    pub fn threshold(&self, source: Source) -> Option<Duration> {
+        match source {
+            Source::GroundSpeed => self.cfg.ground_speed,
+            Source::Lift => self.cfg.lift,
+        }
+    }
+
+    pub fn is_stale(&self, source: Source, now: Instant) -> bool {
+        match self.threshold(source) {
+            Some(max) => now.saturating_duration_since(self.last_seen[source as usize]) > max,
+            None => false,
+        }
+    }
+
+    pub fn stale(&self, now: Instant) -> Vec<Source> {
+        Source::ALL
+            .into_iter()
+            .filter(|s| self.is_stale(*s, now))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn stale_after_threshold() {
+        let start = Instant::now();
+        let mut wd = Watchdog::new(WatchdogCfg::default());
+        wd.feed(Source::GroundSpeed, start);
+
+        assert!(wd.stale(start + Duration::from_secs(1)).is_empty());
+        assert_eq!(
+            wd.stale(start + Duration::from_millis(3500)),
+            vec![Source::GroundSpeed]
+        );
+
+        wd.feed(Source::GroundSpeed, start + Duration::from_secs(3));
+        assert!(wd.stale(start + Duration::from_millis(3500)).is_empty());
+
+        // lift is disabled by default
+        assert!(!wd.is_stale(Source::Lift, start + Duration::from_secs(600)));
+    }
+}